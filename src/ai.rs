@@ -0,0 +1,289 @@
+use crate::rng::*;
+use crate::savestate::*;
+use crate::*;
+use bytes::BytesMut;
+use rand::Rng;
+
+// NOTE(Fermin): How far from the paddle center the AI pushes at full speed
+const AI_TRACKING_RANGE: f32 = 40.0;
+// NOTE(Fermin): Going back to the middle is never urgent
+const AI_RETURN_SPEED: f32 = 0.5;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum AiLevel {
+    Easy,
+    Normal,
+    Hard,
+}
+pub const AI_LEVELS: [AiLevel; 3] = [AiLevel::Easy, AiLevel::Normal, AiLevel::Hard];
+impl AiLevel {
+    pub fn name(self) -> &'static str {
+        match self {
+            AiLevel::Easy => "easy",
+            AiLevel::Normal => "normal",
+            AiLevel::Hard => "hard",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<AiLevel> {
+        AI_LEVELS.into_iter().find(|level| level.name() == name)
+    }
+    pub fn difficulty(self) -> AiDifficulty {
+        match self {
+            AiLevel::Easy => AiDifficulty {
+                reaction_delay: 0.35,
+                prediction_error: 40.0,
+                max_speed: 0.6,
+                lazy_range: 20.0,
+            },
+            AiLevel::Normal => AiDifficulty {
+                reaction_delay: 0.2,
+                prediction_error: 20.0,
+                max_speed: 0.85,
+                lazy_range: 10.0,
+            },
+            AiLevel::Hard => AiDifficulty {
+                reaction_delay: 0.08,
+                prediction_error: 6.0,
+                max_speed: 1.0,
+                lazy_range: 3.0,
+            },
+        }
+    }
+}
+impl Persist for AiLevel {
+    fn write(&self, out: &mut BytesMut) {
+        (*self as u32).write(out);
+    }
+    fn read(buf: &mut &[u8]) -> std::io::Result<Self> {
+        let value = u32::read(buf)?;
+        AI_LEVELS
+            .get(value as usize)
+            .copied()
+            .ok_or_else(|| invalid_data(&format!("invalid ai level {}", value)))
+    }
+}
+
+pub struct AiDifficulty {
+    // NOTE(Fermin): Seconds between the ball turning towards the paddle and
+    // the AI starting to chase it
+    pub reaction_delay: f32,
+    // NOTE(Fermin): Pixels, the guess is off by up to this much, picked
+    // once per approach
+    pub prediction_error: f32,
+    // NOTE(Fermin): Share of the human acceleration the AI can use
+    pub max_speed: f32,
+    // NOTE(Fermin): Pixels, closer than this to the target the AI doesn't
+    // bother moving
+    pub lazy_range: f32,
+}
+
+// NOTE(Fermin): What the AI sees, everything is measured on the ball center.
+// The paddle axis is the one the paddle moves on.
+pub struct AiView {
+    pub horizontal: bool,
+    pub paddle_center: f32,
+    // NOTE(Fermin): Where the ball center touches the paddle, on the other axis
+    pub paddle_line: f32,
+    // NOTE(Fermin): Range the ball center can take on the paddle axis, it
+    // bounces when it reaches either end
+    pub bounds: (f32, f32),
+    pub ball_center: V2,
+    pub ball_dp: V2,
+    pub dt: f32,
+}
+
+#[derive(Clone)]
+pub struct PaddleAi {
+    approaching: bool,
+    reaction_timer: f32,
+    error_offset: f32,
+    rng: GameRng,
+}
+impl PaddleAi {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            approaching: false,
+            reaction_timer: 0.0,
+            error_offset: 0.0,
+            rng: GameRng::new(seed),
+        }
+    }
+
+    // NOTE(Fermin): Returns the acceleration along the paddle axis, -1..1,
+    // the same input a human paddle gets from the buttons or the stick
+    pub fn update(&mut self, view: &AiView, difficulty: &AiDifficulty) -> f32 {
+        let crossing = predict_crossing(view);
+        let approaching = crossing.is_some();
+        if approaching && !self.approaching {
+            self.reaction_timer = difficulty.reaction_delay;
+            self.error_offset = if difficulty.prediction_error > 0.0 {
+                self.rng
+                    .gen_range(-difficulty.prediction_error..difficulty.prediction_error)
+            } else {
+                0.0
+            };
+        }
+        self.approaching = approaching;
+
+        if self.reaction_timer > 0.0 {
+            self.reaction_timer -= view.dt;
+            return 0.0;
+        }
+
+        let (target, speed) = match crossing {
+            Some(crossing) => (crossing + self.error_offset, difficulty.max_speed),
+            None => (
+                (view.bounds.0 + view.bounds.1) * 0.5,
+                difficulty.max_speed * AI_RETURN_SPEED,
+            ),
+        };
+        let distance = target - view.paddle_center;
+        if distance.abs() < difficulty.lazy_range {
+            return 0.0;
+        }
+        (distance / AI_TRACKING_RANGE).clamp(-1.0, 1.0) * speed
+    }
+}
+impl Persist for PaddleAi {
+    fn write(&self, out: &mut BytesMut) {
+        self.approaching.write(out);
+        self.reaction_timer.write(out);
+        self.error_offset.write(out);
+        self.rng.write(out);
+    }
+    fn read(buf: &mut &[u8]) -> std::io::Result<Self> {
+        Ok(Self {
+            approaching: bool::read(buf)?,
+            reaction_timer: f32::read(buf)?,
+            error_offset: f32::read(buf)?,
+            rng: GameRng::read(buf)?,
+        })
+    }
+}
+
+// NOTE(Fermin): Where the ball will cross the paddle line, None if it is
+// moving away. Bounces are unfolded, the ball travels in a straight line
+// through mirrored copies of the arena.
+fn predict_crossing(view: &AiView) -> Option<f32> {
+    let (along, across, along_dp, across_dp) = if view.horizontal {
+        (
+            view.ball_center.x,
+            view.ball_center.y,
+            view.ball_dp.x,
+            view.ball_dp.y,
+        )
+    } else {
+        (
+            view.ball_center.y,
+            view.ball_center.x,
+            view.ball_dp.y,
+            view.ball_dp.x,
+        )
+    };
+    let distance = view.paddle_line - across;
+    if across_dp == 0.0 || distance * across_dp <= 0.0 {
+        return None;
+    }
+
+    let time = distance / across_dp;
+    let unfolded = along + along_dp * time;
+    let (min, max) = view.bounds;
+    let span = max - min;
+    if span <= 0.0 {
+        return Some(min);
+    }
+    let mut offset = (unfolded - min).rem_euclid(2.0 * span);
+    if offset > span {
+        offset = 2.0 * span - offset;
+    }
+    Some(min + offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // NOTE(Fermin): A paddle on the right at x = 300, the ball bounces
+    // between y = 0 and y = 100
+    fn right_paddle_view(ball_center: V2, ball_dp: V2) -> AiView {
+        AiView {
+            horizontal: false,
+            paddle_center: 50.0,
+            paddle_line: 300.0,
+            bounds: (0.0, 100.0),
+            ball_center,
+            ball_dp,
+            dt: 1.0 / 60.0,
+        }
+    }
+
+    fn assert_crossing(view: &AiView, expected: f32) {
+        let crossing = predict_crossing(view).expect("Ball is approaching");
+        assert!(
+            (crossing - expected).abs() < 0.001,
+            "expected {}, got {}",
+            expected,
+            crossing
+        );
+    }
+
+    #[test]
+    fn ball_moving_away_has_no_crossing() {
+        let away = right_paddle_view(V2 { x: 100.0, y: 50.0 }, V2 { x: -10.0, y: 5.0 });
+        assert!(predict_crossing(&away).is_none());
+        let parallel = right_paddle_view(V2 { x: 100.0, y: 50.0 }, V2 { x: 0.0, y: 5.0 });
+        assert!(predict_crossing(&parallel).is_none());
+        let behind = right_paddle_view(V2 { x: 310.0, y: 50.0 }, V2 { x: 10.0, y: 5.0 });
+        assert!(predict_crossing(&behind).is_none());
+    }
+
+    #[test]
+    fn no_bounce() {
+        let straight = right_paddle_view(V2 { x: 100.0, y: 20.0 }, V2 { x: 100.0, y: 0.0 });
+        assert_crossing(&straight, 20.0);
+        let diagonal = right_paddle_view(V2 { x: 200.0, y: 20.0 }, V2 { x: 100.0, y: 50.0 });
+        assert_crossing(&diagonal, 70.0);
+    }
+
+    #[test]
+    fn one_bounce() {
+        // NOTE(Fermin): Unfolded it would cross at 150, 50 past the bottom
+        let off_bottom = right_paddle_view(V2 { x: 200.0, y: 50.0 }, V2 { x: 100.0, y: 100.0 });
+        assert_crossing(&off_bottom, 50.0);
+        // NOTE(Fermin): Unfolded at -30, mirrored off the top
+        let off_top = right_paddle_view(V2 { x: 200.0, y: 20.0 }, V2 { x: 100.0, y: -50.0 });
+        assert_crossing(&off_top, 30.0);
+    }
+
+    #[test]
+    fn multiple_bounces() {
+        // NOTE(Fermin): Unfolded at 250, bottom then top then 50 down again
+        let two = right_paddle_view(V2 { x: 200.0, y: 50.0 }, V2 { x: 50.0, y: 100.0 });
+        assert_crossing(&two, 50.0);
+        // NOTE(Fermin): Unfolded at 330, three bounces and going up
+        let three = right_paddle_view(V2 { x: 0.0, y: 30.0 }, V2 { x: 100.0, y: 100.0 });
+        assert_crossing(&three, 70.0);
+        let upwards = right_paddle_view(
+            V2 { x: 0.0, y: 70.0 },
+            V2 {
+                x: 100.0,
+                y: -100.0,
+            },
+        );
+        assert_crossing(&upwards, 30.0);
+    }
+
+    #[test]
+    fn horizontal_paddle_uses_the_other_axis() {
+        let view = AiView {
+            horizontal: true,
+            paddle_center: 50.0,
+            paddle_line: 0.0,
+            bounds: (0.0, 100.0),
+            ball_center: V2 { x: 80.0, y: 100.0 },
+            ball_dp: V2 { x: 5.0, y: -50.0 },
+            dt: 1.0 / 60.0,
+        };
+        assert_crossing(&view, 90.0);
+    }
+}
@@ -0,0 +1,1155 @@
+use crate::sample::*;
+use crate::synth::*;
+use crate::tracker::*;
+use std::fs::File;
+use std::io::{BufWriter, Error, Result, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+pub const AUDIO_SAMPLE_RATE: u32 = 44100;
+pub const AUDIO_CHANNELS: u16 = 2;
+// NOTE(Fermin): The oldest voice is dropped to make room after this many
+const MAX_VOICES: usize = 32;
+// NOTE(Fermin): Silence written before the first frame so the device never
+// runs dry waiting on a slow frame
+const AUDIO_LATENCY_SECONDS: f32 = 0.05;
+const MUSIC_FADE_SECONDS: f32 = 1.5;
+const TRACKING_TONE_HZ: f32 = 330.0;
+// NOTE(Fermin): The cues stretch the pitch of a position this many times
+// further than the effects do, so small moves can still be heard
+const CUE_PITCH_SPREAD: f32 = 4.0;
+// NOTE(Fermin): How quickly the tone catches up with the ball, short enough
+// to follow it and long enough not to click
+const TRACKING_TONE_GLIDE_SECONDS: f32 = 0.03;
+// NOTE(Fermin): Every paddle ticks once in this long, one after the other
+const PADDLE_CUE_SECONDS: f32 = 0.4;
+
+// NOTE(Fermin): What the game wants heard this frame, the platform layer
+// turns them into voices
+#[derive(Copy, Clone, PartialEq)]
+pub enum SoundKind {
+    PaddleHit,
+    WallHit,
+    BrickBreak,
+    PowerUp,
+    BallLost,
+    Death,
+    LevelClear,
+    MenuMove,
+    MenuSelect,
+    PaddleCue,
+}
+pub const SOUND_KINDS: [SoundKind; 10] = [
+    SoundKind::PaddleHit,
+    SoundKind::WallHit,
+    SoundKind::BrickBreak,
+    SoundKind::PowerUp,
+    SoundKind::BallLost,
+    SoundKind::Death,
+    SoundKind::LevelClear,
+    SoundKind::MenuMove,
+    SoundKind::MenuSelect,
+    SoundKind::PaddleCue,
+];
+impl SoundKind {
+    // NOTE(Fermin): Also the file name of the sample that replaces it
+    pub fn name(self) -> &'static str {
+        match self {
+            SoundKind::PaddleHit => "paddle_hit",
+            SoundKind::WallHit => "wall_hit",
+            SoundKind::BrickBreak => "brick_break",
+            SoundKind::PowerUp => "power_up",
+            SoundKind::BallLost => "ball_lost",
+            SoundKind::Death => "death",
+            SoundKind::LevelClear => "level_clear",
+            SoundKind::MenuMove => "menu_move",
+            SoundKind::MenuSelect => "menu_select",
+            SoundKind::PaddleCue => "paddle_cue",
+        }
+    }
+}
+
+// NOTE(Fermin): Where a sound comes from, pan goes from -1 left to 1 right
+// and pitch scales its frequency
+#[derive(Copy, Clone)]
+pub struct StereoPosition {
+    pub pan: f32,
+    pub pitch: f32,
+}
+impl StereoPosition {
+    pub const CENTER: Self = Self {
+        pan: 0.0,
+        pitch: 1.0,
+    };
+
+    fn spread_for_cues(self) -> Self {
+        Self {
+            pan: self.pan,
+            pitch: self.pitch.powf(CUE_PITCH_SPREAD),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct SoundEvent {
+    pub kind: SoundKind,
+    pub position: StereoPosition,
+}
+impl SoundEvent {
+    pub fn new(kind: SoundKind) -> Self {
+        Self::at(kind, StereoPosition::CENTER)
+    }
+
+    pub fn at(kind: SoundKind, position: StereoPosition) -> Self {
+        Self { kind, position }
+    }
+}
+
+// NOTE(Fermin): What the audio only mode follows, the ball gets the tracking
+// tone and every paddle in the list ticks
+pub struct AudioCues {
+    pub ball: Option<StereoPosition>,
+    pub paddles: Vec<StereoPosition>,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum MusicTrack {
+    Title,
+    Game,
+}
+pub const MUSIC_TRACKS: [MusicTrack; 2] = [MusicTrack::Title, MusicTrack::Game];
+impl MusicTrack {
+    pub fn name(self) -> &'static str {
+        match self {
+            MusicTrack::Title => "title",
+            MusicTrack::Game => "game",
+        }
+    }
+}
+
+#[derive(Clone)]
+enum VoiceSource {
+    Synth(SynthVoice),
+    // NOTE(Fermin): Step is the pitch, in frames per output frame
+    Sample {
+        sample: Rc<Sample>,
+        position: f32,
+        step: f32,
+    },
+}
+
+// NOTE(Fermin): Pan goes from -1 left to 1 right
+#[derive(Clone)]
+pub struct Voice {
+    source: VoiceSource,
+    volume: f32,
+    pan: f32,
+}
+impl Voice {
+    // NOTE(Fermin): Left and right before panning
+    fn next(&mut self, sample_rate: u32) -> Option<(f32, f32)> {
+        match &mut self.source {
+            VoiceSource::Synth(synth) => synth.next(sample_rate).map(|sample| (sample, sample)),
+            VoiceSource::Sample {
+                sample,
+                position,
+                step,
+            } => {
+                if *position >= sample.frame_count() as f32 {
+                    return None;
+                }
+                let result = sample.frame_at(*position);
+                *position += *step;
+                Some(result)
+            }
+        }
+    }
+}
+
+// NOTE(Fermin): A recorded track or a song the tracker plays live
+#[derive(Clone)]
+pub enum Music {
+    Sample(Rc<Sample>),
+    Song(Rc<Song>),
+}
+
+enum MusicSource {
+    Sample { sample: Rc<Sample>, position: usize },
+    Song(Box<Sequencer>),
+}
+
+// NOTE(Fermin): Background music loops until it is faded out, an old track
+// fading out and a new one fading in can play at the same time
+struct MusicVoice {
+    source: MusicSource,
+    volume: f32,
+    // NOTE(Fermin): Volume change per second, negative while fading out
+    fade: f32,
+}
+impl MusicVoice {
+    fn next(&mut self, sample_rate: u32) -> (f32, f32) {
+        match &mut self.source {
+            MusicSource::Sample { sample, position } => {
+                let result = sample.frame(*position);
+                *position = (*position + 1) % sample.frame_count();
+                result
+            }
+            MusicSource::Song(sequencer) => {
+                let sample = sequencer.next(sample_rate);
+                (sample, sample)
+            }
+        }
+    }
+}
+
+// NOTE(Fermin): The audio only mode's ball tone, it glides to where it is
+// told instead of jumping there
+struct TrackingTone {
+    oscillator: Oscillator,
+    position: StereoPosition,
+    volume: f32,
+    target: Option<StereoPosition>,
+}
+
+pub struct Mixer {
+    voices: Vec<Voice>,
+    music: Vec<MusicVoice>,
+    tracking_tone: TrackingTone,
+    pub master_volume: f32,
+    pub music_volume: f32,
+    sample_rate: u32,
+    // NOTE(Fermin): Mix scratch, kept between calls so mixing doesn't
+    // allocate every frame
+    left: Vec<f32>,
+    right: Vec<f32>,
+}
+impl Mixer {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            voices: Vec::new(),
+            music: Vec::new(),
+            tracking_tone: TrackingTone {
+                oscillator: Oscillator::new(Waveform::Triangle),
+                position: StereoPosition::CENTER,
+                volume: 0.0,
+                target: None,
+            },
+            master_volume: 1.0,
+            music_volume: 0.6,
+            sample_rate,
+            left: Vec::new(),
+            right: Vec::new(),
+        }
+    }
+
+    fn add_voice(&mut self, voice: Voice) {
+        if self.voices.len() >= MAX_VOICES {
+            self.voices.remove(0);
+        }
+        self.voices.push(voice);
+    }
+
+    pub fn play_sound(&mut self, kind: SoundKind, volume: f32, position: StereoPosition) {
+        for blip in sound_blips(kind) {
+            self.add_voice(Voice {
+                source: VoiceSource::Synth(SynthVoice::new(*blip, position.pitch)),
+                volume,
+                pan: position.pan.clamp(-1.0, 1.0),
+            });
+        }
+    }
+
+    pub fn play_sample(&mut self, sample: Rc<Sample>, volume: f32, position: StereoPosition) {
+        self.add_voice(Voice {
+            source: VoiceSource::Sample {
+                sample,
+                position: 0.0,
+                step: position.pitch.max(0.01),
+            },
+            volume,
+            pan: position.pan.clamp(-1.0, 1.0),
+        });
+    }
+
+    // NOTE(Fermin): None fades the tone out
+    pub fn set_tracking_tone(&mut self, position: Option<StereoPosition>) {
+        self.tracking_tone.target = position;
+    }
+
+    // NOTE(Fermin): Fades out whatever is playing while the new track fades
+    // in, a fade of 0 cuts straight to it
+    pub fn play_music(&mut self, music: Music, fade_seconds: f32) {
+        self.stop_music(fade_seconds);
+        let source = match music {
+            // NOTE(Fermin): Nothing to loop
+            Music::Sample(sample) if sample.frame_count() == 0 => return,
+            Music::Sample(sample) => MusicSource::Sample {
+                sample,
+                position: 0,
+            },
+            Music::Song(song) => MusicSource::Song(Box::new(Sequencer::new(song))),
+        };
+        let (volume, fade) = if fade_seconds > 0.0 {
+            (0.0, 1.0 / fade_seconds)
+        } else {
+            (1.0, 0.0)
+        };
+        self.music.push(MusicVoice {
+            source,
+            volume,
+            fade,
+        });
+    }
+
+    pub fn set_music_mood(&mut self, mood: MusicMood) {
+        for music in &mut self.music {
+            if let MusicSource::Song(sequencer) = &mut music.source {
+                sequencer.set_mood(mood);
+            }
+        }
+    }
+
+    pub fn play_stinger(&mut self) {
+        for music in &mut self.music {
+            if let MusicSource::Song(sequencer) = &mut music.source {
+                sequencer.stinger();
+            }
+        }
+    }
+
+    pub fn stop_music(&mut self, fade_seconds: f32) {
+        if fade_seconds > 0.0 {
+            for music in &mut self.music {
+                music.fade = -1.0 / fade_seconds;
+            }
+        } else {
+            self.music.clear();
+        }
+    }
+
+    // NOTE(Fermin): Fills interleaved stereo frames, finished voices are
+    // dropped along the way. Equal power panning keeps the loudness the same
+    // across the field.
+    pub fn mix(&mut self, out: &mut [i16]) {
+        let sample_rate = self.sample_rate;
+        let frame_count = out.len() / 2;
+        self.left.clear();
+        self.left.resize(frame_count, 0.0);
+        self.right.clear();
+        self.right.resize(frame_count, 0.0);
+        let (left, right) = (&mut self.left, &mut self.right);
+        self.voices.retain_mut(|voice| {
+            let angle = (voice.pan + 1.0) * std::f32::consts::FRAC_PI_4;
+            let (left_gain, right_gain) = (angle.cos() * voice.volume, angle.sin() * voice.volume);
+            for (left, right) in left.iter_mut().zip(right.iter_mut()) {
+                let Some((left_sample, right_sample)) = voice.next(sample_rate) else {
+                    return false;
+                };
+                *left += left_sample * left_gain;
+                *right += right_sample * right_gain;
+            }
+            true
+        });
+
+        let music_volume = self.music_volume;
+        let fade_step = 1.0 / sample_rate as f32;
+        self.music.retain_mut(|music| {
+            for (left, right) in left.iter_mut().zip(right.iter_mut()) {
+                music.volume = (music.volume + music.fade * fade_step).clamp(0.0, 1.0);
+                if music.fade < 0.0 && music.volume == 0.0 {
+                    return false;
+                }
+                let (left_sample, right_sample) = music.next(sample_rate);
+                *left += left_sample * music.volume * music_volume;
+                *right += right_sample * music.volume * music_volume;
+            }
+            true
+        });
+
+        let tone = &mut self.tracking_tone;
+        if tone.target.is_some() || tone.volume > 0.0 {
+            let glide = (1.0 / (sample_rate as f32 * TRACKING_TONE_GLIDE_SECONDS)).min(1.0);
+            for (left, right) in left.iter_mut().zip(right.iter_mut()) {
+                let (target, target_volume) = match tone.target {
+                    Some(target) => (target, 1.0),
+                    None => (tone.position, 0.0),
+                };
+                tone.position.pan += (target.pan - tone.position.pan) * glide;
+                tone.position.pitch += (target.pitch - tone.position.pitch) * glide;
+                tone.volume += (target_volume - tone.volume) * glide;
+                let sample = tone
+                    .oscillator
+                    .next(TRACKING_TONE_HZ * tone.position.pitch, sample_rate)
+                    * tone.volume;
+                let angle = (tone.position.pan + 1.0) * std::f32::consts::FRAC_PI_4;
+                *left += sample * angle.cos();
+                *right += sample * angle.sin();
+            }
+            if tone.target.is_none() && tone.volume < 0.001 {
+                tone.volume = 0.0;
+            }
+        }
+
+        let scale = self.master_volume * 0.25 * i16::MAX as f32;
+        for (frame, (left, right)) in out.chunks_exact_mut(2).zip(left.iter().zip(right.iter())) {
+            frame[0] = (left * scale).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            frame[1] = (right * scale).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        }
+    }
+}
+
+// NOTE(Fermin): Where mixed audio goes, interleaved stereo 16 bit samples at
+// AUDIO_SAMPLE_RATE
+pub trait AudioOutput {
+    fn write(&mut self, samples: &[i16]) -> Result<()>;
+}
+
+// NOTE(Fermin): Drops everything, for machines without sound
+pub struct NullOutput;
+impl AudioOutput for NullOutput {
+    fn write(&mut self, _samples: &[i16]) -> Result<()> {
+        Ok(())
+    }
+}
+
+// NOTE(Fermin): Whole session to a .wav file, the header sizes are filled in
+// when the output is dropped
+pub struct WavFileOutput {
+    file: BufWriter<File>,
+    data_bytes: u32,
+}
+impl WavFileOutput {
+    pub fn create(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut result = Self {
+            file: BufWriter::new(File::create(path)?),
+            data_bytes: 0,
+        };
+        result.write_header()?;
+        Ok(result)
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        let block_align = AUDIO_CHANNELS * 2;
+        let file = &mut self.file;
+        file.write_all(b"RIFF")?;
+        file.write_all(&(36 + self.data_bytes).to_le_bytes())?;
+        file.write_all(b"WAVEfmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        // NOTE(Fermin): PCM
+        file.write_all(&1u16.to_le_bytes())?;
+        file.write_all(&AUDIO_CHANNELS.to_le_bytes())?;
+        file.write_all(&AUDIO_SAMPLE_RATE.to_le_bytes())?;
+        file.write_all(&(AUDIO_SAMPLE_RATE * block_align as u32).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&16u16.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&self.data_bytes.to_le_bytes())?;
+        Ok(())
+    }
+}
+impl AudioOutput for WavFileOutput {
+    fn write(&mut self, samples: &[i16]) -> Result<()> {
+        for sample in samples {
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_bytes += (samples.len() * 2) as u32;
+        Ok(())
+    }
+}
+impl Drop for WavFileOutput {
+    fn drop(&mut self) {
+        let result = self
+            .file
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| self.write_header())
+            .and_then(|_| self.file.flush());
+        if let Err(error) = result {
+            println!("Error finishing the audio file: {}", error);
+        }
+    }
+}
+
+// NOTE(Fermin): winmm wave out with a ring of buffers, a frame of audio per
+// buffer. If they are all still playing the frame is dropped.
+#[cfg(windows)]
+pub struct WaveOutOutput {
+    device: windows::Win32::Media::Audio::HWAVEOUT,
+    headers: Box<[windows::Win32::Media::Audio::WAVEHDR]>,
+    buffers: Vec<Vec<i16>>,
+}
+#[cfg(windows)]
+const WAVE_OUT_BUFFERS: usize = 8;
+#[cfg(windows)]
+impl WaveOutOutput {
+    pub fn open() -> Result<Self> {
+        use windows::Win32::Media::Audio::*;
+        let block_align = AUDIO_CHANNELS * 2;
+        let format = WAVEFORMATEX {
+            wFormatTag: WAVE_FORMAT_PCM as u16,
+            nChannels: AUDIO_CHANNELS,
+            nSamplesPerSec: AUDIO_SAMPLE_RATE,
+            nAvgBytesPerSec: AUDIO_SAMPLE_RATE * block_align as u32,
+            nBlockAlign: block_align,
+            wBitsPerSample: 16,
+            cbSize: 0,
+        };
+        let mut device = HWAVEOUT::default();
+        let result =
+            unsafe { waveOutOpen(Some(&mut device), WAVE_MAPPER, &format, 0, 0, CALLBACK_NULL) };
+        if result != 0 {
+            return Err(Error::other(format!("waveOutOpen failed with {}", result)));
+        }
+        let headers = vec![WAVEHDR::default(); WAVE_OUT_BUFFERS].into_boxed_slice();
+        Ok(Self {
+            device,
+            headers,
+            buffers: vec![Vec::new(); WAVE_OUT_BUFFERS],
+        })
+    }
+}
+#[cfg(windows)]
+impl AudioOutput for WaveOutOutput {
+    fn write(&mut self, samples: &[i16]) -> Result<()> {
+        use windows::Win32::Media::Audio::*;
+        let header_size = std::mem::size_of::<WAVEHDR>() as u32;
+        // NOTE(Fermin): Headers that were never sent have no flags
+        let Some(index) = self
+            .headers
+            .iter()
+            .position(|header| header.dwFlags == 0 || header.dwFlags & WHDR_DONE != 0)
+        else {
+            return Ok(());
+        };
+        let header = &mut self.headers[index];
+        let buffer = &mut self.buffers[index];
+        unsafe {
+            if header.dwFlags != 0 {
+                waveOutUnprepareHeader(self.device, header, header_size);
+            }
+            buffer.clear();
+            buffer.extend_from_slice(samples);
+            *header = WAVEHDR {
+                lpData: windows::core::PSTR(buffer.as_mut_ptr() as *mut u8),
+                dwBufferLength: (buffer.len() * 2) as u32,
+                ..Default::default()
+            };
+            waveOutPrepareHeader(self.device, header, header_size);
+            let result = waveOutWrite(self.device, header, header_size);
+            if result != 0 {
+                return Err(Error::other(format!("waveOutWrite failed with {}", result)));
+            }
+        }
+        Ok(())
+    }
+}
+#[cfg(windows)]
+impl Drop for WaveOutOutput {
+    fn drop(&mut self) {
+        use windows::Win32::Media::Audio::*;
+        let header_size = std::mem::size_of::<WAVEHDR>() as u32;
+        unsafe {
+            waveOutReset(self.device);
+            for header in self.headers.iter_mut().filter(|header| header.dwFlags != 0) {
+                waveOutUnprepareHeader(self.device, header, header_size);
+            }
+            waveOutClose(self.device);
+        }
+    }
+}
+
+// NOTE(Fermin): A device that plays samples and returns once it took them,
+// which can take as long as the device wants
+#[cfg(target_os = "linux")]
+pub trait AudioDevice: Send {
+    fn play(&mut self, samples: &[i16]) -> Result<()>;
+}
+
+// NOTE(Fermin): Keeps the game loop from ever waiting on the device. Frames
+// go into a bounded queue and a writer thread feeds them to the device. If
+// the queue is full the frame is dropped, like the wave out ring does.
+#[cfg(target_os = "linux")]
+pub struct ThreadedOutput {
+    queue: std::sync::Arc<(std::sync::Mutex<OutputQueue>, std::sync::Condvar)>,
+    writer: Option<std::thread::JoinHandle<()>>,
+}
+#[cfg(target_os = "linux")]
+struct OutputQueue {
+    samples: std::collections::VecDeque<i16>,
+    capacity: usize,
+    running: bool,
+    // NOTE(Fermin): Set when the device fails, the next write reports it
+    error: Option<Error>,
+}
+#[cfg(target_os = "linux")]
+const OUTPUT_QUEUE_SECONDS: f32 = 0.25;
+#[cfg(target_os = "linux")]
+impl ThreadedOutput {
+    pub fn new(device: Box<dyn AudioDevice>) -> Self {
+        let capacity =
+            (OUTPUT_QUEUE_SECONDS * AUDIO_SAMPLE_RATE as f32) as usize * AUDIO_CHANNELS as usize;
+        Self::with_capacity(device, capacity)
+    }
+
+    fn with_capacity(mut device: Box<dyn AudioDevice>, capacity: usize) -> Self {
+        use std::sync::{Arc, Condvar, Mutex};
+        let queue = Arc::new((
+            Mutex::new(OutputQueue {
+                samples: std::collections::VecDeque::with_capacity(capacity),
+                capacity,
+                running: true,
+                error: None,
+            }),
+            Condvar::new(),
+        ));
+        let writer_queue = Arc::clone(&queue);
+        let writer = std::thread::spawn(move || {
+            let (queue, ready) = &*writer_queue;
+            let mut chunk = Vec::new();
+            loop {
+                {
+                    let mut queue = queue.lock().unwrap();
+                    while queue.running && queue.samples.is_empty() {
+                        queue = ready.wait(queue).unwrap();
+                    }
+                    if !queue.running {
+                        return;
+                    }
+                    chunk.clear();
+                    chunk.extend(queue.samples.drain(..));
+                }
+                if let Err(error) = device.play(&chunk) {
+                    let mut queue = queue.lock().unwrap();
+                    queue.error = Some(error);
+                    queue.running = false;
+                    return;
+                }
+            }
+        });
+        Self {
+            queue,
+            writer: Some(writer),
+        }
+    }
+}
+#[cfg(target_os = "linux")]
+impl AudioOutput for ThreadedOutput {
+    fn write(&mut self, samples: &[i16]) -> Result<()> {
+        let (queue, ready) = &*self.queue;
+        let mut queue = queue.lock().unwrap();
+        if let Some(error) = queue.error.take() {
+            return Err(error);
+        }
+        if queue.samples.len() + samples.len() <= queue.capacity {
+            queue.samples.extend(samples);
+            ready.notify_one();
+        }
+        Ok(())
+    }
+}
+#[cfg(target_os = "linux")]
+impl Drop for ThreadedOutput {
+    fn drop(&mut self) {
+        let (queue, ready) = &*self.queue;
+        queue.lock().unwrap().running = false;
+        ready.notify_one();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+// NOTE(Fermin): libasound is loaded at run time so machines without it still
+// start, they play without sound. The default device goes through PulseAudio
+// or PipeWire when they are running.
+#[cfg(target_os = "linux")]
+mod alsa {
+    use super::*;
+    use std::ffi::{c_char, c_int, c_long, c_uint, c_ulong, c_void, CStr};
+
+    const RTLD_NOW: c_int = 2;
+    const SND_PCM_STREAM_PLAYBACK: c_int = 0;
+    const SND_PCM_FORMAT_S16_LE: c_int = 2;
+    const SND_PCM_ACCESS_RW_INTERLEAVED: c_int = 3;
+
+    extern "C" {
+        fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+        fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    }
+
+    type PcmOpen = unsafe extern "C" fn(*mut *mut c_void, *const c_char, c_int, c_int) -> c_int;
+    type PcmSetParams =
+        unsafe extern "C" fn(*mut c_void, c_int, c_int, c_uint, c_uint, c_int, c_uint) -> c_int;
+    type PcmWritei = unsafe extern "C" fn(*mut c_void, *const c_void, c_ulong) -> c_long;
+    type PcmRecover = unsafe extern "C" fn(*mut c_void, c_int, c_int) -> c_int;
+    type PcmClose = unsafe extern "C" fn(*mut c_void) -> c_int;
+    type StrError = unsafe extern "C" fn(c_int) -> *const c_char;
+
+    fn symbol<T: Copy>(library: *mut c_void, name: &CStr) -> Result<T> {
+        let address = unsafe { dlsym(library, name.as_ptr()) };
+        if address.is_null() {
+            return Err(Error::other(format!(
+                "libasound has no {}",
+                name.to_string_lossy()
+            )));
+        }
+        // NOTE(Fermin): T is always one of the function pointer types above
+        Ok(unsafe { std::mem::transmute_copy(&address) })
+    }
+
+    pub struct AlsaDevice {
+        pcm: *mut c_void,
+        writei: PcmWritei,
+        recover: PcmRecover,
+        close: PcmClose,
+        strerror: StrError,
+    }
+    // NOTE(Fermin): The handle moves to the writer thread and is only used
+    // from there
+    unsafe impl Send for AlsaDevice {}
+    impl AlsaDevice {
+        pub fn open() -> Result<Self> {
+            let library = unsafe { dlopen(c"libasound.so.2".as_ptr(), RTLD_NOW) };
+            if library.is_null() {
+                return Err(Error::other("libasound.so.2 not found"));
+            }
+            let open: PcmOpen = symbol(library, c"snd_pcm_open")?;
+            let set_params: PcmSetParams = symbol(library, c"snd_pcm_set_params")?;
+            let mut result = Self {
+                pcm: std::ptr::null_mut(),
+                writei: symbol(library, c"snd_pcm_writei")?,
+                recover: symbol(library, c"snd_pcm_recover")?,
+                close: symbol(library, c"snd_pcm_close")?,
+                strerror: symbol(library, c"snd_strerror")?,
+            };
+
+            let code = unsafe {
+                open(
+                    &mut result.pcm,
+                    c"default".as_ptr(),
+                    SND_PCM_STREAM_PLAYBACK,
+                    0,
+                )
+            };
+            if code < 0 {
+                return Err(result.error("snd_pcm_open", code));
+            }
+            let latency_microseconds = (AUDIO_LATENCY_SECONDS * 1_000_000.0) as c_uint;
+            let code = unsafe {
+                set_params(
+                    result.pcm,
+                    SND_PCM_FORMAT_S16_LE,
+                    SND_PCM_ACCESS_RW_INTERLEAVED,
+                    AUDIO_CHANNELS as c_uint,
+                    AUDIO_SAMPLE_RATE,
+                    1,
+                    latency_microseconds,
+                )
+            };
+            if code < 0 {
+                return Err(result.error("snd_pcm_set_params", code));
+            }
+            Ok(result)
+        }
+
+        fn error(&self, function: &str, code: c_int) -> Error {
+            let message = unsafe { CStr::from_ptr((self.strerror)(code)) };
+            Error::other(format!(
+                "{} failed: {}",
+                function,
+                message.to_string_lossy()
+            ))
+        }
+    }
+    impl AudioDevice for AlsaDevice {
+        fn play(&mut self, samples: &[i16]) -> Result<()> {
+            let channels = AUDIO_CHANNELS as usize;
+            let mut frames = samples;
+            while !frames.is_empty() {
+                let written = unsafe {
+                    (self.writei)(
+                        self.pcm,
+                        frames.as_ptr() as *const c_void,
+                        (frames.len() / channels) as c_ulong,
+                    )
+                };
+                if written < 0 {
+                    // NOTE(Fermin): Underruns and suspends come back as
+                    // errors, recovering gets the device going again
+                    let code = unsafe { (self.recover)(self.pcm, written as c_int, 1) };
+                    if code < 0 {
+                        return Err(self.error("snd_pcm_writei", code));
+                    }
+                } else {
+                    frames = &frames[written as usize * channels..];
+                }
+            }
+            Ok(())
+        }
+    }
+    impl Drop for AlsaDevice {
+        fn drop(&mut self) {
+            if !self.pcm.is_null() {
+                unsafe {
+                    (self.close)(self.pcm);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum AudioBackend {
+    // NOTE(Fermin): The platform one, falling back to null if it fails
+    Auto,
+    Null,
+    WavFile(PathBuf),
+}
+impl AudioBackend {
+    pub fn open(&self) -> Box<dyn AudioOutput> {
+        match self {
+            AudioBackend::Auto => match open_platform_output() {
+                Ok(output) => output,
+                Err(error) => {
+                    println!("Error opening audio, playing without sound: {}", error);
+                    Box::new(NullOutput)
+                }
+            },
+            AudioBackend::Null => Box::new(NullOutput),
+            AudioBackend::WavFile(path) => {
+                Box::new(WavFileOutput::create(path).expect("Error creating the audio file"))
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+fn open_platform_output() -> Result<Box<dyn AudioOutput>> {
+    Ok(Box::new(WaveOutOutput::open()?))
+}
+
+#[cfg(target_os = "linux")]
+fn open_platform_output() -> Result<Box<dyn AudioOutput>> {
+    let device = alsa::AlsaDevice::open()?;
+    Ok(Box::new(ThreadedOutput::new(Box::new(device))))
+}
+
+// TODO(Fermin): Sound output on the other platforms, --audio-wav still
+// records the session there
+#[cfg(not(any(windows, target_os = "linux")))]
+fn open_platform_output() -> Result<Box<dyn AudioOutput>> {
+    Err(Error::other("no audio device support on this platform"))
+}
+
+// NOTE(Fermin): Mixes exactly as many frames as the game simulated, the
+// leftover fraction of a frame carries over to the next one
+pub struct Audio {
+    pub mixer: Mixer,
+    bank: SoundBank,
+    music_track: Option<MusicTrack>,
+    cue_seconds: f32,
+    cue_paddle: usize,
+    output: Box<dyn AudioOutput>,
+    frames_owed: f32,
+    samples: Vec<i16>,
+}
+impl Audio {
+    pub fn new(backend: &AudioBackend, assets: &Path) -> Self {
+        Self {
+            mixer: Mixer::new(AUDIO_SAMPLE_RATE),
+            bank: SoundBank::load(assets),
+            music_track: None,
+            cue_seconds: 0.0,
+            cue_paddle: 0,
+            output: backend.open(),
+            frames_owed: AUDIO_LATENCY_SECONDS * AUDIO_SAMPLE_RATE as f32,
+            samples: Vec::new(),
+        }
+    }
+
+    // NOTE(Fermin): Samples from the assets win over the synthesized blips
+    pub fn play(&mut self, sounds: &[SoundEvent]) {
+        for sound in sounds {
+            if sound.kind == SoundKind::Death {
+                self.mixer.play_stinger();
+            }
+            match self.bank.sound(sound.kind) {
+                Some(sample) => self.mixer.play_sample(sample, 1.0, sound.position),
+                None => self.mixer.play_sound(sound.kind, 1.0, sound.position),
+            }
+        }
+    }
+
+    // NOTE(Fermin): Audio only mode, called every frame with None while no
+    // game is going. The paddles take turns so their ticks don't pile up.
+    pub fn update_cues(&mut self, cues: Option<AudioCues>, dt: f32) {
+        let Some(cues) = cues else {
+            self.mixer.set_tracking_tone(None);
+            return;
+        };
+        self.mixer
+            .set_tracking_tone(cues.ball.map(StereoPosition::spread_for_cues));
+        self.cue_seconds = (self.cue_seconds - dt).max(0.0);
+        if self.cue_seconds == 0.0 && !cues.paddles.is_empty() {
+            self.cue_seconds = PADDLE_CUE_SECONDS / cues.paddles.len() as f32;
+            self.cue_paddle = (self.cue_paddle + 1) % cues.paddles.len();
+            let position = cues.paddles[self.cue_paddle].spread_for_cues();
+            self.play(&[SoundEvent::at(SoundKind::PaddleCue, position)]);
+        }
+    }
+
+    // NOTE(Fermin): Called every frame, only a change of track fades. Tracks
+    // without a file fade to silence.
+    pub fn set_music(&mut self, track: MusicTrack) {
+        if self.music_track == Some(track) {
+            return;
+        }
+        self.music_track = Some(track);
+        match self.bank.music(track) {
+            Some(sample) => self.mixer.play_music(sample, MUSIC_FADE_SECONDS),
+            None => self.mixer.stop_music(MUSIC_FADE_SECONDS),
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.frames_owed += dt * AUDIO_SAMPLE_RATE as f32;
+        let frames = self.frames_owed as usize;
+        self.frames_owed -= frames as f32;
+        self.samples.clear();
+        self.samples.resize(frames * AUDIO_CHANNELS as usize, 0);
+        self.mixer.mix(&mut self.samples);
+        if let Err(error) = self.output.write(&self.samples) {
+            println!("Error writing audio, playing without sound: {}", error);
+            self.output = Box::new(NullOutput);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blip_frames(kind: SoundKind) -> usize {
+        let seconds = sound_blips(kind)
+            .iter()
+            .map(|blip| blip.delay + blip.seconds)
+            .fold(0.0, f32::max);
+        (seconds * AUDIO_SAMPLE_RATE as f32).ceil() as usize
+    }
+
+    fn temp_wav(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("poong_{}_{}.wav", name, std::process::id()))
+    }
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([bytes[at], bytes[at + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+    }
+
+    // NOTE(Fermin): Returns the samples after checking the header matches
+    fn read_wav(path: &Path) -> Vec<i16> {
+        let bytes = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32_at(&bytes, 4) as usize, bytes.len() - 8);
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&bytes, 16), 16);
+        assert_eq!(u16_at(&bytes, 20), 1);
+        assert_eq!(u16_at(&bytes, 22), AUDIO_CHANNELS);
+        assert_eq!(u32_at(&bytes, 24), AUDIO_SAMPLE_RATE);
+        assert_eq!(u32_at(&bytes, 28), AUDIO_SAMPLE_RATE * 4);
+        assert_eq!(u16_at(&bytes, 32), 4);
+        assert_eq!(u16_at(&bytes, 34), 16);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(u32_at(&bytes, 40) as usize, bytes.len() - 44);
+        bytes[44..]
+            .chunks_exact(2)
+            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn silent_mixer_mixes_zeros() {
+        let mut mixer = Mixer::new(AUDIO_SAMPLE_RATE);
+        let mut out = vec![1i16; 512];
+        mixer.mix(&mut out);
+        assert!(out.iter().all(|sample| *sample == 0));
+    }
+
+    #[test]
+    fn blips_end_after_their_length() {
+        let frames = blip_frames(SoundKind::PaddleHit);
+        let mut mixer = Mixer::new(AUDIO_SAMPLE_RATE);
+        mixer.play_sound(SoundKind::PaddleHit, 1.0, StereoPosition::CENTER);
+
+        let mut out = vec![0i16; (frames - 1) * 2];
+        mixer.mix(&mut out);
+        assert!(out.iter().any(|sample| *sample != 0));
+        assert_eq!(mixer.voices.len(), 1);
+
+        let mut out = vec![0i16; 4 * 2];
+        mixer.mix(&mut out);
+        assert!(mixer.voices.is_empty());
+        let mut out = vec![1i16; 64];
+        mixer.mix(&mut out);
+        assert!(out.iter().all(|sample| *sample == 0));
+    }
+
+    #[test]
+    fn panned_blips_stay_on_their_side() {
+        let mut mixer = Mixer::new(AUDIO_SAMPLE_RATE);
+        let left = StereoPosition {
+            pan: -1.0,
+            ..StereoPosition::CENTER
+        };
+        mixer.play_sound(SoundKind::WallHit, 1.0, left);
+        let mut out = vec![0i16; blip_frames(SoundKind::WallHit) * 2];
+        mixer.mix(&mut out);
+        assert!(out.chunks_exact(2).any(|frame| frame[0] != 0));
+        assert!(out.chunks_exact(2).all(|frame| frame[1] == 0));
+    }
+
+    #[test]
+    fn mixed_blips_go_to_the_wav_file() {
+        let path = temp_wav("mix");
+        let frames = blip_frames(SoundKind::BrickBreak) + 100;
+        let mut mixer = Mixer::new(AUDIO_SAMPLE_RATE);
+        let mut expected = vec![0i16; frames * 2];
+        {
+            let mut output = WavFileOutput::create(&path).unwrap();
+            mixer.play_sound(SoundKind::BrickBreak, 1.0, StereoPosition::CENTER);
+            // NOTE(Fermin): Uneven writes, like frames of different lengths
+            for chunk in expected.chunks_mut(734) {
+                mixer.mix(chunk);
+                output.write(chunk).unwrap();
+                NullOutput.write(chunk).unwrap();
+            }
+        }
+        let samples = read_wav(&path);
+        assert_eq!(samples.len(), frames * 2);
+        assert_eq!(samples, expected);
+        assert!(samples.iter().any(|sample| *sample != 0));
+        assert!(samples[samples.len() - 200..]
+            .iter()
+            .all(|sample| *sample == 0));
+    }
+
+    #[test]
+    fn audio_writes_the_frames_it_is_owed() {
+        let path = temp_wav("audio");
+        let updates = 90;
+        let dt = 1.0 / 60.0;
+        {
+            let mut audio = Audio::new(
+                &AudioBackend::WavFile(path.clone()),
+                Path::new("no_assets_here"),
+            );
+            audio.set_music(MusicTrack::Title);
+            audio.play(&[SoundEvent::new(SoundKind::MenuSelect)]);
+            for _ in 0..updates {
+                audio.update(dt);
+            }
+        }
+        let frames = read_wav(&path).len() / 2;
+        let expected = (AUDIO_LATENCY_SECONDS + updates as f32 * dt) * AUDIO_SAMPLE_RATE as f32;
+        assert!((frames as f32 - expected).abs() <= 1.0);
+    }
+
+    // NOTE(Fermin): Stands in for a device, hands every chunk to the test and
+    // waits until the test lets it go
+    #[cfg(target_os = "linux")]
+    struct GatedDevice {
+        played: std::sync::mpsc::Sender<Vec<i16>>,
+        release: std::sync::mpsc::Receiver<Result<()>>,
+    }
+    #[cfg(target_os = "linux")]
+    impl AudioDevice for GatedDevice {
+        fn play(&mut self, samples: &[i16]) -> Result<()> {
+            let _ = self.played.send(samples.to_vec());
+            self.release
+                .recv()
+                .unwrap_or_else(|_| Err(Error::other("test is over")))
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn gated_output(
+        capacity: usize,
+    ) -> (
+        ThreadedOutput,
+        std::sync::mpsc::Receiver<Vec<i16>>,
+        std::sync::mpsc::Sender<Result<()>>,
+    ) {
+        let (played_sender, played) = std::sync::mpsc::channel();
+        let (release, release_receiver) = std::sync::mpsc::channel();
+        let device = GatedDevice {
+            played: played_sender,
+            release: release_receiver,
+        };
+        (
+            ThreadedOutput::with_capacity(Box::new(device), capacity),
+            played,
+            release,
+        )
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn threaded_output_never_waits_on_the_device() {
+        let (mut output, played, release) = gated_output(8);
+        output.write(&[1, 1]).unwrap();
+        assert_eq!(played.recv().unwrap(), vec![1, 1]);
+
+        // NOTE(Fermin): The device is stuck on the first frame, only what
+        // fits the queue is kept and the rest is dropped
+        let start = std::time::Instant::now();
+        for value in 2..10 {
+            output.write(&[value; 4]).unwrap();
+        }
+        assert!(start.elapsed() < std::time::Duration::from_millis(100));
+
+        release.send(Ok(())).unwrap();
+        assert_eq!(played.recv().unwrap(), vec![2, 2, 2, 2, 3, 3, 3, 3]);
+        release.send(Ok(())).unwrap();
+
+        output.write(&[10, 10]).unwrap();
+        assert_eq!(played.recv().unwrap(), vec![10, 10]);
+        release.send(Ok(())).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn threaded_output_reports_device_errors() {
+        let (mut output, played, release) = gated_output(64);
+        output.write(&[1, 1]).unwrap();
+        played.recv().unwrap();
+        release.send(Err(Error::other("unplugged"))).unwrap();
+
+        let start = std::time::Instant::now();
+        while output.write(&[2, 2]).is_ok() {
+            assert!(start.elapsed() < std::time::Duration::from_secs(5));
+            std::thread::yield_now();
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn threaded_output_stops_the_writer_when_dropped() {
+        let (mut output, played, release) = gated_output(64);
+        output.write(&[1, 1]).unwrap();
+        played.recv().unwrap();
+        output.write(&[2, 2]).unwrap();
+        // NOTE(Fermin): Enough to let the writer through whether it plays the
+        // second frame or sees the drop first
+        release.send(Ok(())).unwrap();
+        release.send(Ok(())).unwrap();
+        drop(output);
+        assert!(played.try_iter().all(|chunk| chunk == vec![2, 2]));
+        assert!(release.send(Ok(())).is_err());
+    }
+}
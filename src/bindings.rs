@@ -0,0 +1,457 @@
+use crate::savestate::*;
+use crate::*;
+use bytes::{Buf, BufMut};
+use std::fs;
+use std::io::Result;
+use std::path::Path;
+use windows::Win32::UI::Input::KeyboardAndMouse::*;
+
+pub const BINDINGS_PATH: &str = "bindings.cfg";
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Back,
+    Start,
+    Jump,
+    P2MoveUp,
+    P2MoveDown,
+    P2MoveLeft,
+    P2MoveRight,
+    P3MoveLeft,
+    P3MoveRight,
+    P4MoveLeft,
+    P4MoveRight,
+}
+pub const ACTIONS: [Action; 15] = [
+    Action::MoveUp,
+    Action::MoveDown,
+    Action::MoveLeft,
+    Action::MoveRight,
+    Action::Back,
+    Action::Start,
+    Action::Jump,
+    Action::P2MoveUp,
+    Action::P2MoveDown,
+    Action::P2MoveLeft,
+    Action::P2MoveRight,
+    Action::P3MoveLeft,
+    Action::P3MoveRight,
+    Action::P4MoveLeft,
+    Action::P4MoveRight,
+];
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::MoveUp => "move_up",
+            Action::MoveDown => "move_down",
+            Action::MoveLeft => "move_left",
+            Action::MoveRight => "move_right",
+            Action::Back => "back",
+            Action::Start => "start",
+            Action::Jump => "jump",
+            Action::P2MoveUp => "p2_move_up",
+            Action::P2MoveDown => "p2_move_down",
+            Action::P2MoveLeft => "p2_move_left",
+            Action::P2MoveRight => "p2_move_right",
+            Action::P3MoveLeft => "p3_move_left",
+            Action::P3MoveRight => "p3_move_right",
+            Action::P4MoveLeft => "p4_move_left",
+            Action::P4MoveRight => "p4_move_right",
+        }
+    }
+    pub fn label(self) -> String {
+        self.name().replace('_', " ").to_uppercase()
+    }
+    fn from_name(name: &str) -> Option<Action> {
+        ACTIONS.into_iter().find(|action| action.name() == name)
+    }
+    // NOTE(Fermin): The other players only have movement, their actions
+    // drive the move buttons of their own key set. Players three and four
+    // are the top and bottom paddles so they only go sideways.
+    pub fn player(self) -> usize {
+        match self {
+            Action::P2MoveUp | Action::P2MoveDown | Action::P2MoveLeft | Action::P2MoveRight => 1,
+            Action::P3MoveLeft | Action::P3MoveRight => 2,
+            Action::P4MoveLeft | Action::P4MoveRight => 3,
+            _ => 0,
+        }
+    }
+    pub fn button(self, buttons: &mut InputButtons) -> &mut GameButtonState {
+        match self {
+            Action::MoveUp | Action::P2MoveUp => &mut buttons.move_up,
+            Action::MoveDown | Action::P2MoveDown => &mut buttons.move_down,
+            Action::MoveLeft | Action::P2MoveLeft | Action::P3MoveLeft | Action::P4MoveLeft => {
+                &mut buttons.move_left
+            }
+            Action::MoveRight | Action::P2MoveRight | Action::P3MoveRight | Action::P4MoveRight => {
+                &mut buttons.move_right
+            }
+            Action::Back => &mut buttons.back,
+            Action::Start => &mut buttons.start,
+            Action::Jump => &mut buttons.jump,
+        }
+    }
+    // NOTE(Fermin): Back and start drive the menus, without them there is no
+    // way to get out of the controls screen
+    pub fn is_required(self) -> bool {
+        matches!(self, Action::Back | Action::Start)
+    }
+}
+
+// NOTE(Fermin): Platform key codes (VKs) per action, an action is down while
+// any of its keys is held
+pub struct KeyBindings {
+    keys: [Vec<u16>; ACTIONS.len()],
+    pub changed: bool,
+}
+// NOTE(Fermin): Written out, a derive here makes rustc report every
+// `use crate::bindings::*` next to `use crate::*` as unused
+impl Clone for KeyBindings {
+    fn clone(&self) -> Self {
+        Self {
+            keys: self.keys.clone(),
+            changed: self.changed,
+        }
+    }
+}
+impl KeyBindings {
+    pub fn new() -> Self {
+        let mut result = Self {
+            keys: Default::default(),
+            changed: false,
+        };
+        for action in ACTIONS {
+            result.keys[action as usize] = Self::default_keys(action);
+        }
+        result
+    }
+
+    // NOTE(Fermin): Outside of co-op every key set moves the paddles, so the
+    // arrows keep working for a single player. The top paddle gets keys on
+    // the top letter row, the bottom paddle on the bottom one.
+    fn default_keys(action: Action) -> Vec<u16> {
+        match action {
+            Action::MoveUp => vec!['W' as u16],
+            Action::MoveDown => vec!['S' as u16],
+            Action::MoveLeft => vec!['A' as u16],
+            Action::MoveRight => vec!['D' as u16],
+            Action::Back => vec![VK_ESCAPE.0],
+            Action::Start => vec![VK_RETURN.0],
+            Action::Jump => vec![VK_SPACE.0],
+            Action::P2MoveUp => vec![VK_UP.0],
+            Action::P2MoveDown => vec![VK_DOWN.0],
+            Action::P2MoveLeft => vec![VK_LEFT.0],
+            Action::P2MoveRight => vec![VK_RIGHT.0],
+            Action::P3MoveLeft => vec!['U' as u16],
+            Action::P3MoveRight => vec!['O' as u16],
+            Action::P4MoveLeft => vec!['B' as u16],
+            Action::P4MoveRight => vec!['M' as u16],
+        }
+    }
+
+    pub fn keys(&self, action: Action) -> &[u16] {
+        &self.keys[action as usize]
+    }
+
+    pub fn action_for_key(&self, key: u16) -> Option<Action> {
+        ACTIONS
+            .into_iter()
+            .find(|action| self.keys[*action as usize].contains(&key))
+    }
+
+    // NOTE(Fermin): A key only drives one action, binding it takes it away
+    // from whatever action had it before
+    pub fn bind(&mut self, action: Action, key: u16) {
+        for keys in self.keys.iter_mut() {
+            keys.retain(|bound_key| *bound_key != key);
+        }
+        self.keys[action as usize].push(key);
+        self.changed = true;
+    }
+
+    pub fn clear(&mut self, action: Action) {
+        self.keys[action as usize].clear();
+        self.changed = true;
+    }
+
+    // NOTE(Fermin): One action per line, "move_up = W, UP". Empty lines and
+    // lines starting with # are skipped, actions missing from the file keep
+    // the default keys the file didn't give to something else.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut result = Self {
+            keys: Default::default(),
+            changed: false,
+        };
+        let mut in_file = [false; ACTIONS.len()];
+        for (line_index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| {
+                invalid_data(&format!(
+                    "{}:{}: {}",
+                    path.display(),
+                    line_index + 1,
+                    message
+                ))
+            };
+
+            let (name, keys) = line
+                .split_once('=')
+                .ok_or_else(|| error(format!("expected 'action = KEY, KEY', got '{}'", line)))?;
+            let action = Action::from_name(name.trim())
+                .ok_or_else(|| error(format!("unknown action '{}'", name.trim())))?;
+
+            in_file[action as usize] = true;
+            result.keys[action as usize].clear();
+            for key_name in keys.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+                let key = key_from_name(key_name)
+                    .ok_or_else(|| error(format!("unknown key '{}'", key_name)))?;
+                if let Some(other) = result.action_for_key(key) {
+                    return Err(error(format!(
+                        "key {} is already bound to {}",
+                        key_name,
+                        other.name()
+                    )));
+                }
+                result.keys[action as usize].push(key);
+            }
+        }
+
+        for action in ACTIONS {
+            if !in_file[action as usize] {
+                for key in Self::default_keys(action) {
+                    if result.action_for_key(key).is_none() {
+                        result.keys[action as usize].push(key);
+                    }
+                }
+            }
+        }
+        for action in ACTIONS {
+            if action.is_required() && result.keys(action).is_empty() {
+                return Err(invalid_data(&format!(
+                    "{}: {} needs at least one key",
+                    path.display(),
+                    action.name()
+                )));
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn load_or_default(path: &Path) -> Self {
+        if !path.exists() {
+            return Self::new();
+        }
+        match Self::load(path) {
+            Ok(result) => result,
+            Err(error) => {
+                println!("Error loading bindings, using defaults: {}", error);
+                Self::new()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut text = String::from("# Poong key bindings: action = KEY, KEY\n");
+        for action in ACTIONS {
+            text.push_str(&format!("{} = {}\n", action.name(), self.key_names(action)));
+        }
+        fs::write(path, text)
+    }
+
+    pub fn key_names(&self, action: Action) -> String {
+        self.keys(action)
+            .iter()
+            .map(|key| key_name(*key))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+// NOTE(Fermin): Replays store the bindings they started with, the controls
+// screen plays back differently depending on which keys are taken
+impl Persist for KeyBindings {
+    fn write(&self, out: &mut BytesMut) {
+        for keys in &self.keys {
+            out.put_u8(keys.len() as u8);
+            for key in keys {
+                out.put_u16_le(*key);
+            }
+        }
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        let mut result = Self {
+            keys: Default::default(),
+            changed: false,
+        };
+        for action in ACTIONS {
+            ensure_remaining(buf, 1)?;
+            let count = buf.get_u8() as usize;
+            ensure_remaining(buf, count * 2)?;
+            for _ in 0..count {
+                let key = buf.get_u16_le();
+                if result.action_for_key(key).is_some() {
+                    return Err(invalid_data(&format!("key {} is bound twice", key)));
+                }
+                result.keys[action as usize].push(key);
+            }
+            if action.is_required() && result.keys(action).is_empty() {
+                return Err(invalid_data(&format!(
+                    "{} needs at least one key",
+                    action.name()
+                )));
+            }
+        }
+        Ok(result)
+    }
+}
+
+const NAMED_KEYS: [(VIRTUAL_KEY, &str); 12] = [
+    (VK_UP, "UP"),
+    (VK_DOWN, "DOWN"),
+    (VK_LEFT, "LEFT"),
+    (VK_RIGHT, "RIGHT"),
+    (VK_ESCAPE, "ESCAPE"),
+    (VK_RETURN, "ENTER"),
+    (VK_SPACE, "SPACE"),
+    (VK_TAB, "TAB"),
+    (VK_BACK, "BACKSPACE"),
+    (VK_SHIFT, "SHIFT"),
+    (VK_CONTROL, "CTRL"),
+    (VK_MENU, "ALT"),
+];
+
+// NOTE(Fermin): Letters and digits use their own character, keys without a
+// name are written as KEY<code>
+pub fn key_name(key: u16) -> String {
+    if let Some((_, name)) = NAMED_KEYS.iter().find(|(vk, _)| vk.0 == key) {
+        return name.to_string();
+    }
+    match char::from_u32(key as u32) {
+        Some(c) if c.is_ascii_uppercase() || c.is_ascii_digit() => c.to_string(),
+        _ => format!("KEY{}", key),
+    }
+}
+
+pub fn key_from_name(name: &str) -> Option<u16> {
+    let name = name.to_uppercase();
+    if let Some((vk, _)) = NAMED_KEYS.iter().find(|(_, key_name)| *key_name == name) {
+        return Some(vk.0);
+    }
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_uppercase() || c.is_ascii_digit() => Some(c as u16),
+        _ => name.strip_prefix("KEY")?.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("poong_{}_{}.txt", name, std::process::id()))
+    }
+
+    fn load_text(name: &str, text: &str) -> Result<KeyBindings> {
+        let path = temp_path(name);
+        fs::write(&path, text).unwrap();
+        let result = KeyBindings::load(&path);
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    fn assert_same_keys(a: &KeyBindings, b: &KeyBindings) {
+        for action in ACTIONS {
+            assert_eq!(a.keys(action), b.keys(action), "{}", action.name());
+        }
+    }
+
+    #[test]
+    fn defaults_round_trip() {
+        let bindings = KeyBindings::new();
+        let path = temp_path("defaults");
+        bindings.save(&path).unwrap();
+        let loaded = KeyBindings::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_same_keys(&bindings, &loaded);
+    }
+
+    #[test]
+    fn key_moved_to_an_earlier_action_round_trips() {
+        let mut bindings = KeyBindings::new();
+        bindings.bind(Action::MoveUp, VK_DOWN.0);
+        assert!(bindings.keys(Action::P2MoveDown).is_empty());
+
+        let path = temp_path("moved");
+        bindings.save(&path).unwrap();
+        let loaded = KeyBindings::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_same_keys(&bindings, &loaded);
+        assert!(loaded.action_for_key(VK_DOWN.0) == Some(Action::MoveUp));
+    }
+
+    #[test]
+    fn missing_actions_keep_the_free_defaults() {
+        let loaded = load_text("missing", "move_up = W, DOWN\n").unwrap();
+        assert_eq!(loaded.keys(Action::MoveUp), &['W' as u16, VK_DOWN.0]);
+        assert_eq!(loaded.keys(Action::MoveDown), &['S' as u16]);
+        assert!(loaded.keys(Action::P2MoveDown).is_empty());
+    }
+
+    #[test]
+    fn bad_files_are_errors() {
+        assert!(load_text("no_equals", "move_up W\n").is_err());
+        assert!(load_text("unknown_action", "fly = W\n").is_err());
+        assert!(load_text("unknown_key", "move_up = NOPE\n").is_err());
+        assert!(load_text("duplicate", "move_up = W\nmove_down = W\n").is_err());
+        assert!(load_text("empty_start", "start =\n").is_err());
+    }
+
+    #[test]
+    fn persist_round_trip() {
+        let mut bindings = KeyBindings::new();
+        bindings.bind(Action::MoveUp, VK_DOWN.0);
+        bindings.clear(Action::Jump);
+        let mut out = BytesMut::new();
+        bindings.write(&mut out);
+        let mut buf = &out[..];
+        let read = KeyBindings::read(&mut buf).unwrap();
+        assert!(buf.is_empty());
+        assert!(!read.changed);
+        assert_same_keys(&bindings, &read);
+    }
+
+    #[test]
+    fn bad_persisted_bindings_are_errors() {
+        let mut out = BytesMut::new();
+        KeyBindings::new().write(&mut out);
+        assert!(KeyBindings::read(&mut &out[..out.len() - 1]).is_err());
+
+        // NOTE(Fermin): MoveDown gets W, which MoveUp already has
+        let mut duplicate = out.clone();
+        duplicate[4..6].copy_from_slice(&('W' as u16).to_le_bytes());
+        assert!(KeyBindings::read(&mut &duplicate[..]).is_err());
+
+        let mut no_start = BytesMut::new();
+        let mut bindings = KeyBindings::new();
+        bindings.clear(Action::Start);
+        bindings.write(&mut no_start);
+        assert!(KeyBindings::read(&mut &no_start[..]).is_err());
+    }
+
+    #[test]
+    fn key_names_round_trip() {
+        for key in ['A' as u16, '7' as u16, VK_SPACE.0, VK_UP.0, 0xBA] {
+            assert_eq!(key_from_name(&key_name(key)), Some(key));
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use crate::game::*;
+use crate::level::*;
+use crate::rng::*;
+use crate::savestate::*;
+use bytes::BytesMut;
+use rand::{Rng, RngCore};
+use std::fs;
+use std::io::Result;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const DAILY_RESULTS_PATH: &str = "saves/daily.txt";
+// NOTE(Fermin): Mixed into the day number so the daily seeds don't line up
+// with small seeds people type by hand
+const DAILY_SEED_SALT: u64 = 0x504F_4F4E_4744_4159;
+// NOTE(Fermin): Four digit years, far enough from the i32 day count limits
+// that the calendar math can't overflow
+const MIN_YEAR: i32 = 1;
+const MAX_YEAR: i32 = 9999;
+
+// NOTE(Fermin): Calendar date in UTC, so everyone gets the same challenge
+// no matter their time zone
+#[derive(Copy, Clone, PartialEq)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+impl Date {
+    pub fn today() -> Self {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("System time is before the unix epoch")
+            .as_secs();
+        Self::from_days((seconds / 86400) as i32)
+    }
+
+    // NOTE(Fermin): Days since 1970-01-01, from Howard Hinnant's civil
+    // calendar algorithms
+    pub fn from_days(days: i32) -> Self {
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let day_of_era = z.rem_euclid(146097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = year_of_era + era * 400 + (month <= 2) as i32;
+        Self { year, month, day }
+    }
+
+    pub fn days(self) -> i32 {
+        let year = self.year - (self.month <= 2) as i32;
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let month = self.month as i32;
+        let day_of_year =
+            (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + self.day as i32 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146097 + day_of_era - 719468
+    }
+
+    // NOTE(Fermin): YYYY-MM-DD, days that don't exist are rejected
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut parts = text.trim().split('-');
+        let year = parts.next()?.parse().ok()?;
+        let month = parts.next()?.parse().ok()?;
+        let day = parts.next()?.parse().ok()?;
+        if parts.next().is_some()
+            || !(MIN_YEAR..=MAX_YEAR).contains(&year)
+            || !(1..=12).contains(&month)
+            || !(1..=31).contains(&day)
+        {
+            return None;
+        }
+        let result = Self { year, month, day };
+        (Self::from_days(result.days()) == result).then_some(result)
+    }
+
+    pub fn text(self) -> String {
+        format!("{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+impl Persist for Date {
+    fn write(&self, out: &mut BytesMut) {
+        self.days().write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        let days = i32::read(buf)?;
+        let first = Date {
+            year: MIN_YEAR,
+            month: 1,
+            day: 1,
+        };
+        let last = Date {
+            year: MAX_YEAR,
+            month: 12,
+            day: 31,
+        };
+        if !(first.days()..=last.days()).contains(&days) {
+            return Err(invalid_data(&format!("invalid date {}", days)));
+        }
+        Ok(Self::from_days(days))
+    }
+}
+
+pub fn daily_seed(date: Date) -> u64 {
+    GameRng::new(date.days() as u64 ^ DAILY_SEED_SALT).next_u64()
+}
+
+// NOTE(Fermin): The whole game comes from the date, only the mouse settings
+// are the player's own
+pub fn daily_settings(date: Date, mouse: MouseSettings) -> GameSettings {
+    let mut rng = GameRng::new(daily_seed(date));
+    let ball_speed = rng.gen_range(0.85..1.3);
+    let paddle_scale = rng.gen_range(0.7..1.3);
+
+    let mut level = Level::new();
+    level.name = format!("DAILY {}", date.text());
+    level.paddle_length = (level.paddle_length as f32 * paddle_scale).round() as i32;
+    level.ball_base_speed *= ball_speed;
+    level.ball_min_speed *= ball_speed;
+    level.ball_max_speed *= ball_speed;
+
+    let mut result = GameSettings::new();
+    result.mouse = mouse;
+    result.mode = GameMode::Survival;
+    result.power_ups = rng.gen_bool(0.5);
+    result.levels = vec![level];
+    result.daily = Some(date);
+    result
+}
+
+// NOTE(Fermin): One 'YYYY-MM-DD SCORE' line per day played, lines that
+// don't parse are dropped on the next save
+fn load_daily_results(path: &Path) -> Vec<(Date, u32)> {
+    let Ok(text) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    text.lines()
+        .filter_map(|line| {
+            let (date, score) = line.split_once(' ')?;
+            Some((Date::parse(date)?, score.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+pub fn best_daily_result(date: Date) -> Option<u32> {
+    load_daily_results(Path::new(DAILY_RESULTS_PATH))
+        .into_iter()
+        .find(|(result_date, _)| *result_date == date)
+        .map(|(_, score)| score)
+}
+
+// NOTE(Fermin): Keeps the best score of every day
+pub fn save_daily_result(date: Date, score: u32) -> Result<()> {
+    let path = Path::new(DAILY_RESULTS_PATH);
+    let mut results = load_daily_results(path);
+    match results
+        .iter_mut()
+        .find(|(result_date, _)| *result_date == date)
+    {
+        Some((_, best)) => *best = (*best).max(score),
+        None => results.push((date, score)),
+    }
+    results.sort_by_key(|(result_date, _)| result_date.days());
+
+    let mut text = String::new();
+    for (result_date, result_score) in results {
+        text.push_str(&format!("{} {}\n", result_date.text(), result_score));
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, text)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> Date {
+        Date { year, month, day }
+    }
+
+    #[test]
+    fn known_days() {
+        assert!(Date::from_days(0) == date(1970, 1, 1));
+        assert_eq!(date(1970, 1, 1).days(), 0);
+        assert_eq!(date(1969, 12, 31).days(), -1);
+        assert_eq!(date(2000, 3, 1).days(), 11017);
+        assert_eq!(date(2024, 2, 29).days(), 19782);
+    }
+
+    #[test]
+    fn days_round_trip() {
+        let first = date(MIN_YEAR, 1, 1).days();
+        let last = date(MAX_YEAR, 12, 31).days();
+        let mut previous: Option<Date> = None;
+        for days in first..=last {
+            let result = Date::from_days(days);
+            assert_eq!(result.days(), days);
+            assert!((1..=12).contains(&result.month) && (1..=31).contains(&result.day));
+            if let Some(previous) = previous {
+                assert!(result.year >= previous.year);
+            }
+            previous = Some(result);
+        }
+        assert!(Date::from_days(first) == date(MIN_YEAR, 1, 1));
+        assert!(Date::from_days(last) == date(MAX_YEAR, 12, 31));
+    }
+
+    #[test]
+    fn parse_dates() {
+        assert!(Date::parse("2024-02-29") == Some(date(2024, 2, 29)));
+        assert!(Date::parse(" 1999-12-31 ") == Some(date(1999, 12, 31)));
+        assert_eq!(date(2024, 3, 5).text(), "2024-03-05");
+        assert!(Date::parse(&date(2024, 3, 5).text()) == Some(date(2024, 3, 5)));
+    }
+
+    #[test]
+    fn parse_rejects_bad_dates() {
+        for text in [
+            "",
+            "2024",
+            "2024-01",
+            "2024-01-01-01",
+            "2023-02-29",
+            "1900-02-29",
+            "2024-04-31",
+            "2024-13-01",
+            "2024-00-10",
+            "2024-01-00",
+            "2024-01-32",
+            "0-01-01",
+            "10000-01-01",
+            "9999999-01-01",
+            "-5-01-01",
+            "2024-4294967295-01",
+            "2024-01-4294967295",
+            "a-b-c",
+        ] {
+            assert!(Date::parse(text).is_none(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn read_rejects_days_out_of_range() {
+        for days in [i32::MIN, date(MIN_YEAR, 1, 1).days() - 1, i32::MAX] {
+            let mut out = BytesMut::new();
+            days.write(&mut out);
+            assert!(Date::read(&mut &out[..]).is_err());
+        }
+        let mut out = BytesMut::new();
+        date(2024, 2, 29).write(&mut out);
+        assert!(Date::read(&mut &out[..]).unwrap() == date(2024, 2, 29));
+    }
+}
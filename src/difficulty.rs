@@ -0,0 +1,353 @@
+use crate::level::*;
+use crate::savestate::*;
+use bytes::BytesMut;
+use std::fs;
+use std::io::Result;
+use std::path::Path;
+
+// NOTE(Fermin): Curves are plain text in the same 'key = values' format as
+// the levels, the presets are written the same way so they can be copied
+// into a file and tuned from there
+pub const DIFFICULTY_PRESETS: [(&str, &str); 4] = [
+    ("off", ""),
+    (
+        "easy",
+        "clock = seconds
+         ball_speed = 0 0.85  120 1.0  300 1.15
+         paddle_length = 0 1.25  180 1.0",
+    ),
+    (
+        "normal",
+        "clock = seconds
+         ball_speed = 0 1.0  90 1.2  240 1.4
+         paddle_length = 0 1.0  120 0.85  300 0.7
+         extra_balls = 0 0  120 0  121 1",
+    ),
+    (
+        "hard",
+        "clock = hits
+         ball_speed = 0 1.1  20 1.35  60 1.7
+         paddle_length = 0 1.0  30 0.75  80 0.55
+         paddle_speed = 0 1.0  60 1.25
+         extra_balls = 0 0  15 1  40 2  80 3",
+    ),
+];
+
+// NOTE(Fermin): What the curves are read against, seconds into the level or
+// paddle hits so far
+#[derive(Copy, Clone, PartialEq)]
+pub enum DifficultyClock {
+    Seconds,
+    Hits,
+}
+impl Persist for DifficultyClock {
+    fn write(&self, out: &mut BytesMut) {
+        (*self == DifficultyClock::Hits).write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        Ok(if bool::read(buf)? {
+            DifficultyClock::Hits
+        } else {
+            DifficultyClock::Seconds
+        })
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Keyframe {
+    pub at: f32,
+    pub value: f32,
+}
+impl Persist for Keyframe {
+    fn write(&self, out: &mut BytesMut) {
+        self.at.write(out);
+        self.value.write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        let result = Self {
+            at: f32::read(buf)?,
+            value: f32::read(buf)?,
+        };
+        if !result.at.is_finite() || !result.value.is_finite() {
+            return Err(invalid_data("keyframe that isn't a number"));
+        }
+        Ok(result)
+    }
+}
+
+// NOTE(Fermin): Straight lines between keyframes, flat before the first one
+// and after the last one. Keyframes are sorted by `at`.
+#[derive(Clone)]
+pub struct Curve {
+    keyframes: Vec<Keyframe>,
+}
+impl Curve {
+    fn flat(value: f32) -> Self {
+        Self {
+            keyframes: vec![Keyframe { at: 0.0, value }],
+        }
+    }
+
+    pub fn sample(&self, at: f32) -> f32 {
+        let first = self.keyframes[0];
+        if at <= first.at {
+            return first.value;
+        }
+        for pair in self.keyframes.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            if at < b.at {
+                let t = (at - a.at) / (b.at - a.at);
+                return a.value + (b.value - a.value) * t;
+            }
+        }
+        self.keyframes[self.keyframes.len() - 1].value
+    }
+}
+impl Persist for Curve {
+    fn write(&self, out: &mut BytesMut) {
+        write_vec(&self.keyframes, out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        let keyframes = read_vec(buf)?;
+        if keyframes.is_empty() {
+            return Err(invalid_data("curve without keyframes"));
+        }
+        Ok(Self { keyframes })
+    }
+}
+
+// NOTE(Fermin): Every curve but extra_balls is a share of what the level
+// sets, extra_balls is rounded down and counts balls on top of the serve
+#[derive(Clone)]
+pub struct DifficultyCurves {
+    pub name: String,
+    pub clock: DifficultyClock,
+    pub ball_speed: Curve,
+    pub paddle_length: Curve,
+    pub paddle_speed: Curve,
+    pub extra_balls: Curve,
+}
+impl DifficultyCurves {
+    pub fn new() -> Self {
+        Self {
+            name: "off".to_string(),
+            clock: DifficultyClock::Seconds,
+            ball_speed: Curve::flat(1.0),
+            paddle_length: Curve::flat(1.0),
+            paddle_speed: Curve::flat(1.0),
+            extra_balls: Curve::flat(0.0),
+        }
+    }
+}
+impl Persist for DifficultyCurves {
+    fn write(&self, out: &mut BytesMut) {
+        self.name.write(out);
+        self.clock.write(out);
+        self.ball_speed.write(out);
+        self.paddle_length.write(out);
+        self.paddle_speed.write(out);
+        self.extra_balls.write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        Ok(Self {
+            name: String::read(buf)?,
+            clock: DifficultyClock::read(buf)?,
+            ball_speed: Curve::read(buf)?,
+            paddle_length: Curve::read(buf)?,
+            paddle_speed: Curve::read(buf)?,
+            extra_balls: Curve::read(buf)?,
+        })
+    }
+}
+
+pub fn difficulty_preset(name: &str) -> Option<DifficultyCurves> {
+    let (name, text) = DIFFICULTY_PRESETS
+        .into_iter()
+        .find(|(preset, _)| *preset == name)?;
+    let mut result =
+        parse_difficulty(text, &format!("preset {}", name)).expect("Difficulty presets are valid");
+    result.name = name.to_string();
+    Some(result)
+}
+
+// NOTE(Fermin): One 'key = values' per line and # starts a comment, curves
+// take any number of AT VALUE pairs with AT going up:
+//
+//   clock = seconds | hits
+//   ball_speed = AT VALUE ...
+//   paddle_length = AT VALUE ...
+//   paddle_speed = AT VALUE ...
+//   extra_balls = AT VALUE ...
+pub fn load_difficulty(path: &Path) -> Result<DifficultyCurves> {
+    let text = fs::read_to_string(path)?;
+    let mut result = parse_difficulty(&text, &path.display().to_string())?;
+    result.name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    Ok(result)
+}
+
+fn parse_difficulty(text: &str, source: &str) -> Result<DifficultyCurves> {
+    let mut result = DifficultyCurves::new();
+    for (line_index, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error =
+            |message: String| invalid_data(&format!("{}:{}: {}", source, line_index + 1, message));
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error(format!("expected 'key = value', got '{}'", line)))?;
+        let key = key.trim();
+        let value = value.trim();
+        if key == "clock" {
+            result.clock = match value {
+                "seconds" => DifficultyClock::Seconds,
+                "hits" => DifficultyClock::Hits,
+                _ => {
+                    return Err(error(format!(
+                        "expected 'clock = seconds | hits', got '{}'",
+                        value
+                    )))
+                }
+            };
+            continue;
+        }
+
+        let (curve, min_value) = match key {
+            "ball_speed" => (&mut result.ball_speed, 0.1),
+            "paddle_length" => (&mut result.paddle_length, 0.1),
+            "paddle_speed" => (&mut result.paddle_speed, 0.1),
+            "extra_balls" => (&mut result.extra_balls, 0.0),
+            _ => return Err(error(format!("unknown key '{}'", key))),
+        };
+        let usage = format!("{} = AT VALUE ...", key);
+        let values: Vec<&str> = value.split_whitespace().collect();
+        if values.is_empty() || !values.len().is_multiple_of(2) {
+            return Err(error(format!(
+                "expected '{}', got {} values",
+                usage,
+                values.len()
+            )));
+        }
+        let mut keyframes: Vec<Keyframe> = Vec::new();
+        for pair in values.chunks(2) {
+            let keyframe = Keyframe {
+                at: parse_number(pair[0], &usage).map_err(error)?,
+                value: parse_number(pair[1], &usage).map_err(error)?,
+            };
+            if keyframe.at < 0.0 {
+                return Err(error(format!("{} can't start before 0", key)));
+            }
+            if keyframe.value < min_value {
+                return Err(error(format!(
+                    "{} values must be at least {}",
+                    key, min_value
+                )));
+            }
+            if let Some(previous) = keyframes.last() {
+                if keyframe.at <= previous.at {
+                    return Err(error(format!(
+                        "{} keyframe at {} must come after {}",
+                        key, keyframe.at, previous.at
+                    )));
+                }
+            }
+            keyframes.push(keyframe);
+        }
+        curve.keyframes = keyframes;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve(keyframes: &[(f32, f32)]) -> Curve {
+        Curve {
+            keyframes: keyframes
+                .iter()
+                .map(|(at, value)| Keyframe {
+                    at: *at,
+                    value: *value,
+                })
+                .collect(),
+        }
+    }
+
+    fn parse(text: &str) -> Result<DifficultyCurves> {
+        parse_difficulty(text, "test")
+    }
+
+    #[test]
+    fn sample_is_flat_outside_the_keyframes() {
+        let curve = curve(&[(10.0, 1.0), (20.0, 3.0)]);
+        assert_eq!(curve.sample(0.0), 1.0);
+        assert_eq!(curve.sample(10.0), 1.0);
+        assert_eq!(curve.sample(20.0), 3.0);
+        assert_eq!(curve.sample(1000.0), 3.0);
+        assert_eq!(Curve::flat(0.5).sample(42.0), 0.5);
+    }
+
+    #[test]
+    fn sample_interpolates_between_keyframes() {
+        let curve = curve(&[(0.0, 1.0), (10.0, 2.0), (30.0, 0.0)]);
+        assert_eq!(curve.sample(5.0), 1.5);
+        assert_eq!(curve.sample(10.0), 2.0);
+        assert_eq!(curve.sample(20.0), 1.0);
+        assert_eq!(curve.sample(25.0), 0.5);
+    }
+
+    #[test]
+    fn presets_parse() {
+        for (name, _) in DIFFICULTY_PRESETS {
+            let curves = difficulty_preset(name).unwrap();
+            assert_eq!(curves.name, name);
+        }
+        assert!(difficulty_preset("impossible").is_none());
+    }
+
+    #[test]
+    fn parses_curves() {
+        let curves = parse(
+            "clock = hits # per paddle hit\n\
+             ball_speed = 0 1.0  10 2.0\n\
+             extra_balls = 5 0  6 2\n",
+        )
+        .unwrap();
+        assert!(curves.clock == DifficultyClock::Hits);
+        assert_eq!(curves.ball_speed.sample(5.0), 1.5);
+        assert_eq!(curves.extra_balls.sample(6.0), 2.0);
+        assert_eq!(curves.paddle_length.sample(100.0), 1.0);
+    }
+
+    #[test]
+    fn bad_curves_are_errors() {
+        assert!(parse("clock = minutes\n").is_err());
+        assert!(parse("speed = 0 1\n").is_err());
+        assert!(parse("ball_speed 0 1\n").is_err());
+        assert!(parse("ball_speed =\n").is_err());
+        assert!(parse("ball_speed = 0 1 10\n").is_err());
+        assert!(parse("ball_speed = -1 1\n").is_err());
+        assert!(parse("ball_speed = 0 0.05\n").is_err());
+        assert!(parse("extra_balls = 0 -1\n").is_err());
+        assert!(parse("ball_speed = 10 1 10 2\n").is_err());
+        assert!(parse("ball_speed = 10 1 5 2\n").is_err());
+    }
+
+    #[test]
+    fn non_finite_keyframes_are_errors() {
+        assert!(parse("ball_speed = 0 nan\n").is_err());
+        assert!(parse("ball_speed = nan 1\n").is_err());
+        assert!(parse("paddle_length = 0 1 inf 2\n").is_err());
+        assert!(parse("paddle_speed = 0 infinity\n").is_err());
+
+        let mut out = BytesMut::new();
+        curve(&[(0.0, 1.0), (10.0, f32::NAN)]).write(&mut out);
+        assert!(Curve::read(&mut &out[..]).is_err());
+    }
+}
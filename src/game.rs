@@ -1,43 +1,761 @@
+use crate::ai::*;
+use crate::bindings::*;
+use crate::font::*;
+use crate::level::*;
+use crate::menu::*;
+use crate::obstacle::*;
+use crate::powerup::*;
+use crate::rng::*;
+use crate::savestate::*;
+use crate::title::*;
 use crate::window::*;
 use crate::*;
-use rand::Rng;
+use bytes::BytesMut;
+use rand::{Rng, RngCore};
+use std::io::Result;
 
-const BABY_PINK: Color   = Color { r: 250, g: 193, b: 235, a: 255, };
-const DARK_BLUE: Color   = Color { r:   3, g:  38, b:  82, a: 255, };
-const NEON_MINT: Color   = Color { r:   1, g: 255, b: 198, a: 255, };
-const NEON_YELLOW: Color = Color { r: 253, g: 255, b: 100, a: 255, };
-const RED: Color         = Color { r: 254, g:  80, b: 102, a: 255, };
+pub const BABY_PINK: Color   = Color { r: 250, g: 193, b: 235, a: 255, };
+pub const DARK_BLUE: Color   = Color { r:   3, g:  38, b:  82, a: 255, };
+pub const NEON_MINT: Color   = Color { r:   1, g: 255, b: 198, a: 255, };
+pub const NEON_YELLOW: Color = Color { r: 253, g: 255, b: 100, a: 255, };
+pub const RED: Color         = Color { r: 254, g:  80, b: 102, a: 255, };
+pub const SKY_BLUE: Color    = Color { r:  94, g: 200, b: 255, a: 255, };
+pub const ORANGE: Color      = Color { r: 255, g: 160, b:  60, a: 255, };
+pub const LILAC: Color       = Color { r: 190, g: 150, b: 255, a: 255, };
+pub const WALL_GREY: Color   = Color { r: 110, g: 118, b: 140, a: 255, };
 const BYTES_PER_PIXEL: i32 = 4;
-const ENTITY_Y_PADDING: i32 = 10;
-const ENTITY_X_PADDING: i32 = 10;
-const BALL_SIZE: i32 = 10;
-const PLAYER_WIDTH: i32 = 8;
-const BALL_MIN_DDP: f32 = 30_000.0;
+// NOTE(Fermin): Smallest share of the speed each axis keeps, so the ball
+// never ends up bouncing forever between two paddles on the same axis
+const BALL_MIN_AXIS_FRACTION: f32 = 0.3;
+const BALL_BOUNCE_JITTER: f32 = 0.35;
+const DEATH_SCENE_SECONDS: f32 = 3.0;
+const TIME_ATTACK_SECONDS: f32 = 60.0;
+const LEVEL_CLEAR_SECONDS: f32 = 2.0;
+const POSITION_PITCH_OCTAVES: f32 = 0.5;
+const PLAYER_COLORS: [Color; 4] = [BABY_PINK, NEON_YELLOW, SKY_BLUE, ORANGE];
+const BALL_COLORS: [Color; 4] = [NEON_MINT, RED, SKY_BLUE, NEON_YELLOW];
+const LIVES_PER_PLAYER: u32 = 3;
+const DEFAULT_TARGET_SCORE: u32 = 7;
+// NOTE(Fermin): Paddles by index are left, right, top, bottom
+pub const PADDLE_COUNT: usize = 4;
+const PADDLE_HORIZONTAL: [bool; PADDLE_COUNT] = [false, false, true, true];
+const ALL_PADDLES: u32 = (1 << PADDLE_COUNT) - 1;
+// NOTE(Fermin): Power-ups stay away from the paddles
+const POWER_UP_MARGIN: f32 = 60.0;
 
 static mut RECTANGLES_TO_CLEAR_NEXT_FRAME: Vec<RectForClear> = Vec::new();
 
+#[derive(Clone)]
 pub struct GameMemory {
     l_entity: Entity,
     r_entity: Entity,
     b_entity: Entity,
     t_entity: Entity,
-    ball: Entity,
+    // NOTE(Fermin): Never empty, the last ball is served again instead of
+    // removed
+    balls: Vec<Ball>,
+    balls_lost: u32,
+    // NOTE(Fermin): Every mode but versus and four player scores, rally hits
+    // times the multiplier
+    points: u32,
+    power_ups: Vec<PowerUp>,
+    effects: Vec<ActiveEffect>,
+    power_up_timer: f32,
+    // NOTE(Fermin): Separate from the ball so turning power-ups on doesn't
+    // change the serves
+    power_up_rng: GameRng,
+    obstacles: Vec<Obstacle>,
+    arena: Arena,
+    level_index: u32,
+    level_seconds: f32,
+    // NOTE(Fermin): Balls the difficulty curve added since the last serve
+    difficulty_balls: u32,
+    ball_config: BallConfig,
+    settings: GameSettings,
+    rally_hits: u32,
+    player_hits: [u32; PADDLE_COUNT],
+    lives: [u32; PADDLE_COUNT],
+    scores: [u32; 2],
+    server: u32,
+    ais: [PaddleAi; PADDLE_COUNT],
+    seed: u64,
+    rng: GameRng,
     is_initialized: bool,
+    // NOTE(Fermin): Played by the platform layer after the frame, never
+    // saved
+    sounds: Vec<SoundEvent>,
+    // NOTE(Fermin): Best daily score kept on disk, the platform layer fills
+    // it in and keeps the daily results file. Never saved.
+    daily_best: Option<u32>,
+    daily_result: Option<(Date, u32)>,
 }
 impl GameMemory {
-    pub fn new() -> Self {
+    pub fn new(seed: u64, settings: GameSettings) -> Self {
         Self {
-            l_entity: Entity::new(PLAYER_WIDTH, PLAYER_WIDTH * 5, BABY_PINK),
-            r_entity: Entity::new(PLAYER_WIDTH, PLAYER_WIDTH * 5, BABY_PINK),
-            b_entity: Entity::new(PLAYER_WIDTH * 5, PLAYER_WIDTH, NEON_YELLOW),
-            t_entity: Entity::new(PLAYER_WIDTH * 5, PLAYER_WIDTH, NEON_YELLOW),
-            ball: Entity::new(BALL_SIZE, BALL_SIZE, NEON_MINT),
+            l_entity: Entity::new(PLAYER_WIDTH, PLAYER_LENGTH, PLAYER_COLORS[0]),
+            r_entity: Entity::new(PLAYER_WIDTH, PLAYER_LENGTH, PLAYER_COLORS[0]),
+            b_entity: Entity::new(PLAYER_LENGTH, PLAYER_WIDTH, PLAYER_COLORS[1]),
+            t_entity: Entity::new(PLAYER_LENGTH, PLAYER_WIDTH, PLAYER_COLORS[1]),
+            balls: vec![Ball::new(Entity::new(BALL_SIZE, BALL_SIZE, BALL_COLORS[0]))],
+            balls_lost: 0,
+            points: 0,
+            power_ups: Vec::new(),
+            effects: Vec::new(),
+            power_up_timer: POWER_UP_SPAWN_SECONDS,
+            power_up_rng: power_up_rng(seed),
+            obstacles: Vec::new(),
+            arena: Arena {
+                p: V2 { x: 0.0, y: 0.0 },
+                width: 0,
+                height: 0,
+            },
+            level_index: 0,
+            level_seconds: 0.0,
+            difficulty_balls: 0,
+            ball_config: BallConfig::new(&Level::new()),
+            settings,
+            rally_hits: 0,
+            player_hits: [0; PADDLE_COUNT],
+            lives: [LIVES_PER_PLAYER; PADDLE_COUNT],
+            scores: [0; 2],
+            server: 0,
+            ais: paddle_ais(seed),
+            seed,
+            rng: GameRng::new(seed),
             is_initialized: false,
+            sounds: Vec::new(),
+            daily_best: None,
+            daily_result: None,
+        }
+    }
+
+    pub fn take_sounds(&mut self) -> Vec<SoundEvent> {
+        std::mem::take(&mut self.sounds)
+    }
+
+    pub fn daily(&self) -> Option<Date> {
+        self.settings.daily
+    }
+
+    pub fn set_daily_best(&mut self, best: Option<u32>) {
+        self.daily_best = best;
+    }
+
+    // NOTE(Fermin): Score of the daily game that just ended
+    pub fn take_daily_result(&mut self) -> Option<(Date, u32)> {
+        self.daily_result.take()
+    }
+
+    fn play_sound(&mut self, kind: SoundKind) {
+        self.sounds.push(SoundEvent::new(kind));
+    }
+
+    fn play_ball_sound(&mut self, kind: SoundKind, ball_index: usize) {
+        let position = self.arena.stereo_position(&self.balls[ball_index].entity);
+        self.sounds.push(SoundEvent::at(kind, position));
+    }
+
+    // NOTE(Fermin): For playing by ear, a tone follows the first ball and
+    // the paddles the players move tick where they are
+    pub fn audio_cues(&self) -> AudioCues {
+        let competitive = self.settings.mode.is_competitive();
+        AudioCues {
+            ball: self
+                .balls
+                .first()
+                .map(|ball| self.arena.stereo_position(&ball.entity)),
+            paddles: (0..PADDLE_COUNT)
+                .filter(|index| {
+                    !self.is_wall(*index)
+                        && (!competitive || self.settings.paddles[*index] == PaddleControl::Human)
+                })
+                .map(|index| self.arena.stereo_position(self.paddle(index)))
+                .collect(),
+        }
+    }
+
+    // NOTE(Fermin): Whether a game is going is up to the platform layer
+    pub fn music_mood(&self) -> MusicMood {
+        MusicMood {
+            tempo_scale: self.ball_speed() / self.ball_config.base_speed,
+            rally: self.rally_hits,
+            playing: false,
+        }
+    }
+
+    fn level(&self) -> &Level {
+        &self.settings.levels[self.level_index as usize]
+    }
+
+    // NOTE(Fermin): Where the difficulty curves are read
+    fn difficulty_clock(&self) -> f32 {
+        match self.settings.difficulty.clock {
+            DifficultyClock::Seconds => self.level_seconds,
+            DifficultyClock::Hits => self.player_hits.iter().sum::<u32>() as f32,
+        }
+    }
+
+    // NOTE(Fermin): Rally speed with the active effects and the difficulty
+    // curve on top
+    fn ball_speed(&self) -> f32 {
+        self.ball_config.speed_for_rally(self.rally_hits)
+            * ball_scale(&self.effects)
+            * self
+                .settings
+                .difficulty
+                .ball_speed
+                .sample(self.difficulty_clock())
+    }
+
+    fn paddle(&self, index: usize) -> &Entity {
+        match index {
+            0 => &self.l_entity,
+            1 => &self.r_entity,
+            2 => &self.t_entity,
+            _ => &self.b_entity,
+        }
+    }
+
+    fn paddle_mut(&mut self, index: usize) -> &mut Entity {
+        match index {
+            0 => &mut self.l_entity,
+            1 => &mut self.r_entity,
+            2 => &mut self.t_entity,
+            _ => &mut self.b_entity,
+        }
+    }
+
+    // NOTE(Fermin): Versus plays between top and bottom walls and four
+    // player games turn knocked out players into walls
+    fn is_wall(&self, index: usize) -> bool {
+        match self.settings.mode {
+            GameMode::Survival
+            | GameMode::Coop
+            | GameMode::Multiball
+            | GameMode::TimeAttack
+            | GameMode::Zen => false,
+            GameMode::FourPlayer => self.lives[index] == 0,
+            GameMode::Versus => PADDLE_HORIZONTAL[index],
+        }
+    }
+}
+impl Persist for GameMemory {
+    fn write(&self, out: &mut BytesMut) {
+        self.l_entity.write(out);
+        self.r_entity.write(out);
+        self.b_entity.write(out);
+        self.t_entity.write(out);
+        (self.balls.len() as u32).write(out);
+        for ball in &self.balls {
+            ball.write(out);
+        }
+        self.balls_lost.write(out);
+        self.points.write(out);
+        write_vec(&self.power_ups, out);
+        write_vec(&self.effects, out);
+        self.power_up_timer.write(out);
+        self.power_up_rng.write(out);
+        write_vec(&self.obstacles, out);
+        self.arena.write(out);
+        self.level_index.write(out);
+        self.level_seconds.write(out);
+        self.difficulty_balls.write(out);
+        self.ball_config.write(out);
+        self.settings.write(out);
+        self.rally_hits.write(out);
+        for hits in &self.player_hits {
+            hits.write(out);
+        }
+        for lives in &self.lives {
+            lives.write(out);
+        }
+        for score in &self.scores {
+            score.write(out);
+        }
+        self.server.write(out);
+        for ai in &self.ais {
+            ai.write(out);
+        }
+        self.seed.write(out);
+        self.rng.write(out);
+        self.is_initialized.write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        let l_entity = Entity::read(buf)?;
+        let r_entity = Entity::read(buf)?;
+        let b_entity = Entity::read(buf)?;
+        let t_entity = Entity::read(buf)?;
+        let ball_count = u32::read(buf)?;
+        if ball_count == 0 {
+            return Err(invalid_data("game without balls"));
+        }
+        let mut balls = Vec::new();
+        for _ in 0..ball_count {
+            balls.push(Ball::read(buf)?);
+        }
+        let result = Self {
+            l_entity,
+            r_entity,
+            b_entity,
+            t_entity,
+            balls,
+            balls_lost: u32::read(buf)?,
+            points: u32::read(buf)?,
+            power_ups: read_vec(buf)?,
+            effects: read_vec(buf)?,
+            power_up_timer: f32::read(buf)?,
+            power_up_rng: GameRng::read(buf)?,
+            obstacles: read_vec(buf)?,
+            arena: Arena::read(buf)?,
+            level_index: u32::read(buf)?,
+            level_seconds: f32::read(buf)?,
+            difficulty_balls: u32::read(buf)?,
+            ball_config: BallConfig::read(buf)?,
+            settings: GameSettings::read(buf)?,
+            rally_hits: u32::read(buf)?,
+            player_hits: [
+                u32::read(buf)?,
+                u32::read(buf)?,
+                u32::read(buf)?,
+                u32::read(buf)?,
+            ],
+            lives: [
+                u32::read(buf)?,
+                u32::read(buf)?,
+                u32::read(buf)?,
+                u32::read(buf)?,
+            ],
+            scores: [u32::read(buf)?, u32::read(buf)?],
+            server: u32::read(buf)?,
+            ais: [
+                PaddleAi::read(buf)?,
+                PaddleAi::read(buf)?,
+                PaddleAi::read(buf)?,
+                PaddleAi::read(buf)?,
+            ],
+            seed: u64::read(buf)?,
+            rng: GameRng::read(buf)?,
+            is_initialized: bool::read(buf)?,
+            sounds: Vec::new(),
+            daily_best: None,
+            daily_result: None,
+        };
+        if result.level_index as usize >= result.settings.levels.len() {
+            return Err(invalid_data("level index past the last level"));
+        }
+        Ok(result)
+    }
+}
+
+// NOTE(Fermin): Everything picked before the game starts, replays carry it
+// in their header
+#[derive(Clone)]
+pub struct GameSettings {
+    pub mouse: MouseSettings,
+    pub mode: GameMode,
+    pub paddles: [PaddleControl; PADDLE_COUNT],
+    // NOTE(Fermin): Points needed to win a versus match
+    pub target_score: u32,
+    pub ai_level: AiLevel,
+    pub multiball: MultiballSettings,
+    pub power_ups: bool,
+    // NOTE(Fermin): Played in order, never empty
+    pub levels: Vec<Level>,
+    pub difficulty: DifficultyCurves,
+    // NOTE(Fermin): Set for the daily challenge, results are kept per date
+    pub daily: Option<Date>,
+}
+impl GameSettings {
+    pub fn new() -> Self {
+        Self {
+            mouse: MouseSettings::new(),
+            mode: GameMode::Survival,
+            paddles: [
+                PaddleControl::Human,
+                PaddleControl::Ai,
+                PaddleControl::Ai,
+                PaddleControl::Ai,
+            ],
+            target_score: DEFAULT_TARGET_SCORE,
+            ai_level: AiLevel::Normal,
+            multiball: MultiballSettings::new(),
+            power_ups: false,
+            levels: vec![Level::new()],
+            difficulty: DifficultyCurves::new(),
+            daily: None,
+        }
+    }
+}
+impl Persist for GameSettings {
+    fn write(&self, out: &mut BytesMut) {
+        self.mouse.write(out);
+        self.mode.write(out);
+        for control in &self.paddles {
+            (*control == PaddleControl::Ai).write(out);
+        }
+        self.target_score.write(out);
+        self.ai_level.write(out);
+        self.multiball.write(out);
+        self.power_ups.write(out);
+        write_vec(&self.levels, out);
+        self.difficulty.write(out);
+        self.daily.is_some().write(out);
+        self.daily.unwrap_or(Date::from_days(0)).write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        let mouse = MouseSettings::read(buf)?;
+        let mode = GameMode::read(buf)?;
+        let mut paddles = [PaddleControl::Human; PADDLE_COUNT];
+        for control in paddles.iter_mut() {
+            if bool::read(buf)? {
+                *control = PaddleControl::Ai;
+            }
+        }
+        let target_score = u32::read(buf)?;
+        let ai_level = AiLevel::read(buf)?;
+        let multiball = MultiballSettings::read(buf)?;
+        let power_ups = bool::read(buf)?;
+        let levels = read_vec(buf)?;
+        if levels.is_empty() {
+            return Err(invalid_data("game without levels"));
+        }
+        let difficulty = DifficultyCurves::read(buf)?;
+        let has_daily = bool::read(buf)?;
+        let daily = Date::read(buf)?;
+        Ok(Self {
+            mouse,
+            mode,
+            paddles,
+            target_score,
+            ai_level,
+            multiball,
+            power_ups,
+            levels,
+            difficulty,
+            daily: has_daily.then_some(daily),
+        })
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum GameMode {
+    Survival,
+    Coop,
+    FourPlayer,
+    Versus,
+    Multiball,
+    TimeAttack,
+    Zen,
+}
+pub const GAME_MODES: [GameMode; 7] = [
+    GameMode::Survival,
+    GameMode::Coop,
+    GameMode::FourPlayer,
+    GameMode::Versus,
+    GameMode::Multiball,
+    GameMode::TimeAttack,
+    GameMode::Zen,
+];
+impl GameMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            GameMode::Survival => "survival",
+            GameMode::Coop => "coop",
+            GameMode::FourPlayer => "four",
+            GameMode::Versus => "versus",
+            GameMode::Multiball => "multi",
+            GameMode::TimeAttack => "time",
+            GameMode::Zen => "zen",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<GameMode> {
+        // NOTE(Fermin): Survival used to be called solo
+        if name == "solo" {
+            return Some(GameMode::Survival);
+        }
+        GAME_MODES.into_iter().find(|mode| mode.name() == name)
+    }
+    pub fn label(self) -> &'static str {
+        match self {
+            GameMode::Survival => "SURVIVAL",
+            GameMode::Coop => "CO-OP",
+            GameMode::FourPlayer => "FOUR PLAYER",
+            GameMode::Versus => "VERSUS",
+            GameMode::Multiball => "MULTIBALL",
+            GameMode::TimeAttack => "TIME ATTACK",
+            GameMode::Zen => "ZEN",
+        }
+    }
+    pub fn rules(self) -> ModeRules {
+        let ball_out = match self {
+            GameMode::Survival | GameMode::Coop | GameMode::Multiball => BallOut::Lost,
+            GameMode::TimeAttack => BallOut::Served,
+            GameMode::Zen => BallOut::Reflected,
+            GameMode::FourPlayer => BallOut::Life,
+            GameMode::Versus => BallOut::Point,
+        };
+        let time_limit = match self {
+            GameMode::TimeAttack => Some(TIME_ATTACK_SECONDS),
+            _ => None,
+        };
+        ModeRules {
+            ball_out,
+            time_limit,
+        }
+    }
+    // NOTE(Fermin): In co-op player one has the vertical pair and player two
+    // the horizontal one, in the other modes every paddle is a player
+    fn paddle_player(self, paddle: usize) -> usize {
+        match self {
+            GameMode::FourPlayer | GameMode::Versus => paddle,
+            _ => paddle / 2,
+        }
+    }
+    // NOTE(Fermin): Power-ups go to whoever hit the ball last instead of
+    // helping everyone
+    fn is_competitive(self) -> bool {
+        matches!(self, GameMode::FourPlayer | GameMode::Versus)
+    }
+}
+// NOTE(Fermin): What happens to a ball that gets past the paddles
+#[derive(Copy, Clone, PartialEq)]
+pub enum BallOut {
+    // NOTE(Fermin): Gone, losing the last one ends the game
+    Lost,
+    // NOTE(Fermin): Gone, the last one is served again
+    Served,
+    // NOTE(Fermin): Bounces back in off the edge it went through
+    Reflected,
+    // NOTE(Fermin): The player defending that side loses a life
+    Life,
+    // NOTE(Fermin): A point for the player on the other side
+    Point,
+}
+
+// NOTE(Fermin): How a mode plays on top of the shared simulation, the HUD
+// and controls are picked by mode where they are drawn and read
+#[derive(Copy, Clone)]
+pub struct ModeRules {
+    pub ball_out: BallOut,
+    // NOTE(Fermin): Seconds into the level until the game ends on its own
+    pub time_limit: Option<f32>,
+}
+
+impl Persist for GameMode {
+    fn write(&self, out: &mut BytesMut) {
+        (*self as u32).write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        let value = u32::read(buf)?;
+        GAME_MODES
+            .get(value as usize)
+            .copied()
+            .ok_or_else(|| invalid_data(&format!("invalid game mode {}", value)))
+    }
+}
+
+// NOTE(Fermin): Who moves a paddle in four player and versus games, human
+// paddles read the input of the player with the same index
+#[derive(Copy, Clone, PartialEq)]
+pub enum PaddleControl {
+    Human,
+    Ai,
+}
+
+pub const MOUSE_SENSITIVITY_RANGE: std::ops::RangeInclusive<f32> = 0.25..=4.0;
+pub const MOUSE_SMOOTHING_RANGE: std::ops::RangeInclusive<f32> = 0.0..=0.95;
+
+#[derive(Clone)]
+pub struct MouseSettings {
+    pub enabled: bool,
+    pub sensitivity: f32,
+    pub smoothing: f32,
+}
+impl MouseSettings {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            sensitivity: 1.0,
+            smoothing: 0.5,
+        }
+    }
+}
+impl Persist for MouseSettings {
+    fn write(&self, out: &mut BytesMut) {
+        self.enabled.write(out);
+        self.sensitivity.write(out);
+        self.smoothing.write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        let result = Self {
+            enabled: bool::read(buf)?,
+            sensitivity: f32::read(buf)?,
+            smoothing: f32::read(buf)?,
+        };
+        if !MOUSE_SENSITIVITY_RANGE.contains(&result.sensitivity)
+            || !MOUSE_SMOOTHING_RANGE.contains(&result.smoothing)
+        {
+            return Err(invalid_data("mouse settings out of range"));
+        }
+        Ok(result)
+    }
+}
+
+// NOTE(Fermin): Upper bound for the command line, every ball is drawn and
+// collides with every other one
+pub const MAX_BALLS_LIMIT: u32 = 32;
+
+// NOTE(Fermin): Extra balls come in every `milestone` rally hits in any mode,
+// the multiball mode also serves `start_balls` at once
+#[derive(Clone)]
+pub struct MultiballSettings {
+    pub start_balls: u32,
+    // NOTE(Fermin): 0 never adds balls
+    pub milestone: u32,
+    pub max_balls: u32,
+    // NOTE(Fermin): Survival, co-op and multiball games end after losing
+    // this many balls, 0 plays until the last one is gone
+    pub balls_to_lose: u32,
+}
+impl MultiballSettings {
+    pub fn new() -> Self {
+        Self {
+            start_balls: 3,
+            milestone: 0,
+            max_balls: 6,
+            balls_to_lose: 0,
+        }
+    }
+}
+impl Persist for MultiballSettings {
+    fn write(&self, out: &mut BytesMut) {
+        self.start_balls.write(out);
+        self.milestone.write(out);
+        self.max_balls.write(out);
+        self.balls_to_lose.write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        let result = Self {
+            start_balls: u32::read(buf)?,
+            milestone: u32::read(buf)?,
+            max_balls: u32::read(buf)?,
+            balls_to_lose: u32::read(buf)?,
+        };
+        let limit = 1..=MAX_BALLS_LIMIT;
+        if !limit.contains(&result.start_balls) || !limit.contains(&result.max_balls) {
+            return Err(invalid_data(&format!(
+                "ball counts must be between 1 and {}",
+                MAX_BALLS_LIMIT
+            )));
+        }
+        Ok(result)
+    }
+}
+
+// NOTE(Fermin): Speeds are in pixels per second and apply to the magnitude
+// of the ball velocity, the direction is left alone
+#[derive(Clone)]
+struct BallConfig {
+    base_speed: f32,
+    min_speed: f32,
+    max_speed: f32,
+    speed_ramp: f32,
+}
+impl BallConfig {
+    fn new(level: &Level) -> Self {
+        Self {
+            base_speed: level.ball_base_speed,
+            min_speed: level.ball_min_speed,
+            max_speed: level.ball_max_speed,
+            speed_ramp: level.ball_speed_ramp,
+        }
+    }
+    fn speed_for_rally(&self, rally_hits: u32) -> f32 {
+        let speed = self.base_speed + self.speed_ramp * rally_hits as f32;
+        speed.clamp(self.min_speed, self.max_speed)
+    }
+}
+impl Persist for BallConfig {
+    fn write(&self, out: &mut BytesMut) {
+        self.base_speed.write(out);
+        self.min_speed.write(out);
+        self.max_speed.write(out);
+        self.speed_ramp.write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        let result = Self {
+            base_speed: f32::read(buf)?,
+            min_speed: f32::read(buf)?,
+            max_speed: f32::read(buf)?,
+            speed_ramp: f32::read(buf)?,
+        };
+        // NOTE(Fermin): speed_for_rally clamps between these, f32::clamp
+        // panics when they are out of order or nan
+        if !(result.base_speed.is_finite()
+            && result.speed_ramp.is_finite()
+            && result.max_speed.is_finite()
+            && result.min_speed > 0.0
+            && result.min_speed <= result.max_speed)
+        {
+            return Err(invalid_data("invalid ball speeds"));
+        }
+        Ok(result)
+    }
+}
+
+// NOTE(Fermin): The box the outer edges of the paddles move along, in
+// buffer pixels
+#[derive(Copy, Clone)]
+struct Arena {
+    p: V2,
+    width: i32,
+    height: i32,
+}
+impl Arena {
+    fn center(&self) -> V2 {
+        V2 {
+            x: self.p.x + self.width as f32 * 0.5,
+            y: self.p.y + self.height as f32 * 0.5,
+        }
+    }
+
+    // NOTE(Fermin): Pan follows x across the arena and the pitch goes up
+    // towards the top, by POSITION_PITCH_OCTAVES from bottom to top
+    fn stereo_position(&self, entity: &Entity) -> StereoPosition {
+        let center = entity.p + half_size(entity);
+        let x = ((center.x - self.p.x) / self.width.max(1) as f32).clamp(0.0, 1.0);
+        let y = ((center.y - self.p.y) / self.height.max(1) as f32).clamp(0.0, 1.0);
+        StereoPosition {
+            pan: x * 2.0 - 1.0,
+            pitch: ((0.5 - y) * POSITION_PITCH_OCTAVES).exp2(),
+        }
+    }
+}
+impl Persist for Arena {
+    fn write(&self, out: &mut BytesMut) {
+        self.p.write(out);
+        self.width.write(out);
+        self.height.write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        let result = Self {
+            p: V2::read(buf)?,
+            width: i32::read(buf)?,
+            height: i32::read(buf)?,
+        };
+        if !result.p.x.is_finite()
+            || !result.p.y.is_finite()
+            || !(0..=BUFFER_WIDTH).contains(&result.width)
+            || !(0..=BUFFER_HEIGHT).contains(&result.height)
+        {
+            return Err(invalid_data("arena doesn't fit the buffer"));
         }
+        Ok(result)
     }
 }
 
-struct Color {
+#[derive(Copy, Clone)]
+pub struct Color {
     r: i32,
     g: i32,
     b: i32,
@@ -50,6 +768,22 @@ impl Color {
         result
     }
 }
+impl Persist for Color {
+    fn write(&self, out: &mut BytesMut) {
+        self.r.write(out);
+        self.g.write(out);
+        self.b.write(out);
+        self.a.write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        Ok(Self {
+            r: i32::read(buf)?,
+            g: i32::read(buf)?,
+            b: i32::read(buf)?,
+            a: i32::read(buf)?,
+        })
+    }
+}
 
 struct RectForClear {
     p: V2,
@@ -66,6 +800,7 @@ impl RectForClear {
     }
 }
 
+#[derive(Clone)]
 struct Entity {
     p: V2,
     dp: V2,
@@ -85,27 +820,30 @@ impl Entity {
             height,
         }
     }
-    fn handle_entity_collision(&mut self, entity: &mut Entity, h_axis: bool) {
+    // NOTE(Fermin): Returns true if the ball bounced off the entity
+    fn handle_entity_collision(
+        &mut self,
+        entity: &mut Entity,
+        h_axis: bool,
+        rng: &mut GameRng,
+    ) -> bool {
         // NOTE(Fermin): Double check to improve
-        let dir_mod_range: std::ops::Range<f32> = 1.0..30_000.0;
+        let mut hit = false;
         match h_axis {
             true => {
                 if (self.p.y >= entity.p.y && self.p.y <= entity.p.y + entity.height as f32)
                     || (self.p.y + self.height as f32 >= entity.p.y
                         && self.p.y + self.height as f32 <= entity.p.y + entity.height as f32)
                 {
-                    if self.ddp.x > 0.0 {
-                        if self.p.x + self.width as f32 >= entity.p.x {
-                            let y_mod: f32 = get_rand_f32(dir_mod_range);
-                            self.ddp.y += y_mod;
-                            self.ddp.x *= -1.0;
-                        }
-                    } else if self.ddp.x < 0.0 {
-                        if self.p.x <= entity.p.x + entity.width as f32 {
-                            let y_mod: f32 = get_rand_f32(dir_mod_range);
-                            self.ddp.y += y_mod;
-                            self.ddp.x *= -1.0;
-                        }
+                    if self.dp.x > 0.0 {
+                        hit = self.p.x + self.width as f32 >= entity.p.x;
+                    } else if self.dp.x < 0.0 {
+                        hit = self.p.x <= entity.p.x + entity.width as f32;
+                    }
+                    if hit {
+                        self.dp.x *= -1.0;
+                        self.dp.y += get_rand_f32(rng, -BALL_BOUNCE_JITTER..BALL_BOUNCE_JITTER)
+                            * self.dp.length();
                     }
                 }
             }
@@ -114,27 +852,198 @@ impl Entity {
                     || (self.p.x + self.width as f32 >= entity.p.x
                         && self.p.x + self.width as f32 <= entity.p.x + entity.width as f32)
                 {
-                    if self.ddp.y > 0.0 {
-                        if self.p.y + self.height as f32 >= entity.p.y {
-                            let x_mod: f32 = get_rand_f32(dir_mod_range);
-                            self.ddp.x += x_mod;
-                            self.ddp.y *= -1.0;
-                        }
-                    } else if self.ddp.y < 0.0 {
-                        if self.p.y <= entity.p.y + entity.height as f32 {
-                            let x_mod: f32 = get_rand_f32(dir_mod_range);
-                            self.ddp.x += x_mod;
-                            self.ddp.y *= -1.0;
-                        }
+                    if self.dp.y > 0.0 {
+                        hit = self.p.y + self.height as f32 >= entity.p.y;
+                    } else if self.dp.y < 0.0 {
+                        hit = self.p.y <= entity.p.y + entity.height as f32;
+                    }
+                    if hit {
+                        self.dp.y *= -1.0;
+                        self.dp.x += get_rand_f32(rng, -BALL_BOUNCE_JITTER..BALL_BOUNCE_JITTER)
+                            * self.dp.length();
                     }
                 }
             }
         }
+        hit
+    }
+}
+
+impl Persist for Entity {
+    fn write(&self, out: &mut BytesMut) {
+        self.p.write(out);
+        self.dp.write(out);
+        self.ddp.write(out);
+        self.color.write(out);
+        self.width.write(out);
+        self.height.write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        let result = Self {
+            p: V2::read(buf)?,
+            dp: V2::read(buf)?,
+            ddp: V2::read(buf)?,
+            color: Color::read(buf)?,
+            width: i32::read(buf)?,
+            height: i32::read(buf)?,
+        };
+        // NOTE(Fermin): draw_rectangle only keeps entities up to the buffer
+        // size inside of it
+        if !(0..=BUFFER_WIDTH).contains(&result.width)
+            || !(0..=BUFFER_HEIGHT).contains(&result.height)
+        {
+            return Err(invalid_data(&format!(
+                "{}x{} entity doesn't fit the buffer",
+                result.width, result.height
+            )));
+        }
+        Ok(result)
+    }
+}
+
+#[derive(Clone)]
+struct Ball {
+    entity: Entity,
+    // NOTE(Fermin): Paddle index, power-ups the ball goes through are for
+    // its player
+    last_paddle: Option<usize>,
+    stuck: Option<StuckBall>,
+}
+impl Ball {
+    fn new(entity: Entity) -> Self {
+        Self {
+            entity,
+            last_paddle: None,
+            stuck: None,
+        }
+    }
+}
+impl Persist for Ball {
+    fn write(&self, out: &mut BytesMut) {
+        self.entity.write(out);
+        self.last_paddle.is_some().write(out);
+        (self.last_paddle.unwrap_or(0) as u32).write(out);
+        self.stuck.is_some().write(out);
+        if let Some(stuck) = &self.stuck {
+            stuck.write(out);
+        }
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        let entity = Entity::read(buf)?;
+        let has_last_paddle = bool::read(buf)?;
+        let last_paddle = u32::read(buf)? as usize;
+        if last_paddle >= PADDLE_COUNT {
+            return Err(invalid_data(&format!("invalid paddle {}", last_paddle)));
+        }
+        let stuck = if bool::read(buf)? {
+            Some(StuckBall::read(buf)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            entity,
+            last_paddle: has_last_paddle.then_some(last_paddle),
+            stuck,
+        })
+    }
+}
+
+// NOTE(Fermin): Held by a sticky paddle, the ball keeps the velocity it
+// bounced with for when it is let go
+#[derive(Clone)]
+struct StuckBall {
+    paddle: usize,
+    offset: V2,
+    seconds_left: f32,
+}
+impl Persist for StuckBall {
+    fn write(&self, out: &mut BytesMut) {
+        (self.paddle as u32).write(out);
+        self.offset.write(out);
+        self.seconds_left.write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        let paddle = u32::read(buf)? as usize;
+        if paddle >= PADDLE_COUNT {
+            return Err(invalid_data(&format!("invalid paddle {}", paddle)));
+        }
+        Ok(Self {
+            paddle,
+            offset: V2::read(buf)?,
+            seconds_left: f32::read(buf)?,
+        })
+    }
+}
+
+#[derive(Clone)]
+struct Obstacle {
+    kind: ObstacleKind,
+    entity: Entity,
+    hit_points: u32,
+    // NOTE(Fermin): Blocker track, from the start position along the
+    // direction it first moved in
+    start: V2,
+    direction: V2,
+    travel: f32,
+}
+impl Obstacle {
+    // NOTE(Fermin): Specs are relative to the arena origin
+    fn new(spec: &ObstacleSpec, origin: V2) -> Self {
+        let mut entity = Entity::new(spec.width, spec.height, spec.kind.color(spec.hit_points));
+        entity.p = origin + spec.p;
+        if spec.kind == ObstacleKind::Blocker {
+            entity.dp = spec.dp;
+        }
+        let start = entity.p;
+        Self {
+            kind: spec.kind,
+            entity,
+            hit_points: spec.hit_points,
+            start,
+            direction: spec.dp.normalized(),
+            travel: spec.travel,
+        }
+    }
+}
+impl Persist for Obstacle {
+    fn write(&self, out: &mut BytesMut) {
+        self.kind.write(out);
+        self.entity.write(out);
+        self.hit_points.write(out);
+        self.start.write(out);
+        self.direction.write(out);
+        self.travel.write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        Ok(Self {
+            kind: ObstacleKind::read(buf)?,
+            entity: Entity::read(buf)?,
+            hit_points: u32::read(buf)?,
+            start: V2::read(buf)?,
+            direction: V2::read(buf)?,
+            travel: f32::read(buf)?,
+        })
     }
 }
 
-fn get_rand_f32(range: std::ops::Range<f32>) -> f32 {
-    rand::thread_rng().gen_range(range)
+// NOTE(Fermin): Keeps both axes above BALL_MIN_AXIS_FRACTION and gives the
+// direction the requested length
+fn ball_velocity(direction: V2, speed: f32) -> V2 {
+    let mut direction = direction.normalized();
+    let min_axis = BALL_MIN_AXIS_FRACTION;
+    let max_axis = (1.0 - min_axis * min_axis).sqrt();
+    if direction.x.abs() < min_axis {
+        direction.x = min_axis.copysign(direction.x);
+        direction.y = max_axis.copysign(direction.y);
+    } else if direction.y.abs() < min_axis {
+        direction.y = min_axis.copysign(direction.y);
+        direction.x = max_axis.copysign(direction.x);
+    }
+    direction * speed
+}
+
+fn get_rand_f32(rng: &mut GameRng, range: std::ops::Range<f32>) -> f32 {
+    rng.gen_range(range)
 }
 
 fn draw_rectangle(
@@ -178,250 +1087,1724 @@ fn draw_rectangle(
     }
 }
 
-fn pause_for_then<F>(dur: f32, dt_for_frame: f32, then: F)
-where
-    F: FnOnce(),
-{
-    static mut PAUSE_SECONDS_ELAPSED: f32 = 0.0;
-
-    unsafe {
-        if PAUSE_SECONDS_ELAPSED < dur {
-            PAUSE_SECONDS_ELAPSED += dt_for_frame;
-        } else {
-            PAUSE_SECONDS_ELAPSED = 0.0;
-            then();
-        }
-    }
+pub fn text_width(text: &str, scale: i32) -> i32 {
+    text.chars().count() as i32 * (GLYPH_WIDTH + GLYPH_SPACING) * scale
 }
 
-pub fn update_and_render(
-    memory: &mut GameMemory,
+pub fn draw_text(
+    text: &str,
+    pos: &V2,
+    scale: i32,
+    color: &Color,
     buffer: &mut Win32OffscreenBuffer,
-    input: &GameInput,
-    game_state: &mut GameState,
 ) {
-    if let GameStates::DeathScene = game_state.state {
-        pause_for_then(3.0, input.dt_for_frame, || {
-            memory.is_initialized = false;
-            game_state.state = GameStates::Play
-        });
-        return;
-    }
-
-    if !memory.is_initialized {
-        memory.l_entity.p.x = ENTITY_X_PADDING as f32;
-        memory.l_entity.p.y = (buffer.height / 2 - memory.l_entity.height / 2) as f32;
-
-        memory.r_entity.p.x = (buffer.width - ENTITY_X_PADDING - memory.r_entity.width) as f32;
-        memory.r_entity.p.y = (buffer.height / 2 - memory.r_entity.height / 2) as f32;
-
-        memory.b_entity.p.x = (buffer.width / 2 - memory.b_entity.width / 2) as f32;
-        memory.b_entity.p.y = (buffer.height - ENTITY_Y_PADDING - memory.b_entity.height) as f32;
-
-        memory.t_entity.p.x = (buffer.width / 2 - memory.t_entity.width / 2) as f32;
-        memory.t_entity.p.y = ENTITY_Y_PADDING as f32;
-
-        memory.ball.p.x = buffer.width as f32 * 0.5;
-        memory.ball.p.y = buffer.height as f32 * 0.5;
-
-        memory.ball.ddp = V2 {
-            x: get_rand_f32(-50_000.0..50_000.0),
-            y: get_rand_f32(-50_000.0..50_000.0),
-        };
-
-        buffer.bits.clear();
-        for _y in 0..buffer.height {
-            for _x in 0..buffer.width {
-                buffer.bits.put_i32(DARK_BLUE.get_i32());
+    let mut glyph_p = *pos;
+    for c in text.chars() {
+        for (row_index, row) in glyph(c).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if row & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+                    let pixel_p = V2 {
+                        x: glyph_p.x + (column * scale) as f32,
+                        y: glyph_p.y + (row_index as i32 * scale) as f32,
+                    };
+                    draw_rectangle(&pixel_p, scale, scale, color, buffer);
+                }
             }
         }
-
-        memory.is_initialized = true;
+        glyph_p.x += ((GLYPH_WIDTH + GLYPH_SPACING) * scale) as f32;
     }
 
+    // NOTE(Fermin): Through a raw pointer, a reference to the static mut
+    // is undefined behaviour waiting to happen
+    let rectangles = &raw mut RECTANGLES_TO_CLEAR_NEXT_FRAME;
     unsafe {
-        for rectangle in &RECTANGLES_TO_CLEAR_NEXT_FRAME {
-            draw_rectangle(
-                &rectangle.p,
-                rectangle.width,
-                rectangle.height,
-                &DARK_BLUE,
-                buffer,
-            );
-        }
-        RECTANGLES_TO_CLEAR_NEXT_FRAME.clear();
+        (*rectangles).push(RectForClear {
+            p: *pos,
+            width: text_width(text, scale),
+            height: GLYPH_HEIGHT * scale,
+        });
     }
+}
 
-    // TODO(Fermin): Use only two structs instead of 4 and offset the pair???
-    // NOTE(Fermin): Is vector the best type for this entities?
-    let player_speed = 3000.0;
-    let drag = -7.0;
-    let mut ddp = V2 { x: 0.0, y: 0.0 };
-
-    if input.keyboard.buttons.move_up.ended_down {
-        ddp.y = -1.0;
-    }
-    if input.keyboard.buttons.move_down.ended_down {
-        ddp.y = 1.0;
-    }
-    if input.keyboard.buttons.move_left.ended_down {
-        ddp.x = -1.0;
-    }
-    if input.keyboard.buttons.move_right.ended_down {
-        ddp.x = 1.0;
+// NOTE(Fermin): Range the paddle position can take along the axis it moves on
+fn paddle_limits(paddle: &Entity, horizontal: bool, arena: &Arena) -> (f32, f32) {
+    if horizontal {
+        (arena.p.x, arena.p.x + (arena.width - paddle.width) as f32)
+    } else {
+        (arena.p.y, arena.p.y + (arena.height - paddle.height) as f32)
     }
+}
 
-    ddp *= player_speed;
-    ddp.y += drag * memory.l_entity.dp.y;
-    ddp.x += drag * memory.t_entity.dp.x;
-
-    let mut player_delta = V2 {
-        x: (0.5 * ddp.x * input.dt_for_frame.powi(2) + memory.t_entity.dp.x * input.dt_for_frame),
-        y: (0.5 * ddp.y * input.dt_for_frame.powi(2) + memory.r_entity.dp.y * input.dt_for_frame),
+// NOTE(Fermin): accel is the player input along the paddle axis, -1..1
+fn move_paddle(
+    paddle: &mut Entity,
+    accel: f32,
+    horizontal: bool,
+    arena: &Arena,
+    player_speed: f32,
+    dt: f32,
+) {
+    let drag = -7.0;
+    let (min_p, max_p) = paddle_limits(paddle, horizontal, arena);
+    let (p, dp) = if horizontal {
+        (&mut paddle.p.x, &mut paddle.dp.x)
+    } else {
+        (&mut paddle.p.y, &mut paddle.dp.y)
     };
 
-    let mut new_player_p = V2 {
-        x: memory.b_entity.p.x,
-        y: memory.l_entity.p.y,
-    } + player_delta;
+    let ddp = accel * player_speed + drag * *dp;
+    let mut player_delta = 0.5 * ddp * dt.powi(2) + *dp * dt;
+    let mut new_player_p = *p + player_delta;
 
     let delta_reduction_factor = 0.8;
     let collision_iter = 5;
     for _i in 0..collision_iter {
-        if new_player_p.y > ENTITY_Y_PADDING as f32
-            && new_player_p.y < (buffer.height - ENTITY_Y_PADDING - memory.l_entity.height) as f32
-        {
-            memory.r_entity.p.y += player_delta.y;
-            memory.l_entity.p.y = memory.r_entity.p.y;
-            break;
-        } else {
-            player_delta.y *= delta_reduction_factor;
-            new_player_p.y = memory.l_entity.p.y + player_delta.y;
-        }
-    }
-    for _i in 0..collision_iter {
-        if new_player_p.x > ENTITY_X_PADDING as f32
-            && new_player_p.x < (buffer.width - ENTITY_X_PADDING - memory.b_entity.width) as f32
-        {
-            memory.t_entity.p.x += player_delta.x;
-            memory.b_entity.p.x = memory.t_entity.p.x;
+        if new_player_p > min_p && new_player_p < max_p {
+            *p += player_delta;
             break;
         } else {
-            player_delta.x *= delta_reduction_factor;
-            new_player_p.x = memory.b_entity.p.x + player_delta.x;
+            player_delta *= delta_reduction_factor;
+            new_player_p = *p + player_delta;
         }
     }
 
-    let ball_delta =
-        memory.ball.ddp * 0.5 * input.dt_for_frame.powi(2) + memory.ball.dp * input.dt_for_frame;
-    memory.ball.p += ball_delta;
-
-    memory.r_entity.dp.y = ddp.y * input.dt_for_frame + memory.r_entity.dp.y;
-    memory.l_entity.dp.y = memory.r_entity.dp.y;
-
-    memory.t_entity.dp.x = ddp.x * input.dt_for_frame + memory.t_entity.dp.x;
-    memory.b_entity.dp.x = memory.t_entity.dp.x;
+    *dp += ddp * dt;
+}
 
-    memory.ball.dp.y = 1.0 * input.dt_for_frame;
-    memory.ball.dp.x = 1.0 * input.dt_for_frame;
+// NOTE(Fermin): Input along both axes, -1..1. Sticks push the paddles as hard
+// as they are tilted, either stick drives both axes.
+fn paddle_accel(buttons: &InputButtons, controllers: &[ControllerInput]) -> V2 {
+    let mut result = V2 { x: 0.0, y: 0.0 };
 
-    if memory.ball.ddp.x > 0.0 {
-        memory
-            .ball
-            .handle_entity_collision(&mut memory.r_entity, true);
+    if buttons.move_up.is_held() {
+        result.y = -1.0;
+    }
+    if buttons.move_down.is_held() {
+        result.y = 1.0;
     }
-    if memory.ball.ddp.x < 0.0 {
-        memory
-            .ball
-            .handle_entity_collision(&mut memory.l_entity, true);
+    if buttons.move_left.is_held() {
+        result.x = -1.0;
     }
-    if memory.ball.ddp.y > 0.0 {
-        memory
-            .ball
-            .handle_entity_collision(&mut memory.b_entity, false);
+    if buttons.move_right.is_held() {
+        result.x = 1.0;
     }
-    if memory.ball.ddp.y < 0.0 {
-        memory
-            .ball
-            .handle_entity_collision(&mut memory.t_entity, false);
+    for controller in controllers.iter().filter(|c| c.is_connected) {
+        result += controller.left_stick + controller.right_stick;
     }
+    result.x = result.x.clamp(-1.0, 1.0);
+    result.y = result.y.clamp(-1.0, 1.0);
 
-    if memory.ball.ddp.x.abs() < BALL_MIN_DDP {
-        if memory.ball.ddp.x > 0.0 {
-            memory.ball.ddp.x = BALL_MIN_DDP;
-        } else {
-            memory.ball.ddp.x = -BALL_MIN_DDP;
+    result
+}
+
+fn power_up_rng(seed: u64) -> GameRng {
+    GameRng::new(seed ^ 0x5EED_B0B5_5EED_B0B5)
+}
+
+// NOTE(Fermin): Every AI gets its own generator so humans and AIs can be
+// swapped without changing the ball
+fn paddle_ais(seed: u64) -> [PaddleAi; PADDLE_COUNT] {
+    let mut rng = GameRng::new(seed ^ 0xA1A1_A1A1_A1A1_A1A1);
+    [
+        PaddleAi::new(rng.next_u64()),
+        PaddleAi::new(rng.next_u64()),
+        PaddleAi::new(rng.next_u64()),
+        PaddleAi::new(rng.next_u64()),
+    ]
+}
+
+fn half_size(entity: &Entity) -> V2 {
+    V2 {
+        x: entity.width as f32 * 0.5,
+        y: entity.height as f32 * 0.5,
+    }
+}
+
+// NOTE(Fermin): Where the ball center is when its edge meets the inner face
+// of the paddle
+fn paddle_line(memory: &GameMemory, index: usize, ball: &Entity) -> f32 {
+    let half_ball = half_size(ball);
+    let paddle = memory.paddle(index);
+    match index {
+        0 => paddle.p.x + paddle.width as f32 + half_ball.x,
+        1 => paddle.p.x - half_ball.x,
+        2 => paddle.p.y + paddle.height as f32 + half_ball.y,
+        _ => paddle.p.y - half_ball.y,
+    }
+}
+
+// NOTE(Fermin): None if the ball is moving away from the paddle
+fn seconds_to_paddle(memory: &GameMemory, index: usize, ball: &Entity) -> Option<f32> {
+    let center = ball.p + half_size(ball);
+    let line = paddle_line(memory, index, ball);
+    let (distance, speed) = if PADDLE_HORIZONTAL[index] {
+        (line - center.y, ball.dp.y)
+    } else {
+        (line - center.x, ball.dp.x)
+    };
+    if speed == 0.0 || distance * speed <= 0.0 {
+        None
+    } else {
+        Some(distance / speed)
+    }
+}
+
+// NOTE(Fermin): Soonest a ball gets to the paddle, infinity if none is coming
+fn soonest_ball_to_paddle(memory: &GameMemory, index: usize) -> f32 {
+    memory
+        .balls
+        .iter()
+        .filter_map(|ball| seconds_to_paddle(memory, index, &ball.entity))
+        .fold(f32::INFINITY, f32::min)
+}
+
+// NOTE(Fermin): The AI goes for the ball that gets to its paddle first, with
+// none coming it looks at the first one and heads back to the middle
+fn tracked_ball(memory: &GameMemory, index: usize) -> &Entity {
+    memory
+        .balls
+        .iter()
+        .filter_map(|ball| {
+            seconds_to_paddle(memory, index, &ball.entity).map(|seconds| (seconds, &ball.entity))
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map_or(&memory.balls[0].entity, |(_, ball)| ball)
+}
+
+// NOTE(Fermin): The AI works with the ball center
+fn ai_view(memory: &GameMemory, index: usize, dt: f32) -> AiView {
+    let ball = tracked_ball(memory, index);
+    let half_ball = half_size(ball);
+    let paddle = memory.paddle(index);
+    let horizontal = PADDLE_HORIZONTAL[index];
+    let (paddle_center, bounds) = if horizontal {
+        (
+            paddle.p.x + paddle.width as f32 * 0.5,
+            (
+                memory.l_entity.p.x + memory.l_entity.width as f32 + half_ball.x,
+                memory.r_entity.p.x - half_ball.x,
+            ),
+        )
+    } else {
+        (
+            paddle.p.y + paddle.height as f32 * 0.5,
+            (
+                memory.t_entity.p.y + memory.t_entity.height as f32 + half_ball.y,
+                memory.b_entity.p.y - half_ball.y,
+            ),
+        )
+    };
+
+    AiView {
+        horizontal,
+        paddle_center,
+        paddle_line: paddle_line(memory, index, ball),
+        bounds,
+        ball_center: ball.p + half_ball,
+        ball_dp: ball.dp,
+        dt,
+    }
+}
+
+// NOTE(Fermin): Attract mode input, the AI plays solo through a controller
+// so it goes through the same path as a human. Each pair follows the AI of
+// the paddle the next ball is heading to.
+pub fn demo_input(memory: &mut GameMemory, dt: f32) -> GameInput {
+    let difficulty = memory.settings.ai_level.difficulty();
+    let vertical = if soonest_ball_to_paddle(memory, 0) <= soonest_ball_to_paddle(memory, 1) {
+        0
+    } else {
+        1
+    };
+    let horizontal = if soonest_ball_to_paddle(memory, 2) <= soonest_ball_to_paddle(memory, 3) {
+        2
+    } else {
+        3
+    };
+    let vertical_view = ai_view(memory, vertical, dt);
+    let horizontal_view = ai_view(memory, horizontal, dt);
+
+    let mut result = GameInput::new();
+    result.dt_for_frame = dt;
+    result.controllers[0].is_connected = true;
+    result.controllers[0].left_stick = V2 {
+        x: memory.ais[horizontal].update(&horizontal_view, &difficulty),
+        y: memory.ais[vertical].update(&vertical_view, &difficulty),
+    };
+    result
+}
+
+// NOTE(Fermin): Mouse control, the paddle chases the cursor instead of
+// accelerating. Sensitivity scales the cursor distance from the arena center
+// and smoothing is the share of the distance left after 1/60 of a second.
+fn steer_paddle(
+    paddle: &mut Entity,
+    cursor: f32,
+    horizontal: bool,
+    mouse: &MouseSettings,
+    arena: &Arena,
+    dt: f32,
+) {
+    let (min_p, max_p) = paddle_limits(paddle, horizontal, arena);
+    let center = arena.center();
+    let (center, size, p, dp) = if horizontal {
+        (
+            center.x,
+            paddle.width as f32,
+            &mut paddle.p.x,
+            &mut paddle.dp.x,
+        )
+    } else {
+        (
+            center.y,
+            paddle.height as f32,
+            &mut paddle.p.y,
+            &mut paddle.dp.y,
+        )
+    };
+
+    let target = (center + (cursor - center) * mouse.sensitivity - size * 0.5).clamp(min_p, max_p);
+    let follow = 1.0 - mouse.smoothing.powf(dt * 60.0);
+    let new_p = *p + (target - *p) * follow;
+    if dt > 0.0 {
+        *dp = (new_p - *p) / dt;
+    }
+    *p = new_p;
+}
+
+fn clear_dirty_rectangles(buffer: &mut Win32OffscreenBuffer) {
+    let rectangles = &raw mut RECTANGLES_TO_CLEAR_NEXT_FRAME;
+    unsafe {
+        for rectangle in (*rectangles).iter() {
+            draw_rectangle(
+                &rectangle.p,
+                rectangle.width,
+                rectangle.height,
+                &DARK_BLUE,
+                buffer,
+            );
         }
+        (*rectangles).clear();
     }
-    if memory.ball.ddp.y.abs() < BALL_MIN_DDP {
-        if memory.ball.ddp.y > 0.0 {
-            memory.ball.ddp.y = BALL_MIN_DDP;
-        } else {
-            memory.ball.ddp.y = -BALL_MIN_DDP;
+}
+
+// NOTE(Fermin): Text for the platform layer, on the bottom padding band
+pub fn draw_overlay_text(text: &str, buffer: &mut Win32OffscreenBuffer) {
+    let p = V2 {
+        x: 1.0,
+        y: (buffer.height - GLYPH_HEIGHT - 1) as f32,
+    };
+    draw_rectangle(&p, buffer.width - 2, GLYPH_HEIGHT, &DARK_BLUE, buffer);
+    draw_text(text, &p, 1, &NEON_YELLOW, buffer);
+}
+
+fn pause_for_then<F>(dur: f32, dt_for_frame: f32, seconds_elapsed: &mut f32, then: F)
+where
+    F: FnOnce(),
+{
+    if *seconds_elapsed < dur {
+        *seconds_elapsed += dt_for_frame;
+    } else {
+        *seconds_elapsed = 0.0;
+        then();
+    }
+}
+
+// NOTE(Fermin): Repaints the whole background, for when the buffer no longer
+// matches the game memory (first frame, restored snapshots)
+pub fn clear_screen(buffer: &mut Win32OffscreenBuffer) {
+    buffer.bits.clear();
+    for _y in 0..buffer.height {
+        for _x in 0..buffer.width {
+            buffer.bits.put_i32(DARK_BLUE.get_i32());
         }
     }
 
-    draw_rectangle(
-        &memory.l_entity.p,
-        memory.l_entity.width,
-        memory.l_entity.height,
-        &memory.l_entity.color,
-        buffer,
-    );
-    draw_rectangle(
-        &memory.r_entity.p,
-        memory.r_entity.width,
-        memory.r_entity.height,
-        &memory.r_entity.color,
-        buffer,
-    );
-    draw_rectangle(
-        &memory.t_entity.p,
-        memory.t_entity.width,
-        memory.t_entity.height,
-        &memory.t_entity.color,
+    let rectangles = &raw mut RECTANGLES_TO_CLEAR_NEXT_FRAME;
+    unsafe {
+        (*rectangles).clear();
+    }
+}
+
+pub fn update_and_render(
+    memory: &mut GameMemory,
+    buffer: &mut Win32OffscreenBuffer,
+    input: &GameInput,
+    game_state: &mut GameState,
+    bindings: &mut KeyBindings,
+) {
+    if let GameStates::Title(title) = &mut game_state.state {
+        clear_dirty_rectangles(buffer);
+        let mode = memory.settings.mode;
+        if update_and_render_title(
+            title,
+            input,
+            &mut memory.settings,
+            memory.seed,
+            bindings,
+            buffer,
+        ) {
+            memory.play_sound(SoundKind::MenuSelect);
+            memory.is_initialized = false;
+            game_state.state = GameStates::Play;
+        } else if memory.settings.mode != mode {
+            memory.play_sound(SoundKind::MenuMove);
+        }
+        return;
+    }
+
+    if let GameStates::Controls(menu) = &mut game_state.state {
+        clear_dirty_rectangles(buffer);
+        let selected = menu.selected;
+        if update_and_render_controls(menu, input, bindings, &mut memory.settings.mouse, buffer) {
+            memory.play_sound(SoundKind::MenuSelect);
+            clear_screen(buffer);
+            game_state.state = GameStates::Play;
+        } else if menu.selected != selected {
+            memory.play_sound(SoundKind::MenuMove);
+        }
+        return;
+    }
+
+    let buttons = input.combined_buttons();
+    if let GameStates::DeathScene = game_state.state {
+        // NOTE(Fermin): Start skips the rest of the death scene
+        if buttons.start.was_pressed() {
+            game_state.pause_seconds_elapsed = DEATH_SCENE_SECONDS;
+        }
+        pause_for_then(
+            DEATH_SCENE_SECONDS,
+            input.dt_for_frame,
+            &mut game_state.pause_seconds_elapsed,
+            || {
+                // NOTE(Fermin): Every game gets its own seed, derived from the
+                // previous one so a whole session can be reproduced from the first.
+                // Daily challenges replay the same game all day.
+                memory.seed = match memory.settings.daily {
+                    Some(date) => daily_seed(date),
+                    None => GameRng::new(memory.seed).next_u64(),
+                };
+                memory.level_index = 0;
+                memory.is_initialized = false;
+                game_state.state = GameStates::Play
+            },
+        );
+        return;
+    }
+
+    if let GameStates::LevelClear = game_state.state {
+        if buttons.start.was_pressed() {
+            game_state.pause_seconds_elapsed = LEVEL_CLEAR_SECONDS;
+        }
+        pause_for_then(
+            LEVEL_CLEAR_SECONDS,
+            input.dt_for_frame,
+            &mut game_state.pause_seconds_elapsed,
+            || {
+                // NOTE(Fermin): After the last level the sequence starts over
+                // as a new game
+                memory.level_index += 1;
+                if memory.level_index as usize >= memory.settings.levels.len() {
+                    memory.seed = GameRng::new(memory.seed).next_u64();
+                    memory.level_index = 0;
+                }
+                memory.is_initialized = false;
+                game_state.state = GameStates::Play
+            },
+        );
+        return;
+    }
+
+    if !memory.is_initialized {
+        // NOTE(Fermin): The arena is centered on the buffer
+        let level = memory.level().clone();
+        let (width, height) = level.arena_size(buffer.width, buffer.height);
+        let arena = Arena {
+            p: V2 {
+                x: ((buffer.width - width) / 2) as f32,
+                y: ((buffer.height - height) / 2) as f32,
+            },
+            width,
+            height,
+        };
+        memory.arena = arena;
+        memory.ball_config = BallConfig::new(&level);
+
+        // NOTE(Fermin): Knocked out paddles were stretched into walls
+        let mode = memory.settings.mode;
+        let (length, thickness) = (level.paddle_length, level.paddle_thickness);
+        for index in 0..PADDLE_COUNT {
+            let color = PLAYER_COLORS[mode.paddle_player(index)];
+            *memory.paddle_mut(index) = if PADDLE_HORIZONTAL[index] {
+                Entity::new(length, thickness, color)
+            } else {
+                Entity::new(thickness, length, color)
+            };
+        }
+
+        let center = arena.center();
+        let right = arena.p.x + arena.width as f32;
+        let bottom = arena.p.y + arena.height as f32;
+        memory.l_entity.p.x = arena.p.x;
+        memory.l_entity.p.y = center.y - (memory.l_entity.height / 2) as f32;
+
+        memory.r_entity.p.x = right - memory.r_entity.width as f32;
+        memory.r_entity.p.y = center.y - (memory.r_entity.height / 2) as f32;
+
+        memory.b_entity.p.x = center.x - (memory.b_entity.width / 2) as f32;
+        memory.b_entity.p.y = bottom - memory.b_entity.height as f32;
+
+        memory.t_entity.p.x = center.x - (memory.t_entity.width / 2) as f32;
+        memory.t_entity.p.y = arena.p.y;
+
+        // NOTE(Fermin): The score carries over from one level to the next
+        memory.rng = GameRng::new(memory.seed ^ memory.level_index as u64);
+        memory.player_hits = [0; PADDLE_COUNT];
+        memory.balls_lost = 0;
+        if memory.level_index == 0 {
+            memory.points = 0;
+        }
+        memory.level_seconds = 0.0;
+        memory.power_ups.clear();
+        memory.effects.clear();
+        memory.power_up_timer = POWER_UP_SPAWN_SECONDS;
+        memory.power_up_rng = power_up_rng(memory.seed);
+        memory.obstacles = level
+            .obstacles
+            .iter()
+            .map(|spec| Obstacle::new(spec, arena.p))
+            .collect();
+        memory.lives = [LIVES_PER_PLAYER; PADDLE_COUNT];
+        memory.scores = [0; 2];
+        memory.server = 0;
+        memory.ais = paddle_ais(memory.seed);
+        for index in 0..PADDLE_COUNT {
+            if memory.is_wall(index) {
+                make_wall(memory, index, buffer);
+            }
+        }
+        serve_ball(memory);
+
+        clear_screen(buffer);
+
+        memory.is_initialized = true;
+    }
+
+    clear_dirty_rectangles(buffer);
+
+    if buttons.back.was_pressed() {
+        clear_screen(buffer);
+        game_state.state = GameStates::Controls(ControlsMenu::new());
+        return;
+    }
+
+    // TODO(Fermin): Use only two structs instead of 4 and offset the pair???
+    // NOTE(Fermin): Is vector the best type for this entities?
+    let dt = input.dt_for_frame;
+    let arena = memory.arena;
+    let acceleration = memory.level().paddle_acceleration
+        * memory
+            .settings
+            .difficulty
+            .paddle_speed
+            .sample(memory.difficulty_clock());
+    memory.level_seconds += dt;
+    update_power_ups(memory, dt);
+    resize_paddles(memory);
+    match memory.settings.mode {
+        GameMode::Survival
+        | GameMode::Coop
+        | GameMode::Multiball
+        | GameMode::TimeAttack
+        | GameMode::Zen => {
+            let (vertical_accel, horizontal_accel) = if memory.settings.mode != GameMode::Coop {
+                let accel = paddle_accel(&buttons, &input.controllers);
+                (accel.y, accel.x)
+            } else {
+                // NOTE(Fermin): Each player only has their key set and the
+                // controller in their slot
+                (
+                    paddle_accel(&input.player_buttons(0), &input.controllers[0..1]).y,
+                    paddle_accel(&input.player_buttons(1), &input.controllers[1..2]).x,
+                )
+            };
+            // NOTE(Fermin): In co-op the mouse belongs to player one
+            let mouse = &memory.settings.mouse;
+            let cursor = input.cursor_pos;
+            if mouse.enabled {
+                steer_paddle(&mut memory.l_entity, cursor.y, false, mouse, &arena, dt);
+                steer_paddle(&mut memory.r_entity, cursor.y, false, mouse, &arena, dt);
+            } else {
+                move_paddle(
+                    &mut memory.l_entity,
+                    vertical_accel,
+                    false,
+                    &arena,
+                    acceleration,
+                    dt,
+                );
+                move_paddle(
+                    &mut memory.r_entity,
+                    vertical_accel,
+                    false,
+                    &arena,
+                    acceleration,
+                    dt,
+                );
+            }
+            if mouse.enabled && memory.settings.mode != GameMode::Coop {
+                steer_paddle(&mut memory.t_entity, cursor.x, true, mouse, &arena, dt);
+                steer_paddle(&mut memory.b_entity, cursor.x, true, mouse, &arena, dt);
+            } else {
+                move_paddle(
+                    &mut memory.t_entity,
+                    horizontal_accel,
+                    true,
+                    &arena,
+                    acceleration,
+                    dt,
+                );
+                move_paddle(
+                    &mut memory.b_entity,
+                    horizontal_accel,
+                    true,
+                    &arena,
+                    acceleration,
+                    dt,
+                );
+            }
+        }
+        GameMode::FourPlayer | GameMode::Versus => {
+            for (index, horizontal) in PADDLE_HORIZONTAL.into_iter().enumerate() {
+                if memory.is_wall(index) {
+                    continue;
+                }
+                let accel = match memory.settings.paddles[index] {
+                    PaddleControl::Human => {
+                        let accel = paddle_accel(
+                            &input.player_buttons(index),
+                            &input.controllers[index..index + 1],
+                        );
+                        if horizontal {
+                            accel.x
+                        } else {
+                            accel.y
+                        }
+                    }
+                    PaddleControl::Ai => {
+                        let view = ai_view(memory, index, dt);
+                        let difficulty = memory.settings.ai_level.difficulty();
+                        memory.ais[index].update(&view, &difficulty)
+                    }
+                };
+                move_paddle(
+                    memory.paddle_mut(index),
+                    accel,
+                    horizontal,
+                    &arena,
+                    acceleration,
+                    dt,
+                );
+            }
+        }
+    }
+
+    // NOTE(Fermin): Stuck balls ride along with their paddle until the hold
+    // runs out or its player presses jump
+    for ball_index in 0..memory.balls.len() {
+        let Some(stuck) = &memory.balls[ball_index].stuck else {
+            let ball = &mut memory.balls[ball_index].entity;
+            ball.p += ball.dp * dt;
+            continue;
+        };
+        let paddle = stuck.paddle;
+        let release = stuck.seconds_left <= dt || jump_pressed(memory, input, paddle);
+        let paddle_p = memory.paddle(paddle).p;
+        let ball = &mut memory.balls[ball_index];
+        if let Some(stuck) = &mut ball.stuck {
+            ball.entity.p = paddle_p + stuck.offset;
+            stuck.seconds_left -= dt;
+        }
+        if release {
+            ball.stuck = None;
+        }
+    }
+    move_obstacles(&mut memory.obstacles, dt);
+    handle_ball_collisions(&mut memory.balls);
+
+    // NOTE(Fermin): Hits per player, bouncing off a wall is not a hit
+    let mut hits = [0; PADDLE_COUNT];
+    for ball_index in 0..memory.balls.len() {
+        if memory.balls[ball_index].stuck.is_some() {
+            continue;
+        }
+        for index in [1, 0, 3, 2] {
+            let horizontal = PADDLE_HORIZONTAL[index];
+            let is_wall = memory.is_wall(index);
+            let is_sticky = !is_wall && has_effect(&memory.effects, PowerUpKind::Sticky, index);
+            let mut shield = has_effect(&memory.effects, PowerUpKind::Shield, index)
+                .then(|| shield_entity(memory, index));
+            let GameMemory {
+                l_entity,
+                r_entity,
+                t_entity,
+                b_entity,
+                balls,
+                rng,
+                sounds,
+                arena,
+                ..
+            } = memory;
+            let Ball {
+                entity: ball,
+                last_paddle,
+                stuck,
+            } = &mut balls[ball_index];
+            let towards_paddle = match index {
+                0 => ball.dp.x < 0.0,
+                1 => ball.dp.x > 0.0,
+                2 => ball.dp.y < 0.0,
+                _ => ball.dp.y > 0.0,
+            };
+            if !towards_paddle {
+                continue;
+            }
+            let paddle = match index {
+                0 => l_entity,
+                1 => r_entity,
+                2 => t_entity,
+                _ => b_entity,
+            };
+            if ball.handle_entity_collision(paddle, !horizontal, rng) {
+                let kind = if is_wall {
+                    SoundKind::WallHit
+                } else {
+                    hits[memory.settings.mode.paddle_player(index)] += 1;
+                    *last_paddle = Some(index);
+                    SoundKind::PaddleHit
+                };
+                sounds.push(SoundEvent::at(kind, arena.stereo_position(ball)));
+                if is_sticky {
+                    *stuck = Some(StuckBall {
+                        paddle: index,
+                        offset: ball.p - paddle.p,
+                        seconds_left: STICKY_HOLD_SECONDS,
+                    });
+                }
+            } else if let Some(shield) = &mut shield {
+                if ball.handle_entity_collision(shield, !horizontal, rng) {
+                    let position = arena.stereo_position(ball);
+                    sounds.push(SoundEvent::at(SoundKind::WallHit, position));
+                }
+            }
+        }
+    }
+    let bricks_destroyed = handle_obstacle_collisions(memory);
+    if !memory.settings.mode.is_competitive() {
+        memory.points += bricks_destroyed * BRICK_POINTS * score_multiplier(memory);
+    }
+    if hits.iter().any(|player_hits| *player_hits > 0) {
+        memory.rally_hits += 1;
+        memory.points += score_multiplier(memory);
+
+        let multiball = &memory.settings.multiball;
+        if multiball.milestone > 0
+            && memory.rally_hits.is_multiple_of(multiball.milestone)
+            && memory.balls.len() < multiball.max_balls as usize
+        {
+            let ball = launch_ball(memory);
+            memory.balls.push(ball);
+        }
+    }
+    for (player_hits, new_hits) in memory.player_hits.iter_mut().zip(hits) {
+        *player_hits += new_hits;
+    }
+
+    // NOTE(Fermin): Balls from the difficulty curve come in from the middle
+    // like a serve
+    let extra_balls = memory
+        .settings
+        .difficulty
+        .extra_balls
+        .sample(memory.difficulty_clock()) as u32;
+    while memory.difficulty_balls < extra_balls && memory.balls.len() < MAX_BALLS_LIMIT as usize {
+        let ball = launch_ball(memory);
+        memory.balls.push(ball);
+        memory.difficulty_balls += 1;
+    }
+
+    let speed = memory.ball_speed();
+    for ball in memory.balls.iter_mut() {
+        ball.entity.dp = ball_velocity(ball.entity.dp, speed);
+    }
+
+    draw_text(
+        &format!("SEED {}", memory.seed),
+        &V2 { x: 1.0, y: 1.0 },
+        1,
+        &BABY_PINK,
         buffer,
     );
+    let hud_y = 1.0 + ((GLYPH_HEIGHT + 2) as f32);
+    match memory.settings.mode {
+        GameMode::Survival => {
+            let text = format!(
+                "SCORE {}  TIME {}",
+                memory.points, memory.level_seconds as u32
+            );
+            draw_text(&text, &V2 { x: 1.0, y: hud_y }, 1, &NEON_MINT, buffer);
+        }
+        GameMode::TimeAttack => {
+            let seconds_left = (TIME_ATTACK_SECONDS - memory.level_seconds).ceil().max(0.0);
+            let text = format!("SCORE {}  TIME LEFT {}", memory.points, seconds_left);
+            draw_text(&text, &V2 { x: 1.0, y: hud_y }, 1, &NEON_MINT, buffer);
+        }
+        GameMode::Zen => {
+            // NOTE(Fermin): Nothing to lose, only the rally going
+            let text = format!("RALLY {}", memory.rally_hits);
+            draw_text(&text, &V2 { x: 1.0, y: hud_y }, 1, &NEON_MINT, buffer);
+        }
+        GameMode::Multiball => {
+            let multiball = &memory.settings.multiball;
+            let mut text = format!("SCORE {}  BALLS {}", memory.points, memory.balls.len());
+            if multiball.balls_to_lose > 0 {
+                text += &format!("  LOST {}/{}", memory.balls_lost, multiball.balls_to_lose);
+            }
+            draw_text(&text, &V2 { x: 1.0, y: hud_y }, 1, &NEON_MINT, buffer);
+        }
+        GameMode::Coop => {
+            // NOTE(Fermin): The score is shared, hits are per player
+            let score = format!("SCORE {}", memory.points);
+            draw_text(&score, &V2 { x: 1.0, y: hud_y }, 1, &NEON_MINT, buffer);
+            let mut x = 1.0 + (text_width(&score, 1) + 12) as f32;
+            for (player, color) in PLAYER_COLORS.iter().enumerate().take(2) {
+                let text = format!("P{} HITS {}", player + 1, memory.player_hits[player]);
+                draw_text(&text, &V2 { x, y: hud_y }, 1, color, buffer);
+                x += (text_width(&text, 1) + 12) as f32;
+            }
+        }
+        GameMode::FourPlayer => {
+            let mut x = 1.0;
+            for (player, color) in PLAYER_COLORS.iter().enumerate() {
+                let text = match memory.settings.paddles[player] {
+                    PaddleControl::Human => format!("P{} {}", player + 1, memory.lives[player]),
+                    PaddleControl::Ai => format!("P{} AI {}", player + 1, memory.lives[player]),
+                };
+                draw_text(&text, &V2 { x, y: hud_y }, 1, color, buffer);
+                x += (text_width(&text, 1) + 12) as f32;
+            }
+        }
+        GameMode::Versus => {
+            // NOTE(Fermin): Below the top wall, each score on its player's half
+            let scale = 2;
+            let y = memory.arena.p.y + (memory.level().paddle_thickness + 6) as f32;
+            let center = memory.arena.center().x as i32;
+            let left_score = memory.scores[0].to_string();
+            let left_x = center - 16 - text_width(&left_score, scale);
+            draw_text(
+                &left_score,
+                &V2 {
+                    x: left_x as f32,
+                    y,
+                },
+                scale,
+                &PLAYER_COLORS[0],
+                buffer,
+            );
+            draw_text(
+                &memory.scores[1].to_string(),
+                &V2 {
+                    x: (center + 16) as f32,
+                    y,
+                },
+                scale,
+                &PLAYER_COLORS[1],
+                buffer,
+            );
+            let target = format!("FIRST TO {}", memory.settings.target_score);
+            let target_x = center - text_width(&target, 1) / 2;
+            let target_y = y + (GLYPH_HEIGHT * scale + 4) as f32;
+            draw_text(
+                &target,
+                &V2 {
+                    x: target_x as f32,
+                    y: target_y,
+                },
+                1,
+                &NEON_MINT,
+                buffer,
+            );
+        }
+    }
+
+    // NOTE(Fermin): Level name and goal on the right
+    if !memory.settings.mode.is_competitive() {
+        let level = format!("{}  {}", memory.level().name, level_goal(memory));
+        let level = level.trim();
+        if !level.is_empty() {
+            let x = (buffer.width - text_width(level, 1) - 1) as f32;
+            draw_text(level, &V2 { x, y: hud_y }, 1, &NEON_MINT, buffer);
+        }
+    }
+
+    // NOTE(Fermin): Active effects with the seconds they have left, in
+    // competitive modes with the player that got them
+    let mut x = 1.0;
+    for effect in &memory.effects {
+        let seconds = effect.seconds_left.ceil() as u32;
+        let text = match effect.collector {
+            Some(player) => format!("P{} {} {}", player + 1, effect.kind.label(), seconds),
+            None => format!("{} {}", effect.kind.label(), seconds),
+        };
+        let p = V2 {
+            x,
+            y: hud_y + (GLYPH_HEIGHT + 2) as f32,
+        };
+        draw_text(&text, &p, 1, &effect.kind.color(), buffer);
+        x += (text_width(&text, 1) + 12) as f32;
+    }
+
+    for obstacle in &memory.obstacles {
+        let entity = &obstacle.entity;
+        draw_rectangle(
+            &entity.p,
+            entity.width,
+            entity.height,
+            &entity.color,
+            buffer,
+        );
+    }
+    let mut shields = Vec::new();
+    for index in 0..PADDLE_COUNT {
+        if has_effect(&memory.effects, PowerUpKind::Shield, index) {
+            let shield = shield_entity(memory, index);
+            draw_rectangle(
+                &shield.p,
+                shield.width,
+                shield.height,
+                &shield.color,
+                buffer,
+            );
+            shields.push(shield);
+        }
+    }
+    for index in 0..PADDLE_COUNT {
+        let paddle = memory.paddle(index);
+        draw_rectangle(
+            &paddle.p,
+            paddle.width,
+            paddle.height,
+            &paddle.color,
+            buffer,
+        );
+    }
+    for power_up in &memory.power_ups {
+        let color = power_up.kind.color();
+        draw_rectangle(&power_up.p, POWER_UP_SIZE, POWER_UP_SIZE, &color, buffer);
+        let letter_p = power_up.p
+            + V2 {
+                x: ((POWER_UP_SIZE - GLYPH_WIDTH) / 2) as f32,
+                y: ((POWER_UP_SIZE - GLYPH_HEIGHT) / 2) as f32,
+            };
+        draw_text(power_up.kind.letter(), &letter_p, 1, &DARK_BLUE, buffer);
+    }
+    for ball in &memory.balls {
+        let ball = &ball.entity;
+        draw_rectangle(&ball.p, ball.width, ball.height, &ball.color, buffer);
+    }
+
+    let rectangles = &raw mut RECTANGLES_TO_CLEAR_NEXT_FRAME;
+    unsafe {
+        (*rectangles).push(RectForClear::new_from_entity(&memory.l_entity));
+        (*rectangles).push(RectForClear::new_from_entity(&memory.r_entity));
+        (*rectangles).push(RectForClear::new_from_entity(&memory.t_entity));
+        (*rectangles).push(RectForClear::new_from_entity(&memory.b_entity));
+        let power_up_rects = memory.power_ups.iter().map(|power_up| RectForClear {
+            p: power_up.p,
+            width: POWER_UP_SIZE,
+            height: POWER_UP_SIZE,
+        });
+        (*rectangles).extend(
+            shields
+                .iter()
+                .chain(memory.obstacles.iter().map(|obstacle| &obstacle.entity))
+                .chain(memory.balls.iter().map(|ball| &ball.entity))
+                .map(RectForClear::new_from_entity)
+                .chain(power_up_rects),
+        );
+    }
+
+    // NOTE(Fermin): Newest first, removing a ball doesn't move the ones
+    // still to check
+    let exits: Vec<(usize, usize)> = memory
+        .balls
+        .iter()
+        .enumerate()
+        .filter_map(|(ball_index, ball)| {
+            ball_exit_side(memory, &ball.entity).map(|side| (ball_index, side))
+        })
+        .collect();
+    let rules = memory.settings.mode.rules();
+    for (ball_index, side) in exits.into_iter().rev() {
+        match rules.ball_out {
+            BallOut::Lost => {
+                memory.balls_lost += 1;
+                let balls_to_lose = memory.settings.multiball.balls_to_lose;
+                if memory.balls.len() == 1
+                    || (balls_to_lose > 0 && memory.balls_lost >= balls_to_lose)
+                {
+                    // TODO(Fermin): Death animation (Fill screen with red, slowly??)
+                    let score = format!("SCORE {}", memory.points);
+                    let mut best = String::new();
+                    if let Some(date) = memory.settings.daily {
+                        let best_score = memory
+                            .daily_best
+                            .map_or(memory.points, |daily_best| daily_best.max(memory.points));
+                        memory.daily_best = Some(best_score);
+                        memory.daily_result = Some((date, memory.points));
+                        best = format!("BEST {}", best_score);
+                    }
+                    draw_end_screen(&RED, &[("GAME OVER", 3), (&score, 2), (&best, 1)], buffer);
+                    memory.play_sound(SoundKind::Death);
+
+                    game_state.state = GameStates::DeathScene;
+                    return;
+                }
+                memory.play_ball_sound(SoundKind::BallLost, ball_index);
+                memory.balls.remove(ball_index);
+            }
+            BallOut::Served => {
+                memory.balls_lost += 1;
+                lose_ball(memory, ball_index);
+            }
+            BallOut::Reflected => {
+                reflect_ball(memory, ball_index, side);
+                memory.play_ball_sound(SoundKind::WallHit, ball_index);
+            }
+            BallOut::Life => {
+                // NOTE(Fermin): Whoever defends the side the ball left through
+                // loses a life and the last one standing wins
+                if memory.lives[side] > 0 {
+                    memory.lives[side] -= 1;
+                    if memory.lives[side] == 0 {
+                        make_wall(memory, side, buffer);
+                    }
+                }
+                let players_left: Vec<usize> = (0..PADDLE_COUNT)
+                    .filter(|index| !memory.is_wall(*index))
+                    .collect();
+                if let [winner] = players_left[..] {
+                    draw_winner(winner, buffer);
+                    memory.play_sound(SoundKind::LevelClear);
+                    game_state.state = GameStates::DeathScene;
+                    return;
+                }
+                lose_ball(memory, ball_index);
+            }
+            BallOut::Point => {
+                // NOTE(Fermin): Getting past a paddle is a point for the other
+                // player, the walls only let the ball out if it tunnels through
+                if side < 2 {
+                    let scorer = 1 - side;
+                    memory.scores[scorer] +=
+                        if has_effect(&memory.effects, PowerUpKind::Multiplier, scorer) {
+                            2
+                        } else {
+                            1
+                        };
+                    if memory.scores[scorer] >= memory.settings.target_score {
+                        draw_winner(scorer, buffer);
+                        memory.play_sound(SoundKind::LevelClear);
+                        game_state.state = GameStates::DeathScene;
+                        return;
+                    }
+                }
+                lose_ball(memory, ball_index);
+            }
+        }
+    }
+
+    if let Some(seconds) = rules.time_limit {
+        if memory.level_seconds >= seconds {
+            let score = format!("SCORE {}", memory.points);
+            draw_end_screen(&ORANGE, &[("TIME UP", 3), (&score, 2)], buffer);
+            memory.play_sound(SoundKind::LevelClear);
+            game_state.state = GameStates::DeathScene;
+            return;
+        }
+    }
+
+    // NOTE(Fermin): Versus and four player games are won by the players, the
+    // level goal doesn't apply
+    if !memory.settings.mode.is_competitive() && level_cleared(memory) {
+        let name = memory.level().name.clone();
+        draw_end_screen(&NEON_MINT, &[("LEVEL CLEAR", 3), (&name, 2)], buffer);
+        memory.play_sound(SoundKind::LevelClear);
+        game_state.state = GameStates::LevelClear;
+    }
+}
+
+fn level_cleared(memory: &GameMemory) -> bool {
+    match memory.level().win {
+        WinCondition::Endless => false,
+        WinCondition::Points(points) => memory.points >= points,
+        WinCondition::Bricks => !memory
+            .obstacles
+            .iter()
+            .any(|obstacle| obstacle.kind == ObstacleKind::Brick),
+        WinCondition::Survive(seconds) => memory.level_seconds >= seconds,
+    }
+}
+
+// NOTE(Fermin): What is left of the level goal, empty for endless levels
+fn level_goal(memory: &GameMemory) -> String {
+    match memory.level().win {
+        WinCondition::Endless => String::new(),
+        WinCondition::Points(points) => format!("GOAL {}", points),
+        WinCondition::Bricks => {
+            let bricks = memory
+                .obstacles
+                .iter()
+                .filter(|obstacle| obstacle.kind == ObstacleKind::Brick)
+                .count();
+            format!("BRICKS LEFT {}", bricks)
+        }
+        WinCondition::Survive(seconds) => {
+            format!(
+                "SURVIVE {}",
+                (seconds - memory.level_seconds).ceil().max(0.0)
+            )
+        }
+    }
+}
+
+// NOTE(Fermin): Fills the screen and centers the lines, each with its own
+// scale, starting a bit above the middle
+fn draw_end_screen(color: &Color, lines: &[(&str, i32)], buffer: &mut Win32OffscreenBuffer) {
     draw_rectangle(
-        &memory.b_entity.p,
-        memory.b_entity.width,
-        memory.b_entity.height,
-        &memory.b_entity.color,
+        &V2 { x: 0.0, y: 0.0 },
+        buffer.width,
+        buffer.height,
+        color,
         buffer,
     );
+    let mut y = (buffer.height - GLYPH_HEIGHT * lines.first().map_or(1, |line| line.1)) / 2;
+    for (text, scale) in lines {
+        let p = V2 {
+            x: ((buffer.width - text_width(text, *scale)) / 2) as f32,
+            y: y as f32,
+        };
+        draw_text(text, &p, *scale, &DARK_BLUE, buffer);
+        y += GLYPH_HEIGHT * scale + 6;
+    }
+}
+
+// NOTE(Fermin): Balls weigh the same, on the axis they overlap the least
+// they swap velocities and get pushed apart
+fn handle_ball_collisions(balls: &mut [Ball]) {
+    for first in 0..balls.len() {
+        let (head, tail) = balls.split_at_mut(first + 1);
+        if head[first].stuck.is_some() {
+            continue;
+        }
+        let a = &mut head[first].entity;
+        for b in tail.iter_mut().filter(|b| b.stuck.is_none()) {
+            let b = &mut b.entity;
+            let overlap = overlap(a, b);
+            if overlap.x <= 0.0 || overlap.y <= 0.0 {
+                continue;
+            }
+            let (a_p, b_p, a_dp, b_dp, overlap) = if overlap.x < overlap.y {
+                (&mut a.p.x, &mut b.p.x, &mut a.dp.x, &mut b.dp.x, overlap.x)
+            } else {
+                (&mut a.p.y, &mut b.p.y, &mut a.dp.y, &mut b.dp.y, overlap.y)
+            };
+            let push = if *a_p < *b_p { -0.5 } else { 0.5 } * overlap;
+            let approaching = (*b_dp - *a_dp) * (*b_p - *a_p) < 0.0;
+            *a_p += push;
+            *b_p -= push;
+            if approaching {
+                std::mem::swap(a_dp, b_dp);
+            }
+        }
+    }
+}
+
+// NOTE(Fermin): How far the two rectangles go into each other on each axis,
+// they only touch if both are positive
+fn overlap(a: &Entity, b: &Entity) -> V2 {
+    V2 {
+        x: (a.p.x + a.width as f32).min(b.p.x + b.width as f32) - a.p.x.max(b.p.x),
+        y: (a.p.y + a.height as f32).min(b.p.y + b.height as f32) - a.p.y.max(b.p.y),
+    }
+}
+
+// NOTE(Fermin): Blockers turn around at either end of their track
+fn move_obstacles(obstacles: &mut [Obstacle], dt: f32) {
+    for obstacle in obstacles
+        .iter_mut()
+        .filter(|obstacle| obstacle.kind == ObstacleKind::Blocker)
+    {
+        let entity = &mut obstacle.entity;
+        entity.p += entity.dp * dt;
+        let along = (entity.p - obstacle.start).dot(obstacle.direction);
+        let forwards = entity.dp.dot(obstacle.direction) > 0.0;
+        if (forwards && along >= obstacle.travel) || (!forwards && along <= 0.0) {
+            entity.dp *= -1.0;
+        }
+    }
+}
+
+// NOTE(Fermin): Obstacles sit in the open, so the ball bounces on the axis it
+// overlaps the least and only while it is moving into the obstacle. The
+// bounce itself is the paddle one. Returns the bricks destroyed.
+fn handle_obstacle_collisions(memory: &mut GameMemory) -> u32 {
+    let mut destroyed = 0;
+    let GameMemory {
+        balls,
+        obstacles,
+        rng,
+        sounds,
+        arena,
+        ..
+    } = memory;
+    for ball in balls.iter_mut().filter(|ball| ball.stuck.is_none()) {
+        let ball = &mut ball.entity;
+        for obstacle in obstacles.iter_mut() {
+            let overlap = overlap(ball, &obstacle.entity);
+            if overlap.x <= 0.0 || overlap.y <= 0.0 {
+                continue;
+            }
+            let h_axis = overlap.x < overlap.y;
+            let to_obstacle =
+                obstacle.entity.p + half_size(&obstacle.entity) - (ball.p + half_size(ball));
+            let closing = ball.dp - obstacle.entity.dp;
+            let approaching = if h_axis {
+                to_obstacle.x * closing.x > 0.0
+            } else {
+                to_obstacle.y * closing.y > 0.0
+            };
+            if !approaching || !ball.handle_entity_collision(&mut obstacle.entity, h_axis, rng) {
+                continue;
+            }
+            let mut sound = SoundKind::WallHit;
+            if obstacle.kind == ObstacleKind::Brick {
+                obstacle.hit_points = obstacle.hit_points.saturating_sub(1);
+                obstacle.entity.color = obstacle.kind.color(obstacle.hit_points);
+                if obstacle.hit_points == 0 {
+                    destroyed += 1;
+                    sound = SoundKind::BrickBreak;
+                }
+            }
+            sounds.push(SoundEvent::at(sound, arena.stereo_position(ball)));
+        }
+    }
+    obstacles.retain(|obstacle| obstacle.kind != ObstacleKind::Brick || obstacle.hit_points > 0);
+    destroyed
+}
+
+fn score_multiplier(memory: &GameMemory) -> u32 {
+    if memory
+        .effects
+        .iter()
+        .any(|effect| effect.kind == PowerUpKind::Multiplier)
+    {
+        2
+    } else {
+        1
+    }
+}
+
+// NOTE(Fermin): Gone from the field, the last ball comes back as a new serve
+fn lose_ball(memory: &mut GameMemory, ball_index: usize) {
+    memory.play_ball_sound(SoundKind::BallLost, ball_index);
+    if memory.balls.len() == 1 {
+        serve_ball(memory);
+    } else {
+        memory.balls.remove(ball_index);
+    }
+}
+
+fn draw_winner(player: usize, buffer: &mut Win32OffscreenBuffer) {
     draw_rectangle(
-        &memory.ball.p,
-        memory.ball.width,
-        memory.ball.height,
-        &memory.ball.color,
+        &V2 { x: 0.0, y: 0.0 },
+        buffer.width,
+        buffer.height,
+        &PLAYER_COLORS[player],
         buffer,
     );
+    let text = format!("P{} WINS", player + 1);
+    let scale = 3;
+    let p = V2 {
+        x: ((buffer.width - text_width(&text, scale)) / 2) as f32,
+        y: ((buffer.height - GLYPH_HEIGHT * scale) / 2) as f32,
+    };
+    draw_text(&text, &p, scale, &DARK_BLUE, buffer);
+}
 
-    unsafe {
-        RECTANGLES_TO_CLEAR_NEXT_FRAME.push(RectForClear::new_from_entity(&memory.l_entity));
-        RECTANGLES_TO_CLEAR_NEXT_FRAME.push(RectForClear::new_from_entity(&memory.r_entity));
-        RECTANGLES_TO_CLEAR_NEXT_FRAME.push(RectForClear::new_from_entity(&memory.t_entity));
-        RECTANGLES_TO_CLEAR_NEXT_FRAME.push(RectForClear::new_from_entity(&memory.b_entity));
-        RECTANGLES_TO_CLEAR_NEXT_FRAME.push(RectForClear::new_from_entity(&memory.ball));
-    }
-
-    // NOTE(Fermin): Check if ball is out of bounds
-    if memory.ball.p.x < memory.l_entity.p.x
-        || memory.ball.p.x + memory.ball.width as f32
-            > memory.r_entity.p.x + memory.r_entity.width as f32
-        || memory.ball.p.y < memory.t_entity.p.y
-        || memory.ball.p.y + memory.ball.height as f32
-            > memory.b_entity.p.y + memory.b_entity.height as f32
+// NOTE(Fermin): New rally, the multiball mode serves several balls at once
+fn serve_ball(memory: &mut GameMemory) {
+    memory.rally_hits = 0;
+    memory.difficulty_balls = 0;
+    memory.balls.clear();
+    let ball_count = match memory.settings.mode {
+        GameMode::Multiball => memory.settings.multiball.start_balls.max(1),
+        _ => 1,
+    };
+    for index in 0..ball_count {
+        let mut ball = launch_ball(memory);
+        // NOTE(Fermin): Levels can place the first ball, the rest still go
+        // from the middle
+        if let (0, Some((p, direction))) = (index, memory.level().serve) {
+            ball.entity.p = memory.arena.p + p;
+            ball.entity.dp = ball_velocity(direction, ball.entity.dp.length());
+        }
+        // NOTE(Fermin): Versus serves alternate, the ball goes away from the
+        // server
+        if memory.settings.mode == GameMode::Versus {
+            let dp = &mut ball.entity.dp;
+            dp.x = if memory.server == 0 {
+                dp.x.abs()
+            } else {
+                -dp.x.abs()
+            };
+        }
+        memory.balls.push(ball);
+    }
+    if memory.settings.mode == GameMode::Versus {
+        memory.server = 1 - memory.server;
+    }
+}
+
+// NOTE(Fermin): Ball in the middle of the arena, towards a random corner
+fn launch_ball(memory: &mut GameMemory) -> Ball {
+    let color = BALL_COLORS[memory.balls.len() % BALL_COLORS.len()];
+    let size = memory.level().ball_size;
+    let mut ball = Entity::new(size, size, color);
+    ball.p = memory.arena.center();
+
+    let serve_angle = get_rand_f32(&mut memory.rng, 0.0..4.0).floor() * std::f32::consts::FRAC_PI_2
+        + std::f32::consts::FRAC_PI_4
+        + get_rand_f32(&mut memory.rng, -0.25..0.25);
+    let direction = V2 {
+        x: serve_angle.cos(),
+        y: serve_angle.sin(),
+    };
+    ball.dp = ball_velocity(direction, memory.ball_speed());
+    Ball::new(ball)
+}
+
+// NOTE(Fermin): Back on the paddle line of the side it went out through,
+// heading in again. Counts as a miss, the rally starts over.
+fn reflect_ball(memory: &mut GameMemory, ball_index: usize, side: usize) {
+    let paddle = memory.paddle(side).clone();
+    let ball = &mut memory.balls[ball_index].entity;
+    match side {
+        0 => {
+            ball.p.x = paddle.p.x;
+            ball.dp.x = ball.dp.x.abs();
+        }
+        1 => {
+            ball.p.x = paddle.p.x + (paddle.width - ball.width) as f32;
+            ball.dp.x = -ball.dp.x.abs();
+        }
+        2 => {
+            ball.p.y = paddle.p.y;
+            ball.dp.y = ball.dp.y.abs();
+        }
+        _ => {
+            ball.p.y = paddle.p.y + (paddle.height - ball.height) as f32;
+            ball.dp.y = -ball.dp.y.abs();
+        }
+    }
+    memory.rally_hits = 0;
+}
+
+// NOTE(Fermin): Paddle index of the side the ball went out through
+fn ball_exit_side(memory: &GameMemory, ball: &Entity) -> Option<usize> {
+    if ball.p.x < memory.l_entity.p.x {
+        Some(0)
+    } else if ball.p.x + ball.width as f32 > memory.r_entity.p.x + memory.r_entity.width as f32 {
+        Some(1)
+    } else if ball.p.y < memory.t_entity.p.y {
+        Some(2)
+    } else if ball.p.y + ball.height as f32 > memory.b_entity.p.y + memory.b_entity.height as f32 {
+        Some(3)
+    } else {
+        None
+    }
+}
+
+// NOTE(Fermin): The paddle stretches over its whole side and stays as a wall
+fn make_wall(memory: &mut GameMemory, index: usize, buffer: &mut Win32OffscreenBuffer) {
+    let arena = memory.arena;
+    let paddle = memory.paddle_mut(index);
+    draw_rectangle(&paddle.p, paddle.width, paddle.height, &DARK_BLUE, buffer);
+    if PADDLE_HORIZONTAL[index] {
+        paddle.p.x = arena.p.x;
+        paddle.width = arena.width;
+    } else {
+        paddle.p.y = arena.p.y;
+        paddle.height = arena.height;
+    }
+    paddle.dp = V2 { x: 0.0, y: 0.0 };
+    paddle.color = WALL_GREY;
+}
+
+fn jump_pressed(memory: &GameMemory, input: &GameInput, paddle: usize) -> bool {
+    let mode = memory.settings.mode;
+    match mode {
+        GameMode::Survival | GameMode::Multiball | GameMode::TimeAttack | GameMode::Zen => {
+            input.combined_buttons().jump.was_pressed()
+        }
+        GameMode::Coop => input
+            .player_buttons(mode.paddle_player(paddle))
+            .jump
+            .was_pressed(),
+        GameMode::FourPlayer | GameMode::Versus => {
+            memory.settings.paddles[paddle] == PaddleControl::Human
+                && input.player_buttons(paddle).jump.was_pressed()
+        }
+    }
+}
+
+// NOTE(Fermin): A wall along the whole side, on the same line as the paddle
+// so the ball bounces before it counts as out
+fn shield_entity(memory: &GameMemory, index: usize) -> Entity {
+    let arena = &memory.arena;
+    let paddle = memory.paddle(index);
+    let mut result = Entity::new(paddle.width, paddle.height, LILAC);
+    result.p = paddle.p;
+    if PADDLE_HORIZONTAL[index] {
+        result.p.x = arena.p.x;
+        result.width = arena.width;
+    } else {
+        result.p.y = arena.p.y;
+        result.height = arena.height;
+    }
+    result
+}
+
+// NOTE(Fermin): Paddle lengths come from the active effects every frame, so
+// an effect running out puts the paddle back on its own. The paddle keeps
+// its center.
+fn resize_paddles(memory: &mut GameMemory) {
+    let arena = memory.arena;
+    let paddle_length = memory.level().paddle_length as f32
+        * memory
+            .settings
+            .difficulty
+            .paddle_length
+            .sample(memory.difficulty_clock());
+    for (index, horizontal) in PADDLE_HORIZONTAL.into_iter().enumerate() {
+        if memory.is_wall(index) {
+            continue;
+        }
+        // NOTE(Fermin): Curves and power-ups stack, the paddle still has to
+        // fit the arena or it has nowhere to move
+        let extent = if horizontal {
+            arena.width
+        } else {
+            arena.height
+        };
+        let length = ((paddle_length * paddle_scale(&memory.effects, index)).round() as i32)
+            .clamp(1, extent.max(1));
+        let paddle = memory.paddle_mut(index);
+        let (p, size) = if horizontal {
+            (&mut paddle.p.x, &mut paddle.width)
+        } else {
+            (&mut paddle.p.y, &mut paddle.height)
+        };
+        if *size == length {
+            continue;
+        }
+        *p += (*size - length) as f32 * 0.5;
+        *size = length;
+        let (min_p, max_p) = paddle_limits(paddle, horizontal, &arena);
+        let p = if horizontal {
+            &mut paddle.p.x
+        } else {
+            &mut paddle.p.y
+        };
+        *p = p.clamp(min_p, max_p);
+    }
+}
+
+// NOTE(Fermin): Expiry, spawns and pickups. Effects only change values here,
+// the rest of the frame reads them.
+fn update_power_ups(memory: &mut GameMemory, dt: f32) {
+    for effect in memory.effects.iter_mut() {
+        effect.seconds_left -= dt;
+    }
+    memory.effects.retain(|effect| effect.seconds_left > 0.0);
+    if !memory.settings.power_ups {
+        return;
+    }
+    for power_up in memory.power_ups.iter_mut() {
+        power_up.seconds_left -= dt;
+    }
+    memory
+        .power_ups
+        .retain(|power_up| power_up.seconds_left > 0.0);
+
+    memory.power_up_timer -= dt;
+    if memory.power_up_timer <= 0.0 {
+        memory.power_up_timer = POWER_UP_SPAWN_SECONDS;
+        if memory.power_ups.len() < MAX_POWER_UPS_ON_FIELD {
+            // NOTE(Fermin): Four player games have no score to multiply
+            let kinds: Vec<PowerUpKind> = POWER_UP_KINDS
+                .into_iter()
+                .filter(|kind| {
+                    *kind != PowerUpKind::Multiplier || memory.settings.mode != GameMode::FourPlayer
+                })
+                .collect();
+            let arena = &memory.arena;
+            let rng = &mut memory.power_up_rng;
+            let kind = kinds[rng.gen_range(0..kinds.len())];
+            // NOTE(Fermin): Small arenas get a smaller margin so there is
+            // always somewhere to spawn
+            let mut spawn_range = |size: i32| {
+                let room = (size - POWER_UP_SIZE) as f32;
+                let margin = POWER_UP_MARGIN.min(room * 0.25);
+                rng.gen_range(margin..room - margin)
+            };
+            let p = arena.p
+                + V2 {
+                    x: spawn_range(arena.width),
+                    y: spawn_range(arena.height),
+                };
+            memory.power_ups.push(PowerUp {
+                kind,
+                p,
+                seconds_left: POWER_UP_LIFETIME,
+            });
+        }
+    }
+
+    // NOTE(Fermin): In competitive modes a ball nobody has hit yet goes
+    // through without picking anything up
+    let competitive = memory.settings.mode.is_competitive();
+    let mut collected = Vec::new();
+    let balls = &memory.balls;
+    memory.power_ups.retain(|power_up| {
+        let collector = balls.iter().find(|ball| {
+            let ball_entity = &ball.entity;
+            (!competitive || ball.last_paddle.is_some())
+                && ball_entity.p.x < power_up.p.x + POWER_UP_SIZE as f32
+                && power_up.p.x < ball_entity.p.x + ball_entity.width as f32
+                && ball_entity.p.y < power_up.p.y + POWER_UP_SIZE as f32
+                && power_up.p.y < ball_entity.p.y + ball_entity.height as f32
+        });
+        match collector {
+            Some(ball) => {
+                collected.push((power_up.kind, ball.last_paddle));
+                false
+            }
+            None => true,
+        }
+    });
+    for (kind, last_paddle) in collected {
+        collect_power_up(memory, kind, last_paddle);
+    }
+}
+
+// NOTE(Fermin): In competitive modes the effect is for the player that hit
+// the ball last, shrink goes to everyone else. Otherwise it is for every
+// paddle and shields go up on a random side.
+fn collect_power_up(memory: &mut GameMemory, kind: PowerUpKind, last_paddle: Option<usize>) {
+    memory.play_sound(SoundKind::PowerUp);
+    let (paddles, collector) = match last_paddle {
+        Some(paddle) if memory.settings.mode.is_competitive() => {
+            let own = 1 << paddle;
+            match kind {
+                PowerUpKind::Shrink => (ALL_PADDLES & !own, Some(paddle)),
+                _ => (own, Some(paddle)),
+            }
+        }
+        _ => match kind {
+            PowerUpKind::Shield => (1 << memory.power_up_rng.gen_range(0..PADDLE_COUNT), None),
+            _ => (ALL_PADDLES, None),
+        },
+    };
+
+    // NOTE(Fermin): Picking up the same effect again only restarts it
+    if let Some(effect) = memory
+        .effects
+        .iter_mut()
+        .find(|effect| effect.kind == kind && effect.paddles == paddles)
     {
-        // TODO(Fermin): Death animation (Fill screen with red, slowly??)
-        draw_rectangle(
-            &V2 { x: 0.0, y: 0.0 },
-            buffer.width,
-            buffer.height,
-            &RED,
-            buffer,
+        effect.seconds_left = kind.duration();
+    } else {
+        memory.effects.push(ActiveEffect {
+            kind,
+            paddles,
+            collector,
+            seconds_left: kind.duration(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // NOTE(Fermin): Stands in for a difficulty curve or power-ups scaling the
+    // paddles past the arena
+    #[test]
+    fn paddles_never_outgrow_the_arena() {
+        let mut settings = GameSettings::new();
+        settings.levels[0].paddle_length = 1000;
+        let mut memory = GameMemory::new(7, settings);
+        let mut game_state = GameState {
+            state: GameStates::Play,
+            pause_seconds_elapsed: 0.0,
+        };
+        let mut buffer = Win32OffscreenBuffer::new(400, 400).unwrap();
+        let mut bindings = KeyBindings::new();
+        let mut input = GameInput::new();
+        input.dt_for_frame = 1.0 / 60.0;
+        for _ in 0..30 {
+            input.prepare_for_new_frame();
+            input.keyboard.buttons.move_down.ended_down = true;
+            update_and_render(
+                &mut memory,
+                &mut buffer,
+                &input,
+                &mut game_state,
+                &mut bindings,
+            );
+        }
+        let arena = memory.arena;
+        for (index, horizontal) in PADDLE_HORIZONTAL.into_iter().enumerate() {
+            let paddle = memory.paddle(index);
+            if horizontal {
+                assert!(paddle.width <= arena.width);
+            } else {
+                assert!(paddle.height <= arena.height);
+            }
+        }
+    }
+
+    #[test]
+    fn daily_game_over_reports_the_result() {
+        let date = Date::parse("2024-02-29").unwrap();
+        let mut memory =
+            GameMemory::new(daily_seed(date), daily_settings(date, MouseSettings::new()));
+        memory.set_daily_best(Some(u32::MAX));
+        let mut game_state = GameState {
+            state: GameStates::Play,
+            pause_seconds_elapsed: 0.0,
+        };
+        let mut buffer = Win32OffscreenBuffer::new(400, 400).unwrap();
+        let mut bindings = KeyBindings::new();
+        let mut input = GameInput::new();
+        input.dt_for_frame = 1.0 / 60.0;
+        let mut result = None;
+        for _ in 0..60 * 600 {
+            input.prepare_for_new_frame();
+            update_and_render(
+                &mut memory,
+                &mut buffer,
+                &input,
+                &mut game_state,
+                &mut bindings,
+            );
+            result = memory.take_daily_result();
+            if result.is_some() {
+                break;
+            }
+        }
+        let (result_date, score) = result.expect("Game ends without input");
+        assert!(result_date == date);
+        assert_eq!(score, memory.points);
+        assert_eq!(memory.daily_best, Some(u32::MAX));
+        assert!(memory.take_daily_result().is_none());
+    }
+
+    fn read_back<T: Persist>(value: &T) -> Result<T> {
+        let mut out = BytesMut::new();
+        value.write(&mut out);
+        T::read(&mut &out[..])
+    }
+
+    #[test]
+    fn broken_settings_are_read_errors() {
+        let level = Level::new();
+        let config = BallConfig::new(&level);
+        assert!(read_back(&config).is_ok());
+        for (min_speed, max_speed) in [(900.0, 300.0), (f32::NAN, 900.0), (300.0, f32::NAN)] {
+            let config = BallConfig {
+                min_speed,
+                max_speed,
+                ..config.clone()
+            };
+            assert!(read_back(&config).is_err());
+        }
+
+        let multiball = MultiballSettings::new();
+        assert!(read_back(&multiball).is_ok());
+        for start_balls in [0, MAX_BALLS_LIMIT + 1, u32::MAX] {
+            let multiball = MultiballSettings {
+                start_balls,
+                ..multiball.clone()
+            };
+            assert!(read_back(&multiball).is_err());
+        }
+
+        let mouse = MouseSettings::new();
+        assert!(read_back(&mouse).is_ok());
+        for sensitivity in [f32::NAN, f32::INFINITY, 0.0] {
+            let mouse = MouseSettings {
+                sensitivity,
+                ..mouse.clone()
+            };
+            assert!(read_back(&mouse).is_err());
+        }
+    }
+
+    #[test]
+    fn oversized_entities_are_read_errors() {
+        assert!(read_back(&Entity::new(BUFFER_WIDTH, BUFFER_HEIGHT, RED)).is_ok());
+        assert!(read_back(&Entity::new(BUFFER_WIDTH + 1, 10, RED)).is_err());
+        assert!(read_back(&Entity::new(10, BUFFER_HEIGHT + 1, RED)).is_err());
+        assert!(read_back(&Entity::new(-1, 10, RED)).is_err());
+
+        let arena = Arena {
+            p: V2 {
+                x: f32::NAN,
+                y: 0.0,
+            },
+            width: 100,
+            height: 100,
+        };
+        assert!(read_back(&arena).is_err());
+    }
+
+    #[test]
+    fn games_with_obstacles_read_back() {
+        let mut settings = GameSettings::new();
+        settings.mode = GameMode::Multiball;
+        let (width, height) = settings.levels[0].arena_size(BUFFER_WIDTH, BUFFER_HEIGHT);
+        settings.levels[0].obstacles = arena_layout("bricks", width, height).unwrap();
+        let mut memory = GameMemory::new(3, settings);
+        assert!(read_back(&memory).is_ok());
+        let mut game_state = GameState {
+            state: GameStates::Play,
+            pause_seconds_elapsed: 0.0,
+        };
+        let mut buffer = Win32OffscreenBuffer::new(BUFFER_WIDTH, BUFFER_HEIGHT).unwrap();
+        let mut input = GameInput::new();
+        input.dt_for_frame = 1.0 / 60.0;
+        update_and_render(
+            &mut memory,
+            &mut buffer,
+            &input,
+            &mut game_state,
+            &mut KeyBindings::new(),
         );
+        assert!(read_back(&memory).is_ok());
 
-        game_state.state = GameStates::DeathScene;
+        let date = Date::parse("2024-02-29").unwrap();
+        assert!(read_back(&daily_settings(date, MouseSettings::new())).is_ok());
     }
 }
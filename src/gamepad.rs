@@ -0,0 +1,747 @@
+use crate::bindings::*;
+use crate::*;
+
+pub const MAX_CONTROLLERS: usize = 4;
+const STICK_DEADZONE: f32 = 0.24;
+const SCAN_INTERVAL_SECONDS: f32 = 1.0;
+
+// NOTE(Fermin): What a device reports, sticks are normalized to -1..1 but
+// still have their deadzone. Buttons are reported as the action they drive.
+#[derive(Copy, Clone)]
+pub struct GamepadState {
+    pub actions: [bool; ACTIONS.len()],
+    pub left_stick: V2,
+    pub right_stick: V2,
+}
+impl GamepadState {
+    pub fn new() -> Self {
+        Self {
+            actions: [false; ACTIONS.len()],
+            left_stick: V2 { x: 0.0, y: 0.0 },
+            right_stick: V2 { x: 0.0, y: 0.0 },
+        }
+    }
+}
+
+pub trait GamepadDevice {
+    // NOTE(Fermin): Stays the same while the device is plugged in, backends
+    // use it to know what they already opened
+    fn id(&self) -> &str;
+    fn name(&self) -> &str;
+    // NOTE(Fermin): Applies the pending events to state, returns false once
+    // the device is gone
+    fn poll(&mut self, state: &mut GamepadState) -> bool;
+}
+
+pub trait GamepadBackend {
+    // NOTE(Fermin): Devices that showed up since the last scan, connected
+    // holds the ids of the devices already in a slot
+    fn scan(&mut self, connected: &[&str]) -> Vec<Box<dyn GamepadDevice>>;
+}
+
+struct ControllerSlot {
+    device: Box<dyn GamepadDevice>,
+    state: GamepadState,
+}
+
+// NOTE(Fermin): Owns the controller slots of GameInput. Devices take the
+// first free slot when they show up and give it back when they go away.
+pub struct Gamepads {
+    backends: Vec<Box<dyn GamepadBackend>>,
+    slots: [Option<ControllerSlot>; MAX_CONTROLLERS],
+    seconds_since_scan: f32,
+}
+impl Gamepads {
+    pub fn new() -> Self {
+        let mut result = Self {
+            backends: Vec::new(),
+            slots: Default::default(),
+            // NOTE(Fermin): Scan on the first update
+            seconds_since_scan: SCAN_INTERVAL_SECONDS,
+        };
+        #[cfg(windows)]
+        result.add_backend(Box::new(xinput::XInputBackend::new()));
+        #[cfg(target_os = "linux")]
+        result.add_backend(Box::new(evdev::EvdevBackend::new()));
+        result
+    }
+
+    pub fn add_backend(&mut self, backend: Box<dyn GamepadBackend>) {
+        self.backends.push(backend);
+    }
+
+    pub fn update(&mut self, input: &mut GameInput, dt: f32) {
+        self.seconds_since_scan += dt;
+        if self.seconds_since_scan >= SCAN_INTERVAL_SECONDS {
+            self.seconds_since_scan = 0.0;
+            self.scan();
+        }
+
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            let controller = &mut input.controllers[index];
+            let state = match slot {
+                Some(connected) => {
+                    if connected.device.poll(&mut connected.state) {
+                        Some(connected.state)
+                    } else {
+                        None
+                    }
+                }
+                None => None,
+            };
+
+            match state {
+                Some(state) => {
+                    controller.is_connected = true;
+                    controller.left_stick = apply_deadzone(state.left_stick);
+                    controller.right_stick = apply_deadzone(state.right_stick);
+                    // NOTE(Fermin): A controller is a single player, the
+                    // player two actions only exist for the keyboard
+                    for action in ACTIONS.into_iter().filter(|a| a.player() == 0) {
+                        process_controller_button(
+                            action.button(&mut controller.buttons),
+                            state.actions[action as usize],
+                        );
+                    }
+                }
+                None => {
+                    if let Some(slot) = slot.take() {
+                        println!(
+                            "Controller {} disconnected: {}",
+                            index + 1,
+                            slot.device.name()
+                        );
+                    }
+                    if controller.is_connected {
+                        release_controller(controller);
+                    }
+                }
+            }
+        }
+    }
+
+    fn scan(&mut self) {
+        let connected: Vec<String> = self
+            .slots
+            .iter()
+            .flatten()
+            .map(|slot| slot.device.id().to_string())
+            .collect();
+        let connected: Vec<&str> = connected.iter().map(String::as_str).collect();
+
+        let mut devices = Vec::new();
+        for backend in &mut self.backends {
+            devices.extend(backend.scan(&connected));
+        }
+        for device in devices {
+            match self.slots.iter().position(Option::is_none) {
+                Some(index) => {
+                    println!("Controller {} connected: {}", index + 1, device.name());
+                    self.slots[index] = Some(ControllerSlot {
+                        device,
+                        state: GamepadState::new(),
+                    });
+                }
+                None => println!("No free controller slot for {}", device.name()),
+            }
+        }
+    }
+}
+
+fn process_controller_button(state: &mut GameButtonState, is_down: bool) {
+    if state.ended_down != is_down {
+        state.ended_down = is_down;
+        state.half_transition_count += 1;
+    }
+}
+
+// NOTE(Fermin): Held buttons go up when the controller is unplugged so the
+// game sees them released instead of stuck
+fn release_controller(controller: &mut ControllerInput) {
+    for button in controller.buttons.all_mut() {
+        process_controller_button(button, false);
+    }
+    controller.is_connected = false;
+    controller.left_stick = V2 { x: 0.0, y: 0.0 };
+    controller.right_stick = V2 { x: 0.0, y: 0.0 };
+}
+
+// NOTE(Fermin): Radial deadzone, the range left outside of it is stretched
+// back to 0..1 so the stick doesn't jump when it leaves the deadzone
+fn apply_deadzone(stick: V2) -> V2 {
+    let length = stick.length();
+    if length <= STICK_DEADZONE {
+        return V2 { x: 0.0, y: 0.0 };
+    }
+    let scaled_length = ((length - STICK_DEADZONE) / (1.0 - STICK_DEADZONE)).min(1.0);
+    stick.normalized() * scaled_length
+}
+
+#[cfg(windows)]
+mod xinput {
+    use super::*;
+    use windows::Win32::Foundation::ERROR_SUCCESS;
+    use windows::Win32::UI::Input::XboxController::*;
+
+    // NOTE(Fermin): Both B and back go back so the menus work like the evdev
+    // layout
+    const BUTTON_ACTIONS: [(XINPUT_GAMEPAD_BUTTON_FLAGS, Action); 8] = [
+        (XINPUT_GAMEPAD_A, Action::Jump),
+        (XINPUT_GAMEPAD_B, Action::Back),
+        (XINPUT_GAMEPAD_BACK, Action::Back),
+        (XINPUT_GAMEPAD_START, Action::Start),
+        (XINPUT_GAMEPAD_DPAD_UP, Action::MoveUp),
+        (XINPUT_GAMEPAD_DPAD_DOWN, Action::MoveDown),
+        (XINPUT_GAMEPAD_DPAD_LEFT, Action::MoveLeft),
+        (XINPUT_GAMEPAD_DPAD_RIGHT, Action::MoveRight),
+    ];
+
+    fn get_state(user_index: u32) -> Option<XINPUT_STATE> {
+        let mut state = XINPUT_STATE::default();
+        let result = unsafe { XInputGetState(user_index, &mut state) };
+        (result == ERROR_SUCCESS.0).then_some(state)
+    }
+
+    // NOTE(Fermin): XInput sticks are up positive, the game is y down
+    fn stick(x: i16, y: i16) -> V2 {
+        V2 {
+            x: (x as f32 / 32767.0).max(-1.0),
+            y: -(y as f32 / 32767.0).max(-1.0),
+        }
+    }
+
+    pub struct XInputGamepad {
+        id: String,
+        name: String,
+        user_index: u32,
+        last_packet_number: Option<u32>,
+    }
+    impl GamepadDevice for XInputGamepad {
+        fn id(&self) -> &str {
+            &self.id
+        }
+        fn name(&self) -> &str {
+            &self.name
+        }
+        fn poll(&mut self, state: &mut GamepadState) -> bool {
+            let Some(xinput_state) = get_state(self.user_index) else {
+                return false;
+            };
+            // NOTE(Fermin): The packet number only changes when the pad does
+            if self.last_packet_number == Some(xinput_state.dwPacketNumber) {
+                return true;
+            }
+            self.last_packet_number = Some(xinput_state.dwPacketNumber);
+
+            let pad = xinput_state.Gamepad;
+            for action in ACTIONS {
+                state.actions[action as usize] = BUTTON_ACTIONS
+                    .iter()
+                    .any(|(button, a)| *a == action && pad.wButtons.0 & button.0 != 0);
+            }
+            state.left_stick = stick(pad.sThumbLX, pad.sThumbLY);
+            state.right_stick = stick(pad.sThumbRX, pad.sThumbRY);
+            true
+        }
+    }
+
+    // NOTE(Fermin): XInput has four fixed user slots. Asking for the state of
+    // an empty one is slow, so only the scan does it, once a second.
+    pub struct XInputBackend {}
+    impl XInputBackend {
+        pub fn new() -> Self {
+            Self {}
+        }
+    }
+    impl GamepadBackend for XInputBackend {
+        fn scan(&mut self, connected: &[&str]) -> Vec<Box<dyn GamepadDevice>> {
+            let mut result: Vec<Box<dyn GamepadDevice>> = Vec::new();
+            for user_index in 0..XUSER_MAX_COUNT {
+                let id = format!("xinput{}", user_index);
+                if connected.contains(&id.as_str()) || get_state(user_index).is_none() {
+                    continue;
+                }
+                result.push(Box::new(XInputGamepad {
+                    id,
+                    name: format!("XInput controller {}", user_index + 1),
+                    user_index,
+                    last_packet_number: None,
+                }));
+            }
+            result
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod evdev {
+    use super::*;
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{ErrorKind, Read};
+    use std::os::raw::{c_int, c_ulong};
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+    use std::path::{Path, PathBuf};
+
+    const O_NONBLOCK: i32 = 0o4000;
+    const ENODEV: i32 = 19;
+
+    const EV_KEY: u16 = 0x01;
+    const EV_ABS: u16 = 0x03;
+
+    const BTN_JOYSTICK: usize = 0x120;
+    const BTN_GAMEPAD: usize = 0x130;
+
+    const ABS_X: u16 = 0x00;
+    const ABS_Y: u16 = 0x01;
+    const ABS_RX: u16 = 0x03;
+    const ABS_RY: u16 = 0x04;
+    const ABS_HAT0X: u16 = 0x10;
+    const ABS_HAT0Y: u16 = 0x11;
+    const STICK_AXES: [u16; 4] = [ABS_X, ABS_Y, ABS_RX, ABS_RY];
+
+    // NOTE(Fermin): struct input_event is a timeval followed by type, code
+    // and value, the timeval is two longs
+    const TIMEVAL_SIZE: usize = 2 * std::mem::size_of::<c_ulong>();
+    const EVENT_SIZE: usize = TIMEVAL_SIZE + 8;
+
+    // NOTE(Fermin): Linux gamepad layout, several codes can drive the same
+    // action so a device keeps track of which ones are held
+    const BUTTON_ACTIONS: [(u16, Action); 10] = [
+        (0x130, Action::Jump),      // BTN_SOUTH
+        (0x131, Action::Back),      // BTN_EAST
+        (0x13a, Action::Back),      // BTN_SELECT
+        (0x13b, Action::Start),     // BTN_START
+        (0x220, Action::MoveUp),    // BTN_DPAD_UP
+        (0x221, Action::MoveDown),  // BTN_DPAD_DOWN
+        (0x222, Action::MoveLeft),  // BTN_DPAD_LEFT
+        (0x223, Action::MoveRight), // BTN_DPAD_RIGHT
+        (0x120, Action::Jump),      // BTN_TRIGGER, first button of joysticks
+        (0x129, Action::Start),     // BTN_BASE4
+    ];
+
+    extern "C" {
+        fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+
+    // NOTE(Fermin): EVIOCGABS(axis), _IOR('E', 0x40 + axis, struct input_absinfo)
+    fn eviocgabs(axis: u16) -> c_ulong {
+        let read = 2 << 30;
+        let size = (std::mem::size_of::<[i32; 6]>() as c_ulong) << 16;
+        let kind = (b'E' as c_ulong) << 8;
+        read | size | kind | (0x40 + axis as c_ulong)
+    }
+
+    #[derive(Copy, Clone)]
+    struct AxisRange {
+        min: i32,
+        max: i32,
+    }
+
+    pub struct EvdevGamepad {
+        id: String,
+        name: String,
+        file: File,
+        axis_ranges: [Option<AxisRange>; 4],
+        held_buttons: Vec<u16>,
+        hat: V2,
+    }
+    impl EvdevGamepad {
+        fn open(path: &Path) -> std::io::Result<Self> {
+            let file = OpenOptions::new()
+                .read(true)
+                .custom_flags(O_NONBLOCK)
+                .open(path)?;
+            let mut axis_ranges = [None; 4];
+            for (range, axis) in axis_ranges.iter_mut().zip(STICK_AXES) {
+                let mut info = [0i32; 6];
+                let result = unsafe { ioctl(file.as_raw_fd(), eviocgabs(axis), info.as_mut_ptr()) };
+                if result >= 0 && info[2] > info[1] {
+                    *range = Some(AxisRange {
+                        min: info[1],
+                        max: info[2],
+                    });
+                }
+            }
+            Ok(Self {
+                id: path.display().to_string(),
+                name: device_name(path).unwrap_or_else(|| path.display().to_string()),
+                file,
+                axis_ranges,
+                held_buttons: Vec::new(),
+                hat: V2 { x: 0.0, y: 0.0 },
+            })
+        }
+
+        fn process_event(&mut self, kind: u16, code: u16, value: i32, state: &mut GamepadState) {
+            match kind {
+                EV_KEY => {
+                    let Some(action) = button_action(code) else {
+                        return;
+                    };
+                    // NOTE(Fermin): 2 is autorepeat, the button is still down
+                    if value != 0 {
+                        if !self.held_buttons.contains(&code) {
+                            self.held_buttons.push(code);
+                        }
+                    } else {
+                        self.held_buttons.retain(|held| *held != code);
+                    }
+                    state.actions[action as usize] = self
+                        .held_buttons
+                        .iter()
+                        .any(|held| button_action(*held) == Some(action));
+                }
+                EV_ABS => match code {
+                    ABS_HAT0X => self.hat.x = value.signum() as f32,
+                    ABS_HAT0Y => self.hat.y = value.signum() as f32,
+                    _ => {
+                        let Some(index) = STICK_AXES.iter().position(|axis| *axis == code) else {
+                            return;
+                        };
+                        let Some(range) = self.axis_ranges[index] else {
+                            return;
+                        };
+                        let normalized =
+                            (value - range.min) as f32 / (range.max - range.min) as f32;
+                        let value = (normalized * 2.0 - 1.0).clamp(-1.0, 1.0);
+                        match code {
+                            ABS_X => state.left_stick.x = value,
+                            ABS_Y => state.left_stick.y = value,
+                            ABS_RX => state.right_stick.x = value,
+                            _ => state.right_stick.y = value,
+                        }
+                    }
+                },
+                _ => (),
+            }
+        }
+    }
+    impl GamepadDevice for EvdevGamepad {
+        fn id(&self) -> &str {
+            &self.id
+        }
+        fn name(&self) -> &str {
+            &self.name
+        }
+        fn poll(&mut self, state: &mut GamepadState) -> bool {
+            let mut bytes = [0u8; EVENT_SIZE * 64];
+            loop {
+                match self.file.read(&mut bytes) {
+                    Ok(0) => return false,
+                    Ok(count) => {
+                        for event in bytes[..count].chunks_exact(EVENT_SIZE) {
+                            let kind =
+                                u16::from_ne_bytes([event[TIMEVAL_SIZE], event[TIMEVAL_SIZE + 1]]);
+                            let code = u16::from_ne_bytes([
+                                event[TIMEVAL_SIZE + 2],
+                                event[TIMEVAL_SIZE + 3],
+                            ]);
+                            let value = i32::from_ne_bytes([
+                                event[TIMEVAL_SIZE + 4],
+                                event[TIMEVAL_SIZE + 5],
+                                event[TIMEVAL_SIZE + 6],
+                                event[TIMEVAL_SIZE + 7],
+                            ]);
+                            self.process_event(kind, code, value, state);
+                        }
+                    }
+                    Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                    Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                    Err(error) => {
+                        if error.raw_os_error() != Some(ENODEV) {
+                            println!("Error reading {}: {}", self.id, error);
+                        }
+                        return false;
+                    }
+                }
+            }
+
+            // NOTE(Fermin): The hat is a dpad on most pads, it drives the
+            // move buttons along with the real dpad buttons
+            let hat_actions = [
+                (Action::MoveUp, self.hat.y < 0.0),
+                (Action::MoveDown, self.hat.y > 0.0),
+                (Action::MoveLeft, self.hat.x < 0.0),
+                (Action::MoveRight, self.hat.x > 0.0),
+            ];
+            for (action, hat_down) in hat_actions {
+                let button_down = self
+                    .held_buttons
+                    .iter()
+                    .any(|held| button_action(*held) == Some(action));
+                state.actions[action as usize] = button_down || hat_down;
+            }
+            true
+        }
+    }
+
+    fn button_action(code: u16) -> Option<Action> {
+        BUTTON_ACTIONS
+            .iter()
+            .find(|(button, _)| *button == code)
+            .map(|(_, action)| *action)
+    }
+
+    fn sys_path(path: &Path) -> Option<PathBuf> {
+        let file_name = path.file_name()?;
+        Some(Path::new("/sys/class/input").join(file_name).join("device"))
+    }
+
+    fn device_name(path: &Path) -> Option<String> {
+        let name = fs::read_to_string(sys_path(path)?.join("name")).ok()?;
+        Some(name.trim().to_string())
+    }
+
+    // NOTE(Fermin): The key capabilities are hex words, most significant
+    // first, bit n is key code n
+    fn is_gamepad(path: &Path) -> bool {
+        let Some(sys_path) = sys_path(path) else {
+            return false;
+        };
+        let Ok(text) = fs::read_to_string(sys_path.join("capabilities/key")) else {
+            return false;
+        };
+        let word_bits = c_ulong::BITS as usize;
+        let has_key = |code: usize| {
+            text.split_whitespace()
+                .rev()
+                .nth(code / word_bits)
+                .and_then(|word| u64::from_str_radix(word, 16).ok())
+                .map(|word| word & (1 << (code % word_bits)) != 0)
+                .unwrap_or(false)
+        };
+        has_key(BTN_GAMEPAD) || has_key(BTN_JOYSTICK)
+    }
+
+    // NOTE(Fermin): Devices we can't open are remembered so we don't retry
+    // (and log) them every scan
+    pub struct EvdevBackend {
+        failed: Vec<String>,
+    }
+    impl EvdevBackend {
+        pub fn new() -> Self {
+            Self { failed: Vec::new() }
+        }
+    }
+    impl GamepadBackend for EvdevBackend {
+        fn scan(&mut self, connected: &[&str]) -> Vec<Box<dyn GamepadDevice>> {
+            let mut result: Vec<Box<dyn GamepadDevice>> = Vec::new();
+            let Ok(entries) = fs::read_dir("/dev/input") else {
+                return result;
+            };
+            let mut paths: Vec<PathBuf> = entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| name.starts_with("event"))
+                })
+                .collect();
+            paths.sort();
+
+            let present: Vec<String> = paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect();
+            self.failed.retain(|id| present.contains(id));
+
+            for (path, id) in paths.iter().zip(present) {
+                if connected.contains(&id.as_str())
+                    || self.failed.contains(&id)
+                    || !is_gamepad(path)
+                {
+                    continue;
+                }
+                match EvdevGamepad::open(path) {
+                    Ok(device) => result.push(Box::new(device)),
+                    Err(error) => {
+                        println!("Error opening {}: {}", id, error);
+                        self.failed.push(id);
+                    }
+                }
+            }
+            result
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    // NOTE(Fermin): A device driven from code instead of hardware, the handle
+    // sets what the device reports and unplugs it
+    pub struct VirtualGamepad {
+        id: String,
+        state: Rc<RefCell<GamepadState>>,
+        plugged_in: Rc<Cell<bool>>,
+    }
+    impl GamepadDevice for VirtualGamepad {
+        fn id(&self) -> &str {
+            &self.id
+        }
+        fn name(&self) -> &str {
+            "Virtual gamepad"
+        }
+        fn poll(&mut self, state: &mut GamepadState) -> bool {
+            *state = *self.state.borrow();
+            self.plugged_in.get()
+        }
+    }
+
+    #[derive(Clone)]
+    pub struct VirtualGamepadHandle {
+        state: Rc<RefCell<GamepadState>>,
+        plugged_in: Rc<Cell<bool>>,
+    }
+    impl VirtualGamepadHandle {
+        pub fn set(&self, state: GamepadState) {
+            *self.state.borrow_mut() = state;
+        }
+        pub fn unplug(&self) {
+            self.plugged_in.set(false);
+        }
+    }
+
+    // NOTE(Fermin): Hands out the virtual devices plugged since the last scan
+    pub struct VirtualGamepadBackend {
+        pending: Vec<VirtualGamepad>,
+        next_id: u32,
+    }
+    impl VirtualGamepadBackend {
+        pub fn new() -> Self {
+            Self {
+                pending: Vec::new(),
+                next_id: 0,
+            }
+        }
+
+        pub fn plug(&mut self) -> VirtualGamepadHandle {
+            let handle = VirtualGamepadHandle {
+                state: Rc::new(RefCell::new(GamepadState::new())),
+                plugged_in: Rc::new(Cell::new(true)),
+            };
+            self.pending.push(VirtualGamepad {
+                id: format!("virtual{}", self.next_id),
+                state: handle.state.clone(),
+                plugged_in: handle.plugged_in.clone(),
+            });
+            self.next_id += 1;
+            handle
+        }
+    }
+    impl GamepadBackend for VirtualGamepadBackend {
+        fn scan(&mut self, _connected: &[&str]) -> Vec<Box<dyn GamepadDevice>> {
+            self.pending
+                .drain(..)
+                .map(|device| Box::new(device) as Box<dyn GamepadDevice>)
+                .collect()
+        }
+    }
+
+    fn gamepads_with(backend: VirtualGamepadBackend) -> Gamepads {
+        Gamepads {
+            backends: vec![Box::new(backend)],
+            slots: Default::default(),
+            seconds_since_scan: SCAN_INTERVAL_SECONDS,
+        }
+    }
+
+    fn frame(gamepads: &mut Gamepads, input: &mut GameInput) {
+        input.prepare_for_new_frame();
+        gamepads.update(input, 1.0 / 60.0);
+    }
+
+    fn stick_after_deadzone(x: f32) -> V2 {
+        let mut backend = VirtualGamepadBackend::new();
+        let pad = backend.plug();
+        let mut gamepads = gamepads_with(backend);
+        let mut input = GameInput::new();
+        let mut state = GamepadState::new();
+        state.left_stick = V2 { x, y: 0.0 };
+        pad.set(state);
+        frame(&mut gamepads, &mut input);
+        input.controllers[0].left_stick
+    }
+
+    #[test]
+    fn devices_connect_to_the_first_free_slots() {
+        let mut backend = VirtualGamepadBackend::new();
+        let _first = backend.plug();
+        let _second = backend.plug();
+        let mut gamepads = gamepads_with(backend);
+        let mut input = GameInput::new();
+        frame(&mut gamepads, &mut input);
+        assert!(input.controllers[0].is_connected);
+        assert!(input.controllers[1].is_connected);
+        assert!(!input.controllers[2].is_connected);
+    }
+
+    #[test]
+    fn button_transitions_are_counted() {
+        let mut backend = VirtualGamepadBackend::new();
+        let pad = backend.plug();
+        let mut gamepads = gamepads_with(backend);
+        let mut input = GameInput::new();
+        frame(&mut gamepads, &mut input);
+
+        let mut state = GamepadState::new();
+        state.actions[Action::Jump as usize] = true;
+        pad.set(state);
+        frame(&mut gamepads, &mut input);
+        let jump = &input.controllers[0].buttons.jump;
+        assert!(jump.ended_down);
+        assert_eq!(jump.half_transition_count, 1);
+
+        frame(&mut gamepads, &mut input);
+        let jump = &input.controllers[0].buttons.jump;
+        assert!(jump.ended_down);
+        assert_eq!(jump.half_transition_count, 0);
+
+        pad.set(GamepadState::new());
+        frame(&mut gamepads, &mut input);
+        let jump = &input.controllers[0].buttons.jump;
+        assert!(!jump.ended_down);
+        assert_eq!(jump.half_transition_count, 1);
+    }
+
+    #[test]
+    fn disconnect_releases_held_buttons() {
+        let mut backend = VirtualGamepadBackend::new();
+        let first = backend.plug();
+        let _second = backend.plug();
+        let mut gamepads = gamepads_with(backend);
+        let mut input = GameInput::new();
+
+        let mut state = GamepadState::new();
+        state.actions[Action::MoveUp as usize] = true;
+        state.left_stick = V2 { x: 1.0, y: 0.0 };
+        first.set(state);
+        frame(&mut gamepads, &mut input);
+        assert!(input.controllers[0].buttons.move_up.ended_down);
+
+        first.unplug();
+        frame(&mut gamepads, &mut input);
+        let controller = &input.controllers[0];
+        assert!(!controller.is_connected);
+        assert!(!controller.buttons.move_up.ended_down);
+        assert_eq!(controller.buttons.move_up.half_transition_count, 1);
+        assert_eq!(controller.left_stick.x, 0.0);
+        assert!(gamepads.slots[0].is_none());
+        assert!(input.controllers[1].is_connected);
+    }
+
+    #[test]
+    fn sticks_have_a_deadzone() {
+        assert_eq!(stick_after_deadzone(STICK_DEADZONE * 0.5).x, 0.0);
+        assert_eq!(stick_after_deadzone(-STICK_DEADZONE).x, 0.0);
+        assert_eq!(stick_after_deadzone(1.0).x, 1.0);
+        assert_eq!(stick_after_deadzone(-1.0).x, -1.0);
+        let halfway = (1.0 + STICK_DEADZONE) / 2.0;
+        assert!((stick_after_deadzone(halfway).x - 0.5).abs() < 0.001);
+    }
+}
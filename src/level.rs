@@ -0,0 +1,585 @@
+use crate::savestate::*;
+use crate::window::*;
+use crate::*;
+use bytes::BytesMut;
+use std::fs;
+use std::io::Result;
+use std::path::Path;
+use std::str::FromStr;
+
+// NOTE(Fermin): Space between the buffer edges and the paddles when the
+// level doesn't give an arena size
+pub const ENTITY_Y_PADDING: i32 = 10;
+pub const ENTITY_X_PADDING: i32 = 10;
+pub const BALL_SIZE: i32 = 10;
+pub const PLAYER_WIDTH: i32 = 8;
+pub const PLAYER_LENGTH: i32 = PLAYER_WIDTH * 5;
+pub const PLAYER_ACCELERATION: f32 = 3000.0;
+pub const BALL_BASE_SPEED: f32 = 350.0;
+pub const BALL_MIN_SPEED: f32 = 300.0;
+pub const BALL_MAX_SPEED: f32 = 900.0;
+pub const BALL_SPEED_RAMP: f32 = 15.0;
+// NOTE(Fermin): Smaller arenas leave no room for the paddles to move
+const MIN_ARENA_SIZE: i32 = 100;
+
+// NOTE(Fermin): What clears a level in every mode but versus and four
+// player, those keep their own rules
+#[derive(Copy, Clone, PartialEq)]
+pub enum WinCondition {
+    Endless,
+    Points(u32),
+    Bricks,
+    Survive(f32),
+}
+impl Persist for WinCondition {
+    fn write(&self, out: &mut BytesMut) {
+        match self {
+            WinCondition::Endless => 0u32.write(out),
+            WinCondition::Points(points) => {
+                1u32.write(out);
+                points.write(out);
+            }
+            WinCondition::Bricks => 2u32.write(out),
+            WinCondition::Survive(seconds) => {
+                3u32.write(out);
+                seconds.write(out);
+            }
+        }
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        match u32::read(buf)? {
+            0 => Ok(WinCondition::Endless),
+            1 => Ok(WinCondition::Points(u32::read(buf)?)),
+            2 => Ok(WinCondition::Bricks),
+            3 => match f32::read(buf)? {
+                seconds if seconds.is_finite() && seconds > 0.0 => {
+                    Ok(WinCondition::Survive(seconds))
+                }
+                seconds => Err(invalid_data(&format!("invalid survive time {}", seconds))),
+            },
+            value => Err(invalid_data(&format!("invalid win condition {}", value))),
+        }
+    }
+}
+
+// NOTE(Fermin): Positions are in pixels from the top left corner of the
+// arena, the box the outer edges of the paddles move along
+#[derive(Clone)]
+pub struct Level {
+    pub name: String,
+    // NOTE(Fermin): Centered on the buffer, None fills it leaving the usual
+    // padding
+    pub arena: Option<(i32, i32)>,
+    pub paddle_length: i32,
+    pub paddle_thickness: i32,
+    pub paddle_acceleration: f32,
+    pub ball_size: i32,
+    pub ball_base_speed: f32,
+    pub ball_min_speed: f32,
+    pub ball_max_speed: f32,
+    pub ball_speed_ramp: f32,
+    // NOTE(Fermin): Where the first ball of every serve starts and the
+    // direction it goes in, the middle towards a random corner when None
+    pub serve: Option<(V2, V2)>,
+    pub obstacles: Vec<ObstacleSpec>,
+    pub win: WinCondition,
+}
+impl Level {
+    pub fn new() -> Self {
+        Self {
+            name: String::new(),
+            arena: None,
+            paddle_length: PLAYER_LENGTH,
+            paddle_thickness: PLAYER_WIDTH,
+            paddle_acceleration: PLAYER_ACCELERATION,
+            ball_size: BALL_SIZE,
+            ball_base_speed: BALL_BASE_SPEED,
+            ball_min_speed: BALL_MIN_SPEED,
+            ball_max_speed: BALL_MAX_SPEED,
+            ball_speed_ramp: BALL_SPEED_RAMP,
+            serve: None,
+            obstacles: Vec::new(),
+            win: WinCondition::Endless,
+        }
+    }
+
+    // NOTE(Fermin): Never bigger than the buffer
+    pub fn arena_size(&self, buffer_width: i32, buffer_height: i32) -> (i32, i32) {
+        let max_width = buffer_width - 2 * ENTITY_X_PADDING;
+        let max_height = buffer_height - 2 * ENTITY_Y_PADDING;
+        match self.arena {
+            Some((width, height)) => (width.min(max_width), height.min(max_height)),
+            None => (max_width, max_height),
+        }
+    }
+
+    pub fn has_bricks(&self) -> bool {
+        self.obstacles
+            .iter()
+            .any(|obstacle| obstacle.kind == ObstacleKind::Brick)
+    }
+
+    // NOTE(Fermin): Paddles run along both axes and the ball goes anywhere
+    // in the arena, they have to fit its short side on the game buffer
+    fn check(&self) -> std::result::Result<(), String> {
+        if self.win == WinCondition::Bricks && !self.has_bricks() {
+            return Err("'win = bricks' needs at least one brick".to_string());
+        }
+        let (width, height) = self.arena_size(BUFFER_WIDTH, BUFFER_HEIGHT);
+        let short_side = width.min(height);
+        for (name, size) in [
+            ("paddle length", self.paddle_length),
+            ("paddle thickness", self.paddle_thickness),
+            ("ball size", self.ball_size),
+        ] {
+            if size > short_side {
+                return Err(format!(
+                    "{} {} doesn't fit the {}x{} arena",
+                    name, size, width, height
+                ));
+            }
+        }
+        for obstacle in &self.obstacles {
+            if obstacle.p.x < 0.0
+                || obstacle.p.y < 0.0
+                || obstacle.p.x as i32 + obstacle.width > width
+                || obstacle.p.y as i32 + obstacle.height > height
+            {
+                return Err(format!(
+                    "{} at {} {} is outside the {}x{} arena",
+                    obstacle.kind.name(),
+                    obstacle.p.x,
+                    obstacle.p.y,
+                    width,
+                    height
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+impl Persist for Level {
+    fn write(&self, out: &mut BytesMut) {
+        self.name.write(out);
+        self.arena.is_some().write(out);
+        let (width, height) = self.arena.unwrap_or((0, 0));
+        width.write(out);
+        height.write(out);
+        self.paddle_length.write(out);
+        self.paddle_thickness.write(out);
+        self.paddle_acceleration.write(out);
+        self.ball_size.write(out);
+        self.ball_base_speed.write(out);
+        self.ball_min_speed.write(out);
+        self.ball_max_speed.write(out);
+        self.ball_speed_ramp.write(out);
+        self.serve.is_some().write(out);
+        let zero = V2 { x: 0.0, y: 0.0 };
+        let (serve_p, serve_direction) = self.serve.unwrap_or((zero, zero));
+        serve_p.write(out);
+        serve_direction.write(out);
+        write_vec(&self.obstacles, out);
+        self.win.write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        let name = String::read(buf)?;
+        let has_arena = bool::read(buf)?;
+        let arena = (i32::read(buf)?, i32::read(buf)?);
+        let paddle_length = i32::read(buf)?;
+        let paddle_thickness = i32::read(buf)?;
+        let paddle_acceleration = f32::read(buf)?;
+        let ball_size = i32::read(buf)?;
+        let ball_base_speed = f32::read(buf)?;
+        let ball_min_speed = f32::read(buf)?;
+        let ball_max_speed = f32::read(buf)?;
+        let ball_speed_ramp = f32::read(buf)?;
+        let has_serve = bool::read(buf)?;
+        let serve = (V2::read(buf)?, V2::read(buf)?);
+        let result = Self {
+            name,
+            arena: has_arena.then_some(arena),
+            paddle_length,
+            paddle_thickness,
+            paddle_acceleration,
+            ball_size,
+            ball_base_speed,
+            ball_min_speed,
+            ball_max_speed,
+            ball_speed_ramp,
+            serve: has_serve.then_some(serve),
+            obstacles: read_vec(buf)?,
+            win: WinCondition::read(buf)?,
+        };
+
+        // NOTE(Fermin): The same limits a level file has, saves and replays
+        // can be as broken as any text file
+        let error = |message: &str| invalid_data(&format!("level {}: {}", result.name, message));
+        let numbers = [
+            paddle_acceleration,
+            ball_base_speed,
+            ball_min_speed,
+            ball_max_speed,
+            ball_speed_ramp,
+            serve.0.x,
+            serve.0.y,
+            serve.1.x,
+            serve.1.y,
+        ];
+        if !numbers.iter().all(|number| number.is_finite()) {
+            return Err(error("numbers must be finite"));
+        }
+        if has_arena && (arena.0 < MIN_ARENA_SIZE || arena.1 < MIN_ARENA_SIZE) {
+            return Err(error("arena is too small"));
+        }
+        if paddle_length < 1 || paddle_thickness < 1 || paddle_acceleration <= 0.0 {
+            return Err(error("paddle sizes and acceleration must be positive"));
+        }
+        if ball_size < 1 || ball_min_speed <= 0.0 || ball_min_speed > ball_max_speed {
+            return Err(error("invalid ball size or speeds"));
+        }
+        if has_serve && serve.1.x == 0.0 && serve.1.y == 0.0 {
+            return Err(error("serve direction can't be 0 0"));
+        }
+        result.check().map_err(|message| error(&message))?;
+        Ok(result)
+    }
+}
+
+// NOTE(Fermin): Plain text, one 'key = values' per line and # starts a
+// comment. Every 'level = NAME' line starts the next level of the sequence,
+// the rest of the keys are optional:
+//
+//   level = RING
+//   arena = WIDTH HEIGHT
+//   paddle = LENGTH THICKNESS ACCELERATION
+//   ball = SIZE BASE_SPEED MIN_SPEED MAX_SPEED SPEED_RAMP
+//   serve = X Y DX DY
+//   win = endless | points N | bricks | survive SECONDS
+//   brick = X Y WIDTH HEIGHT HIT_POINTS
+//   block = X Y WIDTH HEIGHT
+//   blocker = X Y WIDTH HEIGHT DX DY TRAVEL
+pub fn load_levels(path: &Path) -> Result<Vec<Level>> {
+    let text = fs::read_to_string(path)?;
+    parse_levels(&text, &path.display().to_string())
+}
+
+fn parse_levels(text: &str, source: &str) -> Result<Vec<Level>> {
+    let mut result: Vec<Level> = Vec::new();
+    let mut level_lines = Vec::new();
+    for (line_index, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error =
+            |message: String| invalid_data(&format!("{}:{}: {}", source, line_index + 1, message));
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error(format!("expected 'key = value', got '{}'", line)))?;
+        let key = key.trim();
+        let value = value.trim();
+        if key == "level" {
+            if value.is_empty() {
+                return Err(error("expected 'level = NAME'".to_string()));
+            }
+            let mut level = Level::new();
+            level.name = value.to_uppercase();
+            result.push(level);
+            level_lines.push(line_index + 1);
+            continue;
+        }
+        let level = result
+            .last_mut()
+            .ok_or_else(|| error(format!("'{}' before the first 'level = NAME'", key)))?;
+
+        let values: Vec<&str> = value.split_whitespace().collect();
+        match key {
+            "arena" => {
+                let [width, height] =
+                    parse_values(&values, "arena = WIDTH HEIGHT").map_err(error)?;
+                if width < MIN_ARENA_SIZE || height < MIN_ARENA_SIZE {
+                    return Err(error(format!(
+                        "arena must be at least {}x{}",
+                        MIN_ARENA_SIZE, MIN_ARENA_SIZE
+                    )));
+                }
+                level.arena = Some((width, height));
+            }
+            "paddle" => {
+                let [length, thickness, acceleration] =
+                    parse_values(&values, "paddle = LENGTH THICKNESS ACCELERATION")
+                        .map_err(error)?;
+                if length < 1.0 || thickness < 1.0 || acceleration <= 0.0 {
+                    return Err(error(
+                        "paddle sizes and acceleration must be positive".to_string(),
+                    ));
+                }
+                level.paddle_length = length as i32;
+                level.paddle_thickness = thickness as i32;
+                level.paddle_acceleration = acceleration;
+            }
+            "ball" => {
+                let [size, base, min, max, ramp] = parse_values(
+                    &values,
+                    "ball = SIZE BASE_SPEED MIN_SPEED MAX_SPEED SPEED_RAMP",
+                )
+                .map_err(error)?;
+                if size < 1.0 || min <= 0.0 || min > max {
+                    return Err(error(
+                        "ball size must be positive and MIN_SPEED between 0 and MAX_SPEED"
+                            .to_string(),
+                    ));
+                }
+                level.ball_size = size as i32;
+                level.ball_base_speed = base;
+                level.ball_min_speed = min;
+                level.ball_max_speed = max;
+                level.ball_speed_ramp = ramp;
+            }
+            "serve" => {
+                let [x, y, dx, dy] = parse_values(&values, "serve = X Y DX DY").map_err(error)?;
+                if dx == 0.0 && dy == 0.0 {
+                    return Err(error("serve direction can't be 0 0".to_string()));
+                }
+                level.serve = Some((V2 { x, y }, V2 { x: dx, y: dy }));
+            }
+            "win" => {
+                let usage = "win = endless | points N | bricks | survive SECONDS";
+                level.win = match values[..] {
+                    ["endless"] => WinCondition::Endless,
+                    ["bricks"] => WinCondition::Bricks,
+                    ["points", points] => WinCondition::Points(
+                        parse_number::<u32>(points, usage).map_err(error)?.max(1),
+                    ),
+                    ["survive", seconds] => {
+                        let seconds: f32 = parse_number(seconds, usage).map_err(error)?;
+                        if seconds <= 0.0 {
+                            return Err(error(
+                                "survive needs a positive number of seconds".to_string(),
+                            ));
+                        }
+                        WinCondition::Survive(seconds)
+                    }
+                    _ => return Err(error(format!("expected '{}', got '{}'", usage, value))),
+                };
+            }
+            "brick" => {
+                let [x, y, width, height, hit_points] =
+                    parse_values(&values, "brick = X Y WIDTH HEIGHT HIT_POINTS").map_err(error)?;
+                check_obstacle_size(width, height).map_err(error)?;
+                if hit_points < 1 {
+                    return Err(error("bricks need at least 1 hit point".to_string()));
+                }
+                level
+                    .obstacles
+                    .push(ObstacleSpec::brick(x, y, width, height, hit_points as u32));
+            }
+            "block" => {
+                let [x, y, width, height] =
+                    parse_values(&values, "block = X Y WIDTH HEIGHT").map_err(error)?;
+                check_obstacle_size(width, height).map_err(error)?;
+                level
+                    .obstacles
+                    .push(ObstacleSpec::block(x, y, width, height));
+            }
+            "blocker" => {
+                let [x, y, width, height, dx, dy, travel] =
+                    parse_values(&values, "blocker = X Y WIDTH HEIGHT DX DY TRAVEL")
+                        .map_err(error)?;
+                check_obstacle_size(width as i32, height as i32).map_err(error)?;
+                if (dx == 0.0 && dy == 0.0) || travel <= 0.0 {
+                    return Err(error(
+                        "blockers need a direction and a positive TRAVEL".to_string(),
+                    ));
+                }
+                level.obstacles.push(ObstacleSpec::blocker(
+                    x as i32,
+                    y as i32,
+                    width as i32,
+                    height as i32,
+                    V2 { x: dx, y: dy },
+                    travel,
+                ));
+            }
+            _ => return Err(error(format!("unknown key '{}'", key))),
+        }
+    }
+
+    if result.is_empty() {
+        return Err(invalid_data(&format!(
+            "{}: no levels, start one with 'level = NAME'",
+            source
+        )));
+    }
+    for (level, line) in result.iter().zip(level_lines) {
+        let error = |message: String| {
+            invalid_data(&format!(
+                "{}:{}: level {}: {}",
+                source, line, level.name, message
+            ))
+        };
+        level.check().map_err(error)?;
+    }
+    Ok(result)
+}
+
+// NOTE(Fermin): nan and inf parse as floats but nothing in the game can use
+// them, they are as invalid as any other garbage
+pub fn parse_number<T: FromStr>(value: &str, usage: &str) -> std::result::Result<T, String> {
+    let error = || format!("invalid number '{}' in '{}'", value, usage);
+    if value.parse::<f64>().is_ok_and(|number| !number.is_finite()) {
+        return Err(error());
+    }
+    value.parse().map_err(|_| error())
+}
+
+fn parse_values<T: FromStr + Copy + Default, const N: usize>(
+    values: &[&str],
+    usage: &str,
+) -> std::result::Result<[T; N], String> {
+    if values.len() != N {
+        return Err(format!("expected '{}', got {} values", usage, values.len()));
+    }
+    let mut result = [T::default(); N];
+    for (slot, value) in result.iter_mut().zip(values) {
+        *slot = parse_number(value, usage)?;
+    }
+    Ok(result)
+}
+
+fn check_obstacle_size(width: i32, height: i32) -> std::result::Result<(), String> {
+    if width < 1 || height < 1 {
+        return Err("obstacle sizes must be positive".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Vec<Level>> {
+        parse_levels(text, "test")
+    }
+
+    #[test]
+    fn parses_a_level() {
+        let levels = parse(
+            "level = ring # the first one\n\
+             arena = 200 150\n\
+             paddle = 30 6 2500\n\
+             ball = 8 300 250 700 10\n\
+             serve = 100 75 1 -1\n\
+             win = bricks\n\
+             brick = 10 10 20 10 2\n\
+             level = two\n",
+        )
+        .unwrap();
+        assert_eq!(levels.len(), 2);
+        let ring = &levels[0];
+        assert_eq!(ring.name, "RING");
+        assert_eq!(ring.arena, Some((200, 150)));
+        assert_eq!(ring.paddle_length, 30);
+        assert_eq!(ring.ball_max_speed, 700.0);
+        assert!(ring.win == WinCondition::Bricks);
+        assert_eq!(ring.obstacles.len(), 1);
+        assert_eq!(levels[1].paddle_length, PLAYER_LENGTH);
+    }
+
+    #[test]
+    fn campaign_loads() {
+        let levels = load_levels(Path::new("levels/campaign.txt")).unwrap();
+        assert!(!levels.is_empty());
+    }
+
+    #[test]
+    fn non_finite_numbers_are_errors() {
+        assert!(parse("level = a\nball = 10 350 nan 900 15\n").is_err());
+        assert!(parse("level = a\nball = 10 350 300 inf 15\n").is_err());
+        assert!(parse("level = a\nball = 10 NaN 300 900 15\n").is_err());
+        assert!(parse("level = a\npaddle = 40 8 infinity\n").is_err());
+        assert!(parse("level = a\nserve = 1e999 0 1 1\n").is_err());
+        assert!(parse("level = a\nwin = survive nan\n").is_err());
+        assert!(parse_number::<f32>("-inf", "test").is_err());
+        assert_eq!(parse_number::<f32>("1.5", "test"), Ok(1.5));
+    }
+
+    #[test]
+    fn paddles_must_fit_the_arena() {
+        assert!(parse("level = a\narena = 300 100\npaddle = 101 8 3000\n").is_err());
+        assert!(parse("level = a\narena = 300 100\npaddle = 100 8 3000\n").is_ok());
+        let (width, height) = Level::new().arena_size(BUFFER_WIDTH, BUFFER_HEIGHT);
+        let too_long = width.min(height) + 1;
+        assert!(parse(&format!("level = a\npaddle = {} 8 3000\n", too_long)).is_err());
+        // NOTE(Fermin): Arenas bigger than the buffer are cut down to it
+        let text = format!(
+            "level = a\narena = 5000 5000\npaddle = {} 8 3000\n",
+            too_long
+        );
+        assert!(parse(&text).is_err());
+
+        // NOTE(Fermin): Thickness and ball size too, past the buffer they
+        // would be drawn out of bounds
+        assert!(parse("level = a\narena = 300 100\npaddle = 40 101 3000\n").is_err());
+        assert!(parse("level = a\npaddle = 40 500 3000\n").is_err());
+        assert!(parse("level = a\npaddle = 40 3000 3000\n").is_err());
+        assert!(parse("level = a\narena = 300 100\nball = 101 300 100 600 10\n").is_err());
+        assert!(parse("level = a\nball = 500 300 100 600 10\n").is_err());
+        assert!(parse("level = a\narena = 300 100\nball = 100 300 100 600 10\n").is_ok());
+    }
+
+    fn read_back(level: &Level) -> Result<Level> {
+        let mut out = BytesMut::new();
+        level.write(&mut out);
+        Level::read(&mut &out[..])
+    }
+
+    #[test]
+    fn broken_levels_are_read_errors() {
+        let levels = load_levels(Path::new("levels/campaign.txt")).unwrap();
+        for level in &levels {
+            assert!(read_back(level).is_ok(), "{}", level.name);
+        }
+
+        let broken: [fn(&mut Level); 8] = [
+            |level| level.ball_min_speed = level.ball_max_speed + 1.0,
+            |level| level.ball_base_speed = f32::NAN,
+            |level| level.ball_size = 500,
+            |level| level.paddle_thickness = 0,
+            |level| level.arena = Some((10, 10)),
+            |level| level.serve = Some((V2 { x: 1.0, y: 1.0 }, V2 { x: 0.0, y: 0.0 })),
+            |level| level.win = WinCondition::Survive(f32::NAN),
+            |level| level.obstacles.push(ObstacleSpec::block(0, 0, 0, 10)),
+        ];
+        for break_level in broken {
+            let mut level = Level::new();
+            break_level(&mut level);
+            assert!(read_back(&level).is_err());
+        }
+
+        let mut level = Level::new();
+        let blocker = ObstacleSpec::blocker(10, 10, 20, 10, V2 { x: 0.0, y: 0.0 }, 50.0);
+        level.obstacles.push(blocker);
+        assert!(read_back(&level).is_err());
+    }
+
+    #[test]
+    fn bad_levels_are_errors() {
+        assert!(parse("").is_err());
+        assert!(parse("arena = 200 200\n").is_err());
+        assert!(parse("level =\n").is_err());
+        assert!(parse("level = a\nspeed = 3\n").is_err());
+        assert!(parse("level = a\narena 200 200\n").is_err());
+        assert!(parse("level = a\narena = 200\n").is_err());
+        assert!(parse("level = a\narena = 50 200\n").is_err());
+        assert!(parse("level = a\nball = 10 350 900 300 15\n").is_err());
+        assert!(parse("level = a\nserve = 10 10 0 0\n").is_err());
+        assert!(parse("level = a\nwin = bricks\n").is_err());
+        assert!(parse("level = a\nbrick = 10 10 20 10 0\n").is_err());
+        assert!(parse("level = a\narena = 200 200\nblock = 190 10 20 10\n").is_err());
+        assert!(parse("level = a\nblock = 1000 10 20 10\n").is_err());
+        assert!(parse("level = a\nbrick = 10 -5 20 10 1\n").is_err());
+        assert!(parse("level = a\nblocker = 10 10 20 10 0 0 50\n").is_err());
+    }
+}
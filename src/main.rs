@@ -1,15 +1,50 @@
 #![windows_subsystem = "windows"]
 
+use crate::ai::*;
+use crate::audio::*;
+use crate::bindings::*;
+use crate::daily::*;
+use crate::difficulty::*;
 use crate::game::*;
+use crate::gamepad::*;
+use crate::level::*;
+use crate::menu::*;
+use crate::obstacle::*;
+use crate::recording::*;
+use crate::replay::*;
+use crate::sample::*;
+use crate::savestate::*;
+use crate::title::*;
+use crate::tracker::*;
 use crate::window::*;
-use bytes::BufMut;
+use bytes::{Buf, BufMut, BytesMut};
+use std::path::{Path, PathBuf};
 use std::thread::sleep;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use windows::core::Result;
 use windows::Win32::Media::timeBeginPeriod;
 
+mod ai;
+mod audio;
+mod bindings;
+mod daily;
+mod difficulty;
+mod font;
 mod game;
+mod gamepad;
 mod handle;
+mod level;
+mod menu;
+mod obstacle;
+mod powerup;
+mod recording;
+mod replay;
+mod rng;
+mod sample;
+mod savestate;
+mod synth;
+mod title;
+mod tracker;
 mod window;
 
 // NOTE(Fermin): Do we need a V2 with generic types?
@@ -18,6 +53,22 @@ struct V2 {
     x: f32,
     y: f32,
 }
+impl V2 {
+    fn length(self) -> f32 {
+        (self.x * self.x + self.y * self.y).sqrt()
+    }
+    fn dot(self, a: V2) -> f32 {
+        self.x * a.x + self.y * a.y
+    }
+    fn normalized(self) -> V2 {
+        let length = self.length();
+        if length > 0.0 {
+            self * (1.0 / length)
+        } else {
+            V2 { x: 0.0, y: 0.0 }
+        }
+    }
+}
 impl std::ops::Add<V2> for V2 {
     type Output = V2;
 
@@ -28,6 +79,16 @@ impl std::ops::Add<V2> for V2 {
         }
     }
 }
+impl std::ops::Sub<V2> for V2 {
+    type Output = V2;
+
+    fn sub(self, a: V2) -> V2 {
+        V2 {
+            x: self.x - a.x,
+            y: self.y - a.y,
+        }
+    }
+}
 impl std::ops::AddAssign<V2> for V2 {
     fn add_assign(&mut self, a: V2) {
         self.x += a.x;
@@ -51,10 +112,32 @@ impl std::ops::MulAssign<f32> for V2 {
     }
 }
 
+#[derive(Copy, Clone)]
 struct GameButtonState {
-    // TODO(Fermin): Half transitions
     ended_down: bool,
+    half_transition_count: u32,
+}
+impl GameButtonState {
+    fn new() -> Self {
+        Self {
+            ended_down: false,
+            half_transition_count: 0,
+        }
+    }
+    // NOTE(Fermin): A tap shorter than a frame still counts, it shows up as
+    // two half transitions with ended_down back to false
+    fn was_pressed(&self) -> bool {
+        self.half_transition_count > 1 || (self.half_transition_count == 1 && self.ended_down)
+    }
+    #[allow(dead_code)]
+    fn was_released(&self) -> bool {
+        self.half_transition_count > 1 || (self.half_transition_count == 1 && !self.ended_down)
+    }
+    fn is_held(&self) -> bool {
+        self.ended_down
+    }
 }
+#[derive(Copy, Clone)]
 struct InputButtons {
     move_up: GameButtonState,
     move_down: GameButtonState,
@@ -64,31 +147,99 @@ struct InputButtons {
     start: GameButtonState,
     jump: GameButtonState,
 }
+impl InputButtons {
+    fn new() -> Self {
+        Self {
+            move_up: GameButtonState::new(),
+            move_down: GameButtonState::new(),
+            move_left: GameButtonState::new(),
+            move_right: GameButtonState::new(),
+            back: GameButtonState::new(),
+            start: GameButtonState::new(),
+            jump: GameButtonState::new(),
+        }
+    }
+    fn all(&self) -> [&GameButtonState; 7] {
+        [
+            &self.move_up,
+            &self.move_down,
+            &self.move_left,
+            &self.move_right,
+            &self.back,
+            &self.start,
+            &self.jump,
+        ]
+    }
+    fn all_mut(&mut self) -> [&mut GameButtonState; 7] {
+        [
+            &mut self.move_up,
+            &mut self.move_down,
+            &mut self.move_left,
+            &mut self.move_right,
+            &mut self.back,
+            &mut self.start,
+            &mut self.jump,
+        ]
+    }
+}
+// NOTE(Fermin): Player one uses buttons, the other key sets only move
+#[derive(Copy, Clone)]
 struct KeyboardInput {
     buttons: InputButtons,
+    other_players: [InputButtons; 3],
 }
 impl KeyboardInput {
     fn new() -> Self {
         Self {
-            buttons: InputButtons {
-                move_up: GameButtonState { ended_down: false },
-                move_down: GameButtonState { ended_down: false },
-                move_left: GameButtonState { ended_down: false },
-                move_right: GameButtonState { ended_down: false },
-                back: GameButtonState { ended_down: false },
-                start: GameButtonState { ended_down: false },
-                jump: GameButtonState { ended_down: false },
-            },
+            buttons: InputButtons::new(),
+            other_players: [InputButtons::new(); 3],
+        }
+    }
+    fn player_buttons(&self, player: usize) -> &InputButtons {
+        if player == 0 {
+            &self.buttons
+        } else {
+            &self.other_players[player - 1]
+        }
+    }
+    fn player_buttons_mut(&mut self, player: usize) -> &mut InputButtons {
+        if player == 0 {
+            &mut self.buttons
+        } else {
+            &mut self.other_players[player - 1]
+        }
+    }
+}
+// NOTE(Fermin): Sticks go from -1 to 1 on each axis with the deadzone already
+// removed, y grows downwards like the screen
+#[derive(Copy, Clone)]
+struct ControllerInput {
+    is_connected: bool,
+    left_stick: V2,
+    right_stick: V2,
+    buttons: InputButtons,
+}
+impl ControllerInput {
+    fn new() -> Self {
+        Self {
+            is_connected: false,
+            left_stick: V2 { x: 0.0, y: 0.0 },
+            right_stick: V2 { x: 0.0, y: 0.0 },
+            buttons: InputButtons::new(),
         }
     }
 }
 
+#[derive(Copy, Clone)]
 pub struct GameInput {
-    // TODO(Fermin): Controller\Keyboard support
     cursor_pos: V2,
     dt_for_frame: f32,
     keyboard: KeyboardInput,
+    controllers: [ControllerInput; MAX_CONTROLLERS],
     mouse_buttons: [GameButtonState; 2],
+    // NOTE(Fermin): Raw platform key code of the last key that went down this
+    // frame, for screens that need keys instead of buttons
+    key_pressed: Option<u16>,
 }
 impl GameInput {
     fn new() -> Self {
@@ -96,30 +247,389 @@ impl GameInput {
             cursor_pos: V2 { x: 0.0, y: 0.0 },
             dt_for_frame: 0.0,
             keyboard: KeyboardInput::new(),
-            mouse_buttons: [
-                GameButtonState { ended_down: false },
-                GameButtonState { ended_down: false },
-            ],
+            controllers: [ControllerInput::new(); MAX_CONTROLLERS],
+            mouse_buttons: [GameButtonState::new(), GameButtonState::new()],
+            key_pressed: None,
         }
     }
+    // NOTE(Fermin): Buttons keep their ended_down state from the previous frame,
+    // only the transitions are per frame
+    fn prepare_for_new_frame(&mut self) {
+        for button in self.buttons_mut() {
+            button.half_transition_count = 0;
+        }
+        self.key_pressed = None;
+    }
+    // NOTE(Fermin): Every button in the input, the order is part of the
+    // replay format
+    fn buttons(&self) -> Vec<&GameButtonState> {
+        let mut result = Vec::from(self.keyboard.buttons.all());
+        for other in &self.keyboard.other_players {
+            result.extend(other.all());
+        }
+        result.extend(self.mouse_buttons.iter());
+        for controller in &self.controllers {
+            result.extend(controller.buttons.all());
+        }
+        result
+    }
+    fn buttons_mut(&mut self) -> Vec<&mut GameButtonState> {
+        let mut result = Vec::from(self.keyboard.buttons.all_mut());
+        for other in &mut self.keyboard.other_players {
+            result.extend(other.all_mut());
+        }
+        result.extend(self.mouse_buttons.iter_mut());
+        for controller in &mut self.controllers {
+            result.extend(controller.buttons.all_mut());
+        }
+        result
+    }
+    // NOTE(Fermin): Every key set and every connected controller pressing the
+    // same buttons, for code that doesn't care who pressed them
+    fn combined_buttons(&self) -> InputButtons {
+        let mut result = self.keyboard.buttons;
+        for other in &self.keyboard.other_players {
+            combine_buttons(&mut result, other);
+        }
+        for controller in self.controllers.iter().filter(|c| c.is_connected) {
+            combine_buttons(&mut result, &controller.buttons);
+        }
+        result
+    }
+    // NOTE(Fermin): Buttons of one player, its key set and the controller in
+    // its slot
+    fn player_buttons(&self, player: usize) -> InputButtons {
+        let mut result = *self.keyboard.player_buttons(player);
+        if self.controllers[player].is_connected {
+            combine_buttons(&mut result, &self.controllers[player].buttons);
+        }
+        result
+    }
+}
+fn combine_buttons(result: &mut InputButtons, other: &InputButtons) {
+    for (button, other_button) in result.all_mut().into_iter().zip(other.all()) {
+        button.ended_down |= other_button.ended_down;
+        button.half_transition_count += other_button.half_transition_count;
+    }
 }
 
+#[derive(Clone)]
 enum GameStates {
+    Title(TitleScreen),
     Play,
     DeathScene,
+    Controls(ControlsMenu),
+    LevelClear,
 }
+#[derive(Clone)]
 pub struct GameState {
     state: GameStates,
+    pause_seconds_elapsed: f32,
+}
+impl Persist for GameState {
+    fn write(&self, out: &mut BytesMut) {
+        let state: u8 = match self.state {
+            GameStates::Play => 0,
+            GameStates::DeathScene => 1,
+            GameStates::Controls(_) => 2,
+            GameStates::Title(_) => 3,
+            GameStates::LevelClear => 4,
+        };
+        out.put_u8(state);
+        self.pause_seconds_elapsed.write(out);
+    }
+    fn read(buf: &mut &[u8]) -> std::io::Result<Self> {
+        ensure_remaining(buf, 1)?;
+        let state = match buf.get_u8() {
+            0 => GameStates::Play,
+            1 => GameStates::DeathScene,
+            // NOTE(Fermin): Only the screen is restored, not where the cursor was
+            2 => GameStates::Controls(ControlsMenu::new()),
+            // NOTE(Fermin): A running demo is not saved, it starts over
+            3 => GameStates::Title(TitleScreen::new()),
+            4 => GameStates::LevelClear,
+            value => return Err(invalid_data(&format!("invalid game state {}", value))),
+        };
+        Ok(Self {
+            state,
+            pause_seconds_elapsed: f32::read(buf)?,
+        })
+    }
+}
+
+struct CommandLineArgs {
+    seed: Option<u64>,
+    replay_path: Option<PathBuf>,
+    // NOTE(Fermin): Layouts are built once the buffer size is known
+    arena: Option<String>,
+    has_levels: bool,
+    daily: Option<Date>,
+    audio: AudioBackend,
+    assets: PathBuf,
+    volume: f32,
+    music_volume: f32,
+    audio_only: bool,
+    settings: GameSettings,
+}
+impl CommandLineArgs {
+    fn parse(mut args: impl Iterator<Item = String>) -> Self {
+        let mut result = Self {
+            seed: None,
+            replay_path: None,
+            arena: None,
+            has_levels: false,
+            daily: None,
+            audio: AudioBackend::Auto,
+            assets: PathBuf::from(ASSETS_PATH),
+            volume: 1.0,
+            music_volume: 0.6,
+            audio_only: false,
+            settings: GameSettings::new(),
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => {
+                    let value = args.next().expect("Missing value for --seed");
+                    result.seed = Some(value.parse().expect("Invalid value for --seed"));
+                }
+                "--mode" => {
+                    let value = args.next().expect("Missing value for --mode");
+                    result.settings.mode = GameMode::from_name(&value).unwrap_or_else(|| {
+                        let names: Vec<&str> = GAME_MODES.iter().map(|mode| mode.name()).collect();
+                        panic!(
+                            "Invalid value for --mode, expected one of {}",
+                            names.join(", ")
+                        )
+                    });
+                }
+                "--players" => {
+                    let value = args.next().expect("Missing value for --players");
+                    let paddles = &mut result.settings.paddles;
+                    if value.len() != paddles.len() {
+                        panic!("Invalid value for --players, expected one H or A per paddle");
+                    }
+                    for (control, c) in paddles.iter_mut().zip(value.chars()) {
+                        *control = match c.to_ascii_uppercase() {
+                            'H' => PaddleControl::Human,
+                            'A' => PaddleControl::Ai,
+                            _ => panic!("Invalid value for --players, expected H or A"),
+                        };
+                    }
+                }
+                "--score-to" => {
+                    let value = args.next().expect("Missing value for --score-to");
+                    let value: u32 = value.parse().expect("Invalid value for --score-to");
+                    result.settings.target_score = value.max(1);
+                }
+                "--ai" => {
+                    let value = args.next().expect("Missing value for --ai");
+                    result.settings.ai_level = AiLevel::from_name(&value).unwrap_or_else(|| {
+                        let names: Vec<&str> = AI_LEVELS.iter().map(|level| level.name()).collect();
+                        panic!(
+                            "Invalid value for --ai, expected one of {}",
+                            names.join(", ")
+                        )
+                    });
+                }
+                "--balls" => {
+                    let value = args.next().expect("Missing value for --balls");
+                    let value: u32 = value.parse().expect("Invalid value for --balls");
+                    result.settings.multiball.start_balls = value.clamp(1, MAX_BALLS_LIMIT);
+                }
+                "--multiball-every" => {
+                    let value = args.next().expect("Missing value for --multiball-every");
+                    result.settings.multiball.milestone =
+                        value.parse().expect("Invalid value for --multiball-every");
+                }
+                "--max-balls" => {
+                    let value = args.next().expect("Missing value for --max-balls");
+                    let value: u32 = value.parse().expect("Invalid value for --max-balls");
+                    result.settings.multiball.max_balls = value.clamp(1, MAX_BALLS_LIMIT);
+                }
+                "--balls-to-lose" => {
+                    let value = args.next().expect("Missing value for --balls-to-lose");
+                    result.settings.multiball.balls_to_lose =
+                        value.parse().expect("Invalid value for --balls-to-lose");
+                }
+                "--arena" => {
+                    let value = args.next().expect("Missing value for --arena");
+                    if !ARENAS.contains(&value.as_str()) {
+                        panic!(
+                            "Invalid value for --arena, expected one of {}",
+                            ARENAS.join(", ")
+                        );
+                    }
+                    result.arena = Some(value);
+                }
+                "--levels" => {
+                    let value = args.next().expect("Missing value for --levels");
+                    result.settings.levels = load_levels(Path::new(&value))
+                        .unwrap_or_else(|error| panic!("Error loading levels: {}", error));
+                    result.has_levels = true;
+                }
+                "--difficulty" => {
+                    // NOTE(Fermin): A preset name or a curves file
+                    let value = args.next().expect("Missing value for --difficulty");
+                    result.settings.difficulty = difficulty_preset(&value).unwrap_or_else(|| {
+                        load_difficulty(Path::new(&value)).unwrap_or_else(|error| {
+                            let names: Vec<&str> =
+                                DIFFICULTY_PRESETS.iter().map(|(name, _)| *name).collect();
+                            panic!(
+                                "Invalid value for --difficulty, expected one of {} or a curves file: {}",
+                                names.join(", "),
+                                error
+                            )
+                        })
+                    });
+                }
+                "--daily" => result.daily = Some(Date::today()),
+                "--daily-date" => {
+                    let value = args.next().expect("Missing value for --daily-date");
+                    result.daily = Some(Date::parse(&value).unwrap_or_else(|| {
+                        panic!("Invalid value for --daily-date, expected YYYY-MM-DD")
+                    }));
+                }
+                "--audio" => {
+                    let value = args.next().expect("Missing value for --audio");
+                    result.audio = match value.as_str() {
+                        "auto" => AudioBackend::Auto,
+                        "null" => AudioBackend::Null,
+                        _ => panic!("Invalid value for --audio, expected auto or null"),
+                    };
+                }
+                "--audio-wav" => {
+                    // NOTE(Fermin): Writes the mix to a file instead of playing it
+                    let value = args.next().expect("Missing value for --audio-wav");
+                    result.audio = AudioBackend::WavFile(PathBuf::from(value));
+                }
+                "--volume" => {
+                    let value = args.next().expect("Missing value for --volume");
+                    let value: f32 = parse_number(&value, "--volume VALUE")
+                        .unwrap_or_else(|error| panic!("Invalid value for --volume: {}", error));
+                    result.volume = value.clamp(0.0, 1.0);
+                }
+                "--music-volume" => {
+                    let value = args.next().expect("Missing value for --music-volume");
+                    let value: f32 =
+                        parse_number(&value, "--music-volume VALUE").unwrap_or_else(|error| {
+                            panic!("Invalid value for --music-volume: {}", error)
+                        });
+                    result.music_volume = value.clamp(0.0, 1.0);
+                }
+                "--assets" => {
+                    let value = args.next().expect("Missing value for --assets");
+                    result.assets = PathBuf::from(value);
+                }
+                "--audio-only" => result.audio_only = true,
+                "--power-ups" => result.settings.power_ups = true,
+                "--mouse" => result.settings.mouse.enabled = true,
+                "--mouse-sensitivity" => {
+                    let value = args.next().expect("Missing value for --mouse-sensitivity");
+                    let value: f32 = parse_number(&value, "--mouse-sensitivity VALUE")
+                        .unwrap_or_else(|error| {
+                            panic!("Invalid value for --mouse-sensitivity: {}", error)
+                        });
+                    result.settings.mouse.sensitivity = value.clamp(
+                        *MOUSE_SENSITIVITY_RANGE.start(),
+                        *MOUSE_SENSITIVITY_RANGE.end(),
+                    );
+                }
+                "--mouse-smoothing" => {
+                    let value = args.next().expect("Missing value for --mouse-smoothing");
+                    let value: f32 = parse_number(&value, "--mouse-smoothing VALUE")
+                        .unwrap_or_else(|error| {
+                            panic!("Invalid value for --mouse-smoothing: {}", error)
+                        });
+                    result.settings.mouse.smoothing =
+                        value.clamp(*MOUSE_SMOOTHING_RANGE.start(), *MOUSE_SMOOTHING_RANGE.end());
+                }
+                "--replay" => {
+                    let value = args.next().expect("Missing value for --replay");
+                    result.replay_path = Some(PathBuf::from(value));
+                }
+                _ => println!("Unknown argument: {}", arg),
+            }
+        }
+        if result.daily.is_some()
+            && (result.seed.is_some() || result.has_levels || result.arena.is_some())
+        {
+            panic!("The daily challenge picks its own seed and arena, it can't be combined with --seed, --levels or --arena");
+        }
+        if result.has_levels && result.arena.is_some() {
+            panic!("--arena and --levels can't be used together, levels bring their own arena");
+        }
+        result
+    }
 }
 
 fn main() -> Result<()> {
+    let args = CommandLineArgs::parse(std::env::args().skip(1));
+    let mut replay_viewer = args.replay_path.map(|path| {
+        println!("Replaying {}", path.display());
+        ReplayViewer::new(Replay::load(&path).expect("Error loading replay"))
+    });
+    let seed = match &replay_viewer {
+        Some(viewer) => viewer.header().seed,
+        None => args.daily.map(daily_seed).or(args.seed).unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("System time is before the unix epoch")
+                .as_nanos() as u64
+        }),
+    };
+    println!("Seed: {}", seed);
+
     let mut window = Window::new(435, 460)?;
     let mut input = GameInput::new();
-    let mut game_memory = GameMemory::new();
+    let settings = match &replay_viewer {
+        Some(viewer) => viewer.header().settings.clone(),
+        None => {
+            let mut settings = args.settings;
+            if let Some(date) = args.daily {
+                println!("Daily challenge {}", date.text());
+                settings = daily_settings(date, settings.mouse);
+            }
+            if let Some(arena) = &args.arena {
+                let mut level = Level::new();
+                let (width, height) = level.arena_size(window.buffer.width, window.buffer.height);
+                level.obstacles = arena_layout(arena, width, height)
+                    .expect("Arena names are checked when parsing");
+                settings.levels = vec![level];
+            }
+            settings
+        }
+    };
+    let mut game_memory = GameMemory::new(seed, settings.clone());
+    game_memory.set_daily_best(settings.daily.and_then(best_daily_result));
     let mut game_state = GameState {
-        state: GameStates::Play,
+        state: GameStates::Title(TitleScreen::new()),
+        pause_seconds_elapsed: 0.0,
     };
+    if let Some(viewer) = &replay_viewer {
+        let header = viewer.header();
+        if header.buffer_width != window.buffer.width
+            || header.buffer_height != window.buffer.height
+        {
+            println!(
+                "Replay was recorded on a {}x{} buffer, playback may diverge",
+                header.buffer_width, header.buffer_height
+            );
+        }
+    }
+    let mut bindings = KeyBindings::load_or_default(Path::new(BINDINGS_PATH));
+    let mut gamepads = Gamepads::new();
+    let mut input_loop = InputLoop::new();
+    // NOTE(Fermin): The whole session is recorded, F6 writes it to disk.
+    // Looping inputs rewinds the game, so it ends the recording.
+    let mut replay = Some(Replay::new(seed, &settings, &bindings, &window.buffer));
+    let mut save_state_slot: usize = 0;
+    // NOTE(Fermin): Loading a state or looping inputs can play the same daily
+    // game again for a better score, after that none of its results count
+    let mut daily_tainted = false;
     let target_seconds_per_frame: f32 = 1.0 / window.refresh_rate as f32;
+    let mut audio = Audio::new(&args.audio, &args.assets);
+    audio.mixer.master_volume = args.volume;
+    audio.mixer.music_volume = args.music_volume;
 
     // NOTE(Fermin): Set the Windows scheduler granularity to 1ms,
     // should this be in window.rs????
@@ -133,13 +643,128 @@ fn main() -> Result<()> {
 
         input.dt_for_frame = target_seconds_per_frame;
 
-        update_and_render(
-            &mut game_memory,
-            &mut window.buffer,
-            &input,
-            &mut game_state,
-        );
-        window.win32_process_pending_messages(&mut input);
+        if let Some(viewer) = &mut replay_viewer {
+            viewer.update(&mut game_memory, &mut game_state, &mut window.buffer);
+        } else {
+            let frame_input = input_loop.frame_input(
+                &input,
+                &mut game_memory,
+                &mut game_state,
+                &mut window.buffer,
+            );
+            if let Some(replay) = &mut replay {
+                replay.record(frame_input);
+            }
+            update_and_render(
+                &mut game_memory,
+                &mut window.buffer,
+                frame_input,
+                &mut game_state,
+                &mut bindings,
+            );
+
+            if bindings.changed {
+                bindings.changed = false;
+                if let Err(error) = bindings.save(Path::new(BINDINGS_PATH)) {
+                    println!("Error saving bindings: {}", error);
+                }
+            }
+        }
+        audio.play(&game_memory.take_sounds());
+        // NOTE(Fermin): Replays and seeks play games that already happened,
+        // only live games played start to end count for the daily results
+        if let Some((date, score)) = game_memory.take_daily_result() {
+            if daily_tainted {
+                println!("Daily result not saved, the game was loaded or looped");
+            } else if replay_viewer.is_none() {
+                if let Err(error) = save_daily_result(date, score) {
+                    println!("Error saving daily result: {}", error);
+                }
+            }
+        }
+        audio.mixer.set_music_mood(MusicMood {
+            playing: matches!(game_state.state, GameStates::Play),
+            ..game_memory.music_mood()
+        });
+        audio.set_music(match game_state.state {
+            GameStates::Title(_) => MusicTrack::Title,
+            _ => MusicTrack::Game,
+        });
+        if args.audio_only {
+            let cues =
+                matches!(game_state.state, GameStates::Play).then(|| game_memory.audio_cues());
+            audio.update_cues(cues, target_seconds_per_frame);
+        }
+        audio.update(target_seconds_per_frame);
+        input.prepare_for_new_frame();
+        window.win32_process_pending_messages(&mut input, &bindings);
+        gamepads.update(&mut input, target_seconds_per_frame);
+
+        for command in window.commands.drain(..) {
+            match (command, &mut replay_viewer) {
+                (PlatformCommand::ToggleInputLoop, None) => {
+                    input_loop.toggle(&game_memory, &game_state);
+                    daily_tainted = true;
+                    if replay.take().is_some() {
+                        println!("Input loop started, session replay discarded");
+                    }
+                }
+                (PlatformCommand::SaveReplay, None) => match &replay {
+                    Some(replay) => {
+                        let path = PathBuf::from(format!(
+                            "replays/poong_{}_{}.prpl",
+                            seed,
+                            replay.frames.len()
+                        ));
+                        match replay.save(&path) {
+                            Ok(()) => println!("Replay saved to {}", path.display()),
+                            Err(error) => println!("Error saving replay: {}", error),
+                        }
+                    }
+                    None => println!("No replay to save"),
+                },
+                (PlatformCommand::SelectSaveStateSlot(slot), None) => {
+                    save_state_slot = slot;
+                    println!("Save state slot {}", slot + 1);
+                }
+                (PlatformCommand::QuickSave, None) => {
+                    match save_state(save_state_slot, &game_memory, &game_state) {
+                        Ok(path) => println!("State saved to {}", path.display()),
+                        Err(error) => println!("Error saving state: {}", error),
+                    }
+                }
+                (PlatformCommand::QuickLoad, None) => match load_state(save_state_slot) {
+                    Ok((loaded_memory, loaded_state)) => {
+                        game_memory = loaded_memory;
+                        daily_tainted = true;
+                        game_memory.set_daily_best(game_memory.daily().and_then(best_daily_result));
+                        game_state = loaded_state;
+                        clear_screen(&mut window.buffer);
+                        println!("State loaded from slot {}", save_state_slot + 1);
+                        if replay.take().is_some() {
+                            println!("State loaded, session replay discarded");
+                        }
+                    }
+                    Err(error) => println!("Error loading state: {}", error),
+                },
+                (PlatformCommand::ReplayTogglePause, Some(viewer)) => viewer.toggle_pause(),
+                (PlatformCommand::ReplayStep, Some(viewer)) => viewer.step(),
+                (PlatformCommand::ReplayFastForward, Some(viewer)) => {
+                    viewer.toggle_fast_forward();
+                }
+                (PlatformCommand::ReplaySeekBack, Some(viewer)) => {
+                    viewer.seek_back(&mut game_memory, &mut game_state, &mut window.buffer);
+                    // NOTE(Fermin): Seeking replays many frames at once, don't play them
+                    game_memory.take_sounds();
+                }
+                (PlatformCommand::ReplaySeekForward, Some(viewer)) => {
+                    viewer.seek_forward(&mut game_memory, &mut game_state, &mut window.buffer);
+                    // NOTE(Fermin): Seeking replays many frames at once, don't play them
+                    game_memory.take_sounds();
+                }
+                _ => (),
+            }
+        }
 
         let target_ms_per_frame = target_seconds_per_frame * 1000.0;
         if frame_start_instant.elapsed().as_millis() < target_ms_per_frame as u128 {
@@ -158,3 +783,106 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn button(ended_down: bool, half_transition_count: u32) -> GameButtonState {
+        GameButtonState {
+            ended_down,
+            half_transition_count,
+        }
+    }
+
+    #[test]
+    fn tap_inside_one_frame_is_pressed_and_released() {
+        let tap = button(false, 2);
+        assert!(tap.was_pressed());
+        assert!(tap.was_released());
+        assert!(!tap.is_held());
+
+        // NOTE(Fermin): Up, down and up again still ends down
+        let tap_and_hold = button(true, 3);
+        assert!(tap_and_hold.was_pressed());
+        assert!(tap_and_hold.was_released());
+        assert!(tap_and_hold.is_held());
+    }
+
+    #[test]
+    fn single_transitions() {
+        let press = button(true, 1);
+        assert!(press.was_pressed());
+        assert!(!press.was_released());
+
+        let release = button(false, 1);
+        assert!(!release.was_pressed());
+        assert!(release.was_released());
+
+        for (ended_down, held) in [(true, true), (false, false)] {
+            let state = button(ended_down, 0);
+            assert!(!state.was_pressed());
+            assert!(!state.was_released());
+            assert_eq!(state.is_held(), held);
+        }
+    }
+
+    fn parse_args(args: &[&str]) -> CommandLineArgs {
+        CommandLineArgs::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn mouse_arguments_are_clamped() {
+        let args = parse_args(&["--mouse-sensitivity", "100", "--mouse-smoothing", "-1"]);
+        assert_eq!(
+            args.settings.mouse.sensitivity,
+            *MOUSE_SENSITIVITY_RANGE.end()
+        );
+        assert_eq!(
+            args.settings.mouse.smoothing,
+            *MOUSE_SMOOTHING_RANGE.start()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "--mouse-sensitivity")]
+    fn nan_mouse_sensitivity_is_rejected() {
+        parse_args(&["--mouse-sensitivity", "nan"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "--mouse-smoothing")]
+    fn infinite_mouse_smoothing_is_rejected() {
+        parse_args(&["--mouse-smoothing", "inf"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "--volume")]
+    fn nan_volume_is_rejected() {
+        parse_args(&["--volume", "NaN"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "--music-volume")]
+    fn infinite_music_volume_is_rejected() {
+        parse_args(&["--music-volume", "-inf"]);
+    }
+
+    #[test]
+    fn volumes_are_clamped() {
+        let args = parse_args(&["--volume", "2", "--music-volume", "-0.5"]);
+        assert_eq!(args.volume, 1.0);
+        assert_eq!(args.music_volume, 0.0);
+    }
+
+    #[test]
+    fn new_frame_keeps_held_buttons_down() {
+        let mut input = GameInput::new();
+        input.keyboard.buttons.jump = button(true, 1);
+        input.mouse_buttons[0] = button(false, 2);
+        input.prepare_for_new_frame();
+        assert!(input.keyboard.buttons.jump.is_held());
+        assert!(!input.keyboard.buttons.jump.was_pressed());
+        assert!(!input.mouse_buttons[0].was_released());
+    }
+}
@@ -0,0 +1,217 @@
+use crate::bindings::*;
+use crate::game::*;
+use crate::window::*;
+use crate::*;
+use windows::Win32::UI::Input::KeyboardAndMouse::VK_ESCAPE;
+
+const MENU_X: f32 = 30.0;
+const MENU_LINE_HEIGHT: f32 = 14.0;
+// NOTE(Fermin): Rows after the actions
+const MOUSE_ROWS: usize = 3;
+const MOUSE_SENSITIVITY_STEP: f32 = 0.25;
+const MOUSE_SMOOTHING_STEP: f32 = 0.05;
+
+#[derive(Clone)]
+pub struct ControlsMenu {
+    pub selected: usize,
+    capturing: bool,
+    // NOTE(Fermin): Key waiting for confirmation and the action that has it
+    conflict: Option<(u16, Action)>,
+    message: String,
+}
+impl ControlsMenu {
+    pub fn new() -> Self {
+        Self {
+            selected: 0,
+            capturing: false,
+            conflict: None,
+            message: String::new(),
+        }
+    }
+}
+
+// NOTE(Fermin): Returns true when the player leaves the screen
+pub fn update_and_render_controls(
+    menu: &mut ControlsMenu,
+    input: &GameInput,
+    bindings: &mut KeyBindings,
+    mouse: &mut MouseSettings,
+    buffer: &mut Win32OffscreenBuffer,
+) -> bool {
+    let buttons = &input.combined_buttons();
+    let row_count = ACTIONS.len() + MOUSE_ROWS;
+    let action = ACTIONS.get(menu.selected).copied();
+
+    if let (true, Some(action)) = (menu.capturing, action) {
+        if let Some(key) = input.key_pressed {
+            menu.capturing = false;
+            if key == VK_ESCAPE.0 {
+                menu.message.clear();
+            } else {
+                match bindings.action_for_key(key) {
+                    Some(other) if other == action => {
+                        menu.message = format!("{} ALREADY USES {}", action.label(), key_name(key));
+                    }
+                    Some(other) if other.is_required() && bindings.keys(other).len() == 1 => {
+                        menu.message =
+                            format!("{} IS THE ONLY KEY FOR {}", key_name(key), other.label());
+                    }
+                    Some(other) => {
+                        menu.conflict = Some((key, other));
+                        menu.message.clear();
+                    }
+                    None => {
+                        bindings.bind(action, key);
+                        menu.message.clear();
+                    }
+                }
+            }
+        }
+    } else if let (Some((key, _)), Some(action)) = (menu.conflict, action) {
+        if buttons.start.was_pressed() {
+            bindings.bind(action, key);
+            menu.conflict = None;
+        } else if buttons.back.was_pressed() {
+            menu.conflict = None;
+        }
+    } else {
+        if buttons.back.was_pressed() {
+            return true;
+        }
+        if buttons.move_up.was_pressed() {
+            menu.selected = (menu.selected + row_count - 1) % row_count;
+            menu.message.clear();
+        }
+        if buttons.move_down.was_pressed() {
+            menu.selected = (menu.selected + 1) % row_count;
+            menu.message.clear();
+        }
+        match ACTIONS.get(menu.selected).copied() {
+            Some(action) => {
+                if buttons.start.was_pressed() {
+                    menu.capturing = true;
+                    menu.message.clear();
+                }
+                if buttons.jump.was_pressed() {
+                    if action.is_required() {
+                        menu.message = format!("{} NEEDS A KEY", action.label());
+                    } else {
+                        bindings.clear(action);
+                    }
+                }
+            }
+            None => {
+                let mut step = 0.0;
+                if buttons.move_left.was_pressed() {
+                    step -= 1.0;
+                }
+                if buttons.move_right.was_pressed() || buttons.start.was_pressed() {
+                    step += 1.0;
+                }
+                match menu.selected - ACTIONS.len() {
+                    0 => mouse.enabled ^= step != 0.0,
+                    1 => {
+                        mouse.sensitivity = (mouse.sensitivity + step * MOUSE_SENSITIVITY_STEP)
+                            .clamp(
+                                *MOUSE_SENSITIVITY_RANGE.start(),
+                                *MOUSE_SENSITIVITY_RANGE.end(),
+                            );
+                    }
+                    _ => {
+                        mouse.smoothing = (mouse.smoothing + step * MOUSE_SMOOTHING_STEP)
+                            .clamp(*MOUSE_SMOOTHING_RANGE.start(), *MOUSE_SMOOTHING_RANGE.end());
+                    }
+                }
+            }
+        }
+    }
+
+    draw_text(
+        "CONTROLS",
+        &V2 { x: MENU_X, y: 30.0 },
+        2,
+        &NEON_MINT,
+        buffer,
+    );
+    let mut rows: Vec<(String, String)> = ACTIONS
+        .iter()
+        .map(|row_action| (row_action.label(), bindings.key_names(*row_action)))
+        .collect();
+    rows.push((
+        "MOUSE CONTROL".to_string(),
+        if mouse.enabled { "ON" } else { "OFF" }.to_string(),
+    ));
+    rows.push((
+        "MOUSE SENSITIVITY".to_string(),
+        format!("{:.2}", mouse.sensitivity),
+    ));
+    rows.push((
+        "MOUSE SMOOTHING".to_string(),
+        format!("{:.2}", mouse.smoothing),
+    ));
+    for (index, (label, value)) in rows.iter().enumerate() {
+        let y = 70.0 + index as f32 * MENU_LINE_HEIGHT;
+        let color = if index == menu.selected {
+            &NEON_YELLOW
+        } else {
+            &BABY_PINK
+        };
+        if index == menu.selected {
+            draw_text(
+                ">",
+                &V2 {
+                    x: MENU_X - 12.0,
+                    y,
+                },
+                1,
+                color,
+                buffer,
+            );
+        }
+        draw_text(label, &V2 { x: MENU_X, y }, 1, color, buffer);
+        draw_text(
+            value,
+            &V2 {
+                x: MENU_X + 120.0,
+                y,
+            },
+            1,
+            color,
+            buffer,
+        );
+    }
+
+    let status_p = V2 {
+        x: MENU_X,
+        y: 70.0 + (row_count as f32 + 1.0) * MENU_LINE_HEIGHT,
+    };
+    let status = if let (true, Some(action)) = (menu.capturing, action) {
+        format!("PRESS A KEY FOR {}, ESCAPE CANCELS", action.label())
+    } else if let Some((key, other)) = menu.conflict {
+        format!("{} IS USED BY {}", key_name(key), other.label())
+    } else {
+        menu.message.clone()
+    };
+    draw_text(&status, &status_p, 1, &RED, buffer);
+    if menu.conflict.is_some() {
+        let confirm_p = status_p
+            + V2 {
+                x: 0.0,
+                y: MENU_LINE_HEIGHT,
+            };
+        draw_text("START MOVES IT, BACK CANCELS", &confirm_p, 1, &RED, buffer);
+    }
+
+    draw_text(
+        "START: ADD KEY  JUMP: CLEAR  LEFT/RIGHT: CHANGE  BACK: DONE",
+        &V2 {
+            x: MENU_X,
+            y: buffer.height as f32 - 40.0,
+        },
+        1,
+        &NEON_MINT,
+        buffer,
+    );
+
+    false
+}
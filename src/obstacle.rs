@@ -0,0 +1,242 @@
+use crate::game::*;
+use crate::savestate::*;
+use crate::*;
+use bytes::BytesMut;
+use std::io::Result;
+
+// NOTE(Fermin): Points for every brick destroyed, before the multiplier
+pub const BRICK_POINTS: u32 = 5;
+const BRICK_LONG_SIDE: i32 = 28;
+const BRICK_SHORT_SIDE: i32 = 10;
+const BRICK_GAP: i32 = 2;
+const BLOCK_SIZE: i32 = 12;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum ObstacleKind {
+    // NOTE(Fermin): Breaks after its hit points run out
+    Brick,
+    // NOTE(Fermin): Never breaks
+    Block,
+    // NOTE(Fermin): Never breaks and goes back and forth
+    Blocker,
+}
+pub const OBSTACLE_KINDS: [ObstacleKind; 3] = [
+    ObstacleKind::Brick,
+    ObstacleKind::Block,
+    ObstacleKind::Blocker,
+];
+impl ObstacleKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ObstacleKind::Brick => "brick",
+            ObstacleKind::Block => "block",
+            ObstacleKind::Blocker => "blocker",
+        }
+    }
+    // NOTE(Fermin): Bricks go from pink to red as they get tougher
+    pub fn color(self, hit_points: u32) -> Color {
+        match self {
+            ObstacleKind::Brick => match hit_points {
+                0 | 1 => BABY_PINK,
+                2 => ORANGE,
+                _ => RED,
+            },
+            ObstacleKind::Block => WALL_GREY,
+            ObstacleKind::Blocker => SKY_BLUE,
+        }
+    }
+}
+impl Persist for ObstacleKind {
+    fn write(&self, out: &mut BytesMut) {
+        (*self as u32).write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        let value = u32::read(buf)?;
+        OBSTACLE_KINDS
+            .get(value as usize)
+            .copied()
+            .ok_or_else(|| invalid_data(&format!("invalid obstacle {}", value)))
+    }
+}
+
+// NOTE(Fermin): How an obstacle starts the game, in buffer pixels. Blockers
+// move with dp and turn around after travelling `travel` pixels.
+#[derive(Clone)]
+pub struct ObstacleSpec {
+    pub kind: ObstacleKind,
+    pub p: V2,
+    pub width: i32,
+    pub height: i32,
+    pub hit_points: u32,
+    pub dp: V2,
+    pub travel: f32,
+}
+impl ObstacleSpec {
+    pub fn brick(x: i32, y: i32, width: i32, height: i32, hit_points: u32) -> Self {
+        Self {
+            kind: ObstacleKind::Brick,
+            p: V2 {
+                x: x as f32,
+                y: y as f32,
+            },
+            width,
+            height,
+            hit_points: hit_points.max(1),
+            dp: V2 { x: 0.0, y: 0.0 },
+            travel: 0.0,
+        }
+    }
+    pub fn block(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Self {
+            kind: ObstacleKind::Block,
+            ..Self::brick(x, y, width, height, 1)
+        }
+    }
+    pub fn blocker(x: i32, y: i32, width: i32, height: i32, dp: V2, travel: f32) -> Self {
+        Self {
+            kind: ObstacleKind::Blocker,
+            dp,
+            travel,
+            ..Self::brick(x, y, width, height, 1)
+        }
+    }
+}
+impl Persist for ObstacleSpec {
+    fn write(&self, out: &mut BytesMut) {
+        self.kind.write(out);
+        self.p.write(out);
+        self.width.write(out);
+        self.height.write(out);
+        self.hit_points.write(out);
+        self.dp.write(out);
+        self.travel.write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        let result = Self {
+            kind: ObstacleKind::read(buf)?,
+            p: V2::read(buf)?,
+            width: i32::read(buf)?,
+            height: i32::read(buf)?,
+            hit_points: u32::read(buf)?,
+            dp: V2::read(buf)?,
+            travel: f32::read(buf)?,
+        };
+        // NOTE(Fermin): Where it goes in the arena is up to the level check
+        let finite = [
+            result.p.x,
+            result.p.y,
+            result.dp.x,
+            result.dp.y,
+            result.travel,
+        ]
+        .iter()
+        .all(|number| number.is_finite());
+        let moves = (result.dp.x != 0.0 || result.dp.y != 0.0) && result.travel > 0.0;
+        if !finite
+            || result.width < 1
+            || result.height < 1
+            || result.hit_points < 1
+            || (result.kind == ObstacleKind::Blocker && !moves)
+        {
+            return Err(invalid_data(&format!("invalid {}", result.kind.name())));
+        }
+        Ok(result)
+    }
+}
+
+pub const ARENAS: [&str; 2] = ["empty", "bricks"];
+
+// NOTE(Fermin): Built in layouts for the given buffer size, the middle stays
+// clear for the serve
+pub fn arena_layout(name: &str, width: i32, height: i32) -> Option<Vec<ObstacleSpec>> {
+    match name {
+        "empty" => Some(Vec::new()),
+        "bricks" => Some(bricks_layout(width, height)),
+        _ => None,
+    }
+}
+
+// NOTE(Fermin): A ring of bricks around the serve, tougher in the middle of
+// each side, blocks on the corners and a blocker sliding past each end
+fn bricks_layout(width: i32, height: i32) -> Vec<ObstacleSpec> {
+    let mut result = Vec::new();
+    let center_x = width / 2;
+    let center_y = height / 2;
+    let count = 6;
+    let step = BRICK_LONG_SIDE + BRICK_GAP;
+    let row_start = center_x - (count * step - BRICK_GAP) / 2;
+    let column_start = center_y - (count * step - BRICK_GAP) / 2;
+    let ring = 90;
+    for index in 0..count {
+        let hit_points = if index == count / 2 - 1 || index == count / 2 {
+            3
+        } else {
+            2
+        };
+        let along_x = row_start + index * step;
+        let along_y = column_start + index * step;
+        result.push(ObstacleSpec::brick(
+            along_x,
+            center_y - ring - BRICK_SHORT_SIDE,
+            BRICK_LONG_SIDE,
+            BRICK_SHORT_SIDE,
+            hit_points,
+        ));
+        result.push(ObstacleSpec::brick(
+            along_x,
+            center_y + ring,
+            BRICK_LONG_SIDE,
+            BRICK_SHORT_SIDE,
+            hit_points,
+        ));
+        result.push(ObstacleSpec::brick(
+            center_x - ring - BRICK_SHORT_SIDE,
+            along_y,
+            BRICK_SHORT_SIDE,
+            BRICK_LONG_SIDE,
+            hit_points - 1,
+        ));
+        result.push(ObstacleSpec::brick(
+            center_x + ring,
+            along_y,
+            BRICK_SHORT_SIDE,
+            BRICK_LONG_SIDE,
+            hit_points - 1,
+        ));
+    }
+
+    let corner = ring + BRICK_SHORT_SIDE + 20;
+    for (x_sign, y_sign) in [(-1, -1), (1, -1), (-1, 1), (1, 1)] {
+        result.push(ObstacleSpec::block(
+            center_x + x_sign * corner - BLOCK_SIZE / 2,
+            center_y + y_sign * corner - BLOCK_SIZE / 2,
+            BLOCK_SIZE,
+            BLOCK_SIZE,
+        ));
+    }
+
+    let blocker_width = BRICK_LONG_SIDE * 2;
+    let travel = (2 * corner - blocker_width) as f32;
+    // NOTE(Fermin): The top one starts on the left going right, the bottom
+    // one the other way around
+    for y_sign in [-1, 1] {
+        let x = if y_sign < 0 {
+            center_x - corner
+        } else {
+            center_x + corner - blocker_width
+        };
+        result.push(ObstacleSpec::blocker(
+            x,
+            center_y + y_sign * (corner + 20) - BRICK_SHORT_SIDE / 2,
+            blocker_width,
+            BRICK_SHORT_SIDE / 2,
+            V2 {
+                x: -60.0 * y_sign as f32,
+                y: 0.0,
+            },
+            travel,
+        ));
+    }
+
+    result
+}
@@ -0,0 +1,223 @@
+use crate::game::*;
+use crate::savestate::*;
+use crate::*;
+use bytes::BytesMut;
+use std::io::Result;
+
+pub const POWER_UP_SIZE: i32 = 14;
+// NOTE(Fermin): Seconds between spawns and how long a pickup waits to be
+// collected
+pub const POWER_UP_SPAWN_SECONDS: f32 = 6.0;
+pub const POWER_UP_LIFETIME: f32 = 10.0;
+pub const MAX_POWER_UPS_ON_FIELD: usize = 2;
+// NOTE(Fermin): Seconds a sticky paddle holds the ball if nobody lets it go
+pub const STICKY_HOLD_SECONDS: f32 = 1.0;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum PowerUpKind {
+    Grow,
+    Shrink,
+    SlowBall,
+    SpeedBoost,
+    Shield,
+    Sticky,
+    Multiplier,
+}
+pub const POWER_UP_KINDS: [PowerUpKind; 7] = [
+    PowerUpKind::Grow,
+    PowerUpKind::Shrink,
+    PowerUpKind::SlowBall,
+    PowerUpKind::SpeedBoost,
+    PowerUpKind::Shield,
+    PowerUpKind::Sticky,
+    PowerUpKind::Multiplier,
+];
+impl PowerUpKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            PowerUpKind::Grow => "GROW",
+            PowerUpKind::Shrink => "SHRINK",
+            PowerUpKind::SlowBall => "SLOW",
+            PowerUpKind::SpeedBoost => "FAST",
+            PowerUpKind::Shield => "SHIELD",
+            PowerUpKind::Sticky => "STICKY",
+            PowerUpKind::Multiplier => "X2",
+        }
+    }
+    // NOTE(Fermin): Drawn on the pickup
+    pub fn letter(self) -> &'static str {
+        match self {
+            PowerUpKind::Grow => "G",
+            PowerUpKind::Shrink => "S",
+            PowerUpKind::SlowBall => "L",
+            PowerUpKind::SpeedBoost => "F",
+            PowerUpKind::Shield => "W",
+            PowerUpKind::Sticky => "K",
+            PowerUpKind::Multiplier => "X",
+        }
+    }
+    pub fn color(self) -> Color {
+        match self {
+            PowerUpKind::Grow => NEON_MINT,
+            PowerUpKind::Shrink => RED,
+            PowerUpKind::SlowBall => SKY_BLUE,
+            PowerUpKind::SpeedBoost => ORANGE,
+            PowerUpKind::Shield => LILAC,
+            PowerUpKind::Sticky => BABY_PINK,
+            PowerUpKind::Multiplier => NEON_YELLOW,
+        }
+    }
+    pub fn duration(self) -> f32 {
+        match self {
+            PowerUpKind::Grow | PowerUpKind::Shrink | PowerUpKind::Shield => 8.0,
+            PowerUpKind::SlowBall | PowerUpKind::SpeedBoost => 6.0,
+            PowerUpKind::Sticky | PowerUpKind::Multiplier => 10.0,
+        }
+    }
+    // NOTE(Fermin): Share of the normal paddle length
+    pub fn paddle_scale(self) -> f32 {
+        match self {
+            PowerUpKind::Grow => 1.5,
+            PowerUpKind::Shrink => 0.6,
+            _ => 1.0,
+        }
+    }
+    // NOTE(Fermin): Share of the rally speed
+    pub fn ball_scale(self) -> f32 {
+        match self {
+            PowerUpKind::SlowBall => 0.6,
+            PowerUpKind::SpeedBoost => 1.4,
+            _ => 1.0,
+        }
+    }
+}
+impl Persist for PowerUpKind {
+    fn write(&self, out: &mut BytesMut) {
+        (*self as u32).write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        let value = u32::read(buf)?;
+        POWER_UP_KINDS
+            .get(value as usize)
+            .copied()
+            .ok_or_else(|| invalid_data(&format!("invalid power-up {}", value)))
+    }
+}
+
+// NOTE(Fermin): Waiting on the field for a ball to go through it
+#[derive(Clone)]
+pub struct PowerUp {
+    pub kind: PowerUpKind,
+    pub p: V2,
+    pub seconds_left: f32,
+}
+impl Persist for PowerUp {
+    fn write(&self, out: &mut BytesMut) {
+        self.kind.write(out);
+        self.p.write(out);
+        self.seconds_left.write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        Ok(Self {
+            kind: PowerUpKind::read(buf)?,
+            p: V2::read(buf)?,
+            seconds_left: f32::read(buf)?,
+        })
+    }
+}
+
+// NOTE(Fermin): A collected power-up. Paddles is a mask of the paddle
+// indices it applies to, for shields it is the side that gets the wall.
+#[derive(Clone)]
+pub struct ActiveEffect {
+    pub kind: PowerUpKind,
+    pub paddles: u32,
+    // NOTE(Fermin): Player that collected it, None when it helps everyone
+    pub collector: Option<usize>,
+    pub seconds_left: f32,
+}
+impl ActiveEffect {
+    pub fn applies_to(&self, paddle: usize) -> bool {
+        self.paddles & (1 << paddle) != 0
+    }
+}
+impl Persist for ActiveEffect {
+    fn write(&self, out: &mut BytesMut) {
+        self.kind.write(out);
+        self.paddles.write(out);
+        self.collector.is_some().write(out);
+        (self.collector.unwrap_or(0) as u32).write(out);
+        self.seconds_left.write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        let kind = PowerUpKind::read(buf)?;
+        let paddles = u32::read(buf)?;
+        let has_collector = bool::read(buf)?;
+        let collector = u32::read(buf)? as usize;
+        if has_collector && collector >= PADDLE_COUNT {
+            return Err(invalid_data(&format!("invalid collector {}", collector)));
+        }
+        Ok(Self {
+            kind,
+            paddles,
+            collector: has_collector.then_some(collector),
+            seconds_left: f32::read(buf)?,
+        })
+    }
+}
+
+// NOTE(Fermin): Product of the scales of every effect that applies
+pub fn paddle_scale(effects: &[ActiveEffect], paddle: usize) -> f32 {
+    effects
+        .iter()
+        .filter(|effect| effect.applies_to(paddle))
+        .map(|effect| effect.kind.paddle_scale())
+        .product()
+}
+
+pub fn ball_scale(effects: &[ActiveEffect]) -> f32 {
+    effects
+        .iter()
+        .map(|effect| effect.kind.ball_scale())
+        .product()
+}
+
+pub fn has_effect(effects: &[ActiveEffect], kind: PowerUpKind, paddle: usize) -> bool {
+    effects
+        .iter()
+        .any(|effect| effect.kind == kind && effect.applies_to(paddle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn effect_bytes(collector: Option<usize>) -> BytesMut {
+        let mut out = BytesMut::new();
+        ActiveEffect {
+            kind: PowerUpKind::Multiplier,
+            paddles: 0b0100,
+            collector,
+            seconds_left: 4.5,
+        }
+        .write(&mut out);
+        out
+    }
+
+    #[test]
+    fn active_effect_round_trip() {
+        for collector in [None, Some(0), Some(PADDLE_COUNT - 1)] {
+            let bytes = effect_bytes(collector);
+            let effect = ActiveEffect::read(&mut &bytes[..]).unwrap();
+            assert_eq!(effect.collector, collector);
+            assert_eq!(effect.paddles, 0b0100);
+            assert_eq!(effect.seconds_left, 4.5);
+        }
+    }
+
+    #[test]
+    fn collector_out_of_range_is_an_error() {
+        let bytes = effect_bytes(Some(PADDLE_COUNT));
+        assert!(ActiveEffect::read(&mut &bytes[..]).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use crate::game::*;
+use crate::window::*;
+use crate::*;
+
+enum InputLoopState {
+    Idle,
+    Recording,
+    PlayingBack,
+}
+
+// NOTE(Fermin): Debug input looping. The first toggle snapshots the game and
+// starts recording inputs, the second one starts playing them back in a loop
+// (restoring the snapshot every time the recording ends) and the third one
+// hands control back to the player.
+pub struct InputLoop {
+    state: InputLoopState,
+    snapshot: Option<(GameMemory, GameState)>,
+    inputs: Vec<GameInput>,
+    playback_index: usize,
+}
+impl InputLoop {
+    pub fn new() -> Self {
+        Self {
+            state: InputLoopState::Idle,
+            snapshot: None,
+            inputs: Vec::new(),
+            playback_index: 0,
+        }
+    }
+
+    pub fn toggle(&mut self, memory: &GameMemory, game_state: &GameState) {
+        match self.state {
+            InputLoopState::Idle => {
+                println!("Input loop: recording");
+                self.snapshot = Some((memory.clone(), game_state.clone()));
+                self.inputs.clear();
+                self.state = InputLoopState::Recording;
+            }
+            InputLoopState::Recording => {
+                if self.inputs.is_empty() {
+                    println!("Input loop: nothing recorded");
+                    self.state = InputLoopState::Idle;
+                } else {
+                    println!("Input loop: playing back {} frames", self.inputs.len());
+                    // NOTE(Fermin): Forces a restore on the next frame
+                    self.playback_index = self.inputs.len();
+                    self.state = InputLoopState::PlayingBack;
+                }
+            }
+            InputLoopState::PlayingBack => {
+                println!("Input loop: stopped");
+                self.snapshot = None;
+                self.inputs.clear();
+                self.state = InputLoopState::Idle;
+            }
+        }
+    }
+
+    // NOTE(Fermin): Returns the input the game should run with this frame
+    pub fn frame_input<'a>(
+        &'a mut self,
+        live_input: &'a GameInput,
+        memory: &mut GameMemory,
+        game_state: &mut GameState,
+        buffer: &mut Win32OffscreenBuffer,
+    ) -> &'a GameInput {
+        match self.state {
+            InputLoopState::Idle => live_input,
+            InputLoopState::Recording => {
+                self.inputs.push(*live_input);
+                live_input
+            }
+            InputLoopState::PlayingBack => {
+                if self.playback_index >= self.inputs.len() {
+                    if let Some((snapshot_memory, snapshot_state)) = &self.snapshot {
+                        *memory = snapshot_memory.clone();
+                        *game_state = snapshot_state.clone();
+                    }
+                    clear_screen(buffer);
+                    self.playback_index = 0;
+                }
+
+                let result = &self.inputs[self.playback_index];
+                self.playback_index += 1;
+                result
+            }
+        }
+    }
+}
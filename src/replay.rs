@@ -0,0 +1,579 @@
+use crate::bindings::*;
+use crate::game::*;
+use crate::savestate::*;
+use crate::window::*;
+use crate::*;
+use bytes::{Buf, BufMut, BytesMut};
+use std::fs;
+use std::io::Result;
+use std::path::Path;
+
+const REPLAY_MAGIC: &[u8; 4] = b"PRPL";
+const REPLAY_VERSION: u16 = 18;
+const SNAPSHOT_INTERVAL: usize = 120;
+const FAST_FORWARD_FACTOR: usize = 4;
+const SEEK_FRAMES: usize = 300;
+
+// NOTE(Fermin): Every frame starts with a byte saying what changed since the
+// previous frame, only those fields follow. A zero byte is followed by the
+// number of frames that repeat the previous one. Buttons are a varint bit
+// mask of ended_down, a varint bit mask of the buttons that transitioned and
+// a varint count for each of those. Controllers are a connected byte and both
+// sticks for every slot.
+const FRAME_CHANGED_DT: u8 = 1 << 0;
+const FRAME_CHANGED_CURSOR: u8 = 1 << 1;
+const FRAME_CHANGED_BUTTONS: u8 = 1 << 2;
+const FRAME_KEY_PRESSED: u8 = 1 << 3;
+const FRAME_CHANGED_CONTROLLERS: u8 = 1 << 4;
+
+pub struct ReplayHeader {
+    pub seed: u64,
+    pub settings: GameSettings,
+    pub bindings: KeyBindings,
+    pub buffer_width: i32,
+    pub buffer_height: i32,
+}
+
+// NOTE(Fermin): A whole session, from the first frame the game ran with
+// the seed in the header
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub frames: Vec<GameInput>,
+}
+impl Replay {
+    pub fn new(
+        seed: u64,
+        settings: &GameSettings,
+        bindings: &KeyBindings,
+        buffer: &Win32OffscreenBuffer,
+    ) -> Self {
+        Self {
+            header: ReplayHeader {
+                seed,
+                settings: settings.clone(),
+                bindings: bindings.clone(),
+                buffer_width: buffer.width,
+                buffer_height: buffer.height,
+            },
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, input: &GameInput) {
+        self.frames.push(*input);
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.encode())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::decode(&fs::read(path)?)
+    }
+
+    fn encode(&self) -> BytesMut {
+        let mut out = BytesMut::new();
+        out.put_slice(REPLAY_MAGIC);
+        out.put_u16_le(REPLAY_VERSION);
+        out.put_u64_le(self.header.seed);
+        self.header.settings.write(&mut out);
+        self.header.bindings.write(&mut out);
+        out.put_i32_le(self.header.buffer_width);
+        out.put_i32_le(self.header.buffer_height);
+        out.put_u32_le(self.frames.len() as u32);
+
+        let mut previous = GameInput::new();
+        let mut unchanged_frames: u64 = 0;
+        for frame in &self.frames {
+            let mut flags = 0;
+            if frame.dt_for_frame != previous.dt_for_frame {
+                flags |= FRAME_CHANGED_DT;
+            }
+            if frame.cursor_pos.x != previous.cursor_pos.x
+                || frame.cursor_pos.y != previous.cursor_pos.y
+            {
+                flags |= FRAME_CHANGED_CURSOR;
+            }
+            if button_bits(frame) != button_bits(&previous) || has_transitions(frame) {
+                flags |= FRAME_CHANGED_BUTTONS;
+            }
+
+            if frame.key_pressed.is_some() {
+                flags |= FRAME_KEY_PRESSED;
+            }
+            if controllers_changed(frame, &previous) {
+                flags |= FRAME_CHANGED_CONTROLLERS;
+            }
+
+            if flags == 0 {
+                unchanged_frames += 1;
+                continue;
+            }
+            if unchanged_frames > 0 {
+                out.put_u8(0);
+                put_varint(&mut out, unchanged_frames);
+                unchanged_frames = 0;
+            }
+
+            out.put_u8(flags);
+            if flags & FRAME_CHANGED_DT != 0 {
+                out.put_f32_le(frame.dt_for_frame);
+            }
+            if flags & FRAME_CHANGED_CURSOR != 0 {
+                out.put_f32_le(frame.cursor_pos.x);
+                out.put_f32_le(frame.cursor_pos.y);
+            }
+            if flags & FRAME_CHANGED_BUTTONS != 0 {
+                put_varint(&mut out, button_bits(frame));
+                put_varint(&mut out, transition_bits(frame));
+                for button in frame.buttons() {
+                    if button.half_transition_count > 0 {
+                        put_varint(&mut out, button.half_transition_count as u64);
+                    }
+                }
+            }
+            if let Some(key) = frame.key_pressed {
+                out.put_u16_le(key);
+            }
+            if flags & FRAME_CHANGED_CONTROLLERS != 0 {
+                for controller in &frame.controllers {
+                    controller.is_connected.write(&mut out);
+                    controller.left_stick.write(&mut out);
+                    controller.right_stick.write(&mut out);
+                }
+            }
+            previous = *frame;
+        }
+        if unchanged_frames > 0 {
+            out.put_u8(0);
+            put_varint(&mut out, unchanged_frames);
+        }
+
+        out
+    }
+
+    fn decode(mut bytes: &[u8]) -> Result<Self> {
+        let buf = &mut bytes;
+        ensure_remaining(buf, REPLAY_MAGIC.len() + 2)?;
+        if &buf[..REPLAY_MAGIC.len()] != REPLAY_MAGIC {
+            return Err(invalid_data("not a replay file"));
+        }
+        buf.advance(REPLAY_MAGIC.len());
+        let version = buf.get_u16_le();
+        if version != REPLAY_VERSION {
+            return Err(invalid_data(&format!(
+                "unsupported replay version {}, expected {}",
+                version, REPLAY_VERSION
+            )));
+        }
+
+        ensure_remaining(buf, 8)?;
+        let seed = buf.get_u64_le();
+        let settings = GameSettings::read(buf)?;
+        let bindings = KeyBindings::read(buf)?;
+        ensure_remaining(buf, 4 + 4 + 4)?;
+        let header = ReplayHeader {
+            seed,
+            settings,
+            bindings,
+            buffer_width: buf.get_i32_le(),
+            buffer_height: buf.get_i32_le(),
+        };
+        let frame_count = buf.get_u32_le() as usize;
+
+        // NOTE(Fermin): The count comes from the file, a broken one shouldn't
+        // allocate more than there could be frames left in it
+        let mut frames = Vec::with_capacity(frame_count.min(buf.remaining()));
+        let mut current = GameInput::new();
+        while frames.len() < frame_count {
+            current.prepare_for_new_frame();
+            ensure_remaining(buf, 1)?;
+            let flags = buf.get_u8();
+            if flags == 0 {
+                let repeat = get_varint(buf)? as usize;
+                if repeat > frame_count - frames.len() {
+                    return Err(invalid_data("frame run goes past the end of the replay"));
+                }
+                for _ in 0..repeat {
+                    frames.push(current);
+                }
+                continue;
+            }
+
+            if flags & FRAME_CHANGED_DT != 0 {
+                ensure_remaining(buf, 4)?;
+                current.dt_for_frame = buf.get_f32_le();
+            }
+            if flags & FRAME_CHANGED_CURSOR != 0 {
+                ensure_remaining(buf, 8)?;
+                current.cursor_pos.x = buf.get_f32_le();
+                current.cursor_pos.y = buf.get_f32_le();
+            }
+            if flags & FRAME_CHANGED_BUTTONS != 0 {
+                let ended_down = get_varint(buf)?;
+                let transitions = get_varint(buf)?;
+                for (index, button) in current.buttons_mut().into_iter().enumerate() {
+                    button.ended_down = ended_down & (1 << index) != 0;
+                    button.half_transition_count = if transitions & (1 << index) != 0 {
+                        get_varint(buf)? as u32
+                    } else {
+                        0
+                    };
+                }
+            }
+            if flags & FRAME_KEY_PRESSED != 0 {
+                ensure_remaining(buf, 2)?;
+                current.key_pressed = Some(buf.get_u16_le());
+            }
+            if flags & FRAME_CHANGED_CONTROLLERS != 0 {
+                for controller in &mut current.controllers {
+                    controller.is_connected = bool::read(buf)?;
+                    controller.left_stick = V2::read(buf)?;
+                    controller.right_stick = V2::read(buf)?;
+                }
+            }
+            frames.push(current);
+        }
+
+        Ok(Self { header, frames })
+    }
+}
+
+fn button_bits(input: &GameInput) -> u64 {
+    let mut result = 0;
+    for (index, button) in input.buttons().iter().enumerate() {
+        if button.ended_down {
+            result |= 1 << index;
+        }
+    }
+    result
+}
+
+fn transition_bits(input: &GameInput) -> u64 {
+    let mut result = 0;
+    for (index, button) in input.buttons().iter().enumerate() {
+        if button.half_transition_count > 0 {
+            result |= 1 << index;
+        }
+    }
+    result
+}
+
+fn has_transitions(input: &GameInput) -> bool {
+    transition_bits(input) != 0
+}
+
+fn controllers_changed(input: &GameInput, previous: &GameInput) -> bool {
+    input
+        .controllers
+        .iter()
+        .zip(&previous.controllers)
+        .any(|(controller, previous)| {
+            controller.is_connected != previous.is_connected
+                || controller.left_stick.x != previous.left_stick.x
+                || controller.left_stick.y != previous.left_stick.y
+                || controller.right_stick.x != previous.right_stick.x
+                || controller.right_stick.y != previous.right_stick.y
+        })
+}
+
+fn put_varint(out: &mut BytesMut, mut value: u64) {
+    while value >= 0x80 {
+        out.put_u8((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.put_u8(value as u8);
+}
+
+fn get_varint(buf: &mut &[u8]) -> Result<u64> {
+    let mut result: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        ensure_remaining(buf, 1)?;
+        let byte = buf.get_u8();
+        result |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+    }
+    Err(invalid_data("varint is too long"))
+}
+
+// NOTE(Fermin): Plays a replay back instead of the live input. Snapshots of
+// the game are taken every SNAPSHOT_INTERVAL frames while playing, seeking
+// restores the closest one before the target and simulates up to it. The
+// bindings come from the header and are the viewer's own, rebinding keys in
+// the replay doesn't touch the live ones.
+pub struct ReplayViewer {
+    replay: Replay,
+    frame_index: usize,
+    bindings: KeyBindings,
+    snapshots: Vec<(GameMemory, GameState, KeyBindings)>,
+    paused: bool,
+    step_requested: bool,
+    fast_forward: bool,
+}
+impl ReplayViewer {
+    pub fn new(replay: Replay) -> Self {
+        Self {
+            bindings: replay.header.bindings.clone(),
+            replay,
+            frame_index: 0,
+            snapshots: Vec::new(),
+            paused: false,
+            step_requested: false,
+            fast_forward: false,
+        }
+    }
+
+    pub fn header(&self) -> &ReplayHeader {
+        &self.replay.header
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn step(&mut self) {
+        self.paused = true;
+        self.step_requested = true;
+    }
+
+    pub fn toggle_fast_forward(&mut self) {
+        self.fast_forward = !self.fast_forward;
+    }
+
+    pub fn seek_back(
+        &mut self,
+        memory: &mut GameMemory,
+        game_state: &mut GameState,
+        buffer: &mut Win32OffscreenBuffer,
+    ) {
+        let target = self.frame_index.saturating_sub(SEEK_FRAMES);
+        self.seek(target, memory, game_state, buffer);
+    }
+
+    pub fn seek_forward(
+        &mut self,
+        memory: &mut GameMemory,
+        game_state: &mut GameState,
+        buffer: &mut Win32OffscreenBuffer,
+    ) {
+        let target = self.frame_index + SEEK_FRAMES;
+        self.seek(target, memory, game_state, buffer);
+    }
+
+    fn seek(
+        &mut self,
+        target: usize,
+        memory: &mut GameMemory,
+        game_state: &mut GameState,
+        buffer: &mut Win32OffscreenBuffer,
+    ) {
+        let target = target.min(self.replay.frames.len());
+        if self.snapshots.is_empty() {
+            return;
+        }
+
+        let snapshot_index = (target / SNAPSHOT_INTERVAL).min(self.snapshots.len() - 1);
+        if target < self.frame_index || snapshot_index * SNAPSHOT_INTERVAL > self.frame_index {
+            let (snapshot_memory, snapshot_state, snapshot_bindings) =
+                &self.snapshots[snapshot_index];
+            *memory = snapshot_memory.clone();
+            *game_state = snapshot_state.clone();
+            self.bindings = snapshot_bindings.clone();
+            self.frame_index = snapshot_index * SNAPSHOT_INTERVAL;
+            clear_screen(buffer);
+        }
+
+        while self.frame_index < target {
+            self.run_frame(memory, game_state, buffer);
+        }
+    }
+
+    fn run_frame(
+        &mut self,
+        memory: &mut GameMemory,
+        game_state: &mut GameState,
+        buffer: &mut Win32OffscreenBuffer,
+    ) {
+        if self.frame_index.is_multiple_of(SNAPSHOT_INTERVAL)
+            && self.snapshots.len() == self.frame_index / SNAPSHOT_INTERVAL
+        {
+            self.snapshots
+                .push((memory.clone(), game_state.clone(), self.bindings.clone()));
+        }
+
+        update_and_render(
+            memory,
+            buffer,
+            &self.replay.frames[self.frame_index],
+            game_state,
+            &mut self.bindings,
+        );
+        self.frame_index += 1;
+    }
+
+    pub fn update(
+        &mut self,
+        memory: &mut GameMemory,
+        game_state: &mut GameState,
+        buffer: &mut Win32OffscreenBuffer,
+    ) {
+        let frames_to_run = if self.paused {
+            self.step_requested as usize
+        } else if self.fast_forward {
+            FAST_FORWARD_FACTOR
+        } else {
+            1
+        };
+        self.step_requested = false;
+
+        for _ in 0..frames_to_run {
+            if self.frame_index >= self.replay.frames.len() {
+                self.paused = true;
+                break;
+            }
+            self.run_frame(memory, game_state, buffer);
+        }
+
+        let status = if self.paused {
+            " PAUSED"
+        } else if self.fast_forward {
+            " FF"
+        } else {
+            ""
+        };
+        draw_overlay_text(
+            &format!(
+                "REPLAY {}/{}{}",
+                self.frame_index,
+                self.replay.frames.len(),
+                status
+            ),
+            buffer,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_replay() -> Replay {
+        let mut frames = Vec::new();
+        let mut input = GameInput::new();
+        input.dt_for_frame = 1.0 / 60.0;
+        for index in 0..200u32 {
+            input.prepare_for_new_frame();
+            if index % 50 == 10 {
+                input.keyboard.buttons.move_up.ended_down = true;
+                input.keyboard.buttons.move_up.half_transition_count = 1;
+            }
+            if index % 50 == 20 {
+                input.keyboard.buttons.move_up.ended_down = false;
+                input.keyboard.buttons.move_up.half_transition_count = 1;
+                input.keyboard.buttons.start.half_transition_count = 2;
+            }
+            if index == 30 {
+                input.cursor_pos = V2 { x: 12.5, y: -3.0 };
+                input.key_pressed = Some(0x41);
+            }
+            if index == 40 {
+                input.controllers[1].is_connected = true;
+                input.controllers[1].left_stick = V2 { x: 0.5, y: -0.25 };
+            }
+            if index == 120 {
+                input.dt_for_frame = 1.0 / 144.0;
+            }
+            frames.push(input);
+        }
+        Replay {
+            header: ReplayHeader {
+                seed: 0xDEAD_BEEF,
+                settings: GameSettings::new(),
+                bindings: {
+                    let mut bindings = KeyBindings::new();
+                    bindings.bind(Action::Jump, 'J' as u16);
+                    bindings
+                },
+                buffer_width: 435,
+                buffer_height: 460,
+            },
+            frames,
+        }
+    }
+
+    fn assert_same_input(a: &GameInput, b: &GameInput) {
+        assert_eq!(a.dt_for_frame, b.dt_for_frame);
+        assert_eq!(a.cursor_pos.x, b.cursor_pos.x);
+        assert_eq!(a.cursor_pos.y, b.cursor_pos.y);
+        assert_eq!(a.key_pressed, b.key_pressed);
+        for (a, b) in a.buttons().iter().zip(b.buttons()) {
+            assert_eq!(a.ended_down, b.ended_down);
+            assert_eq!(a.half_transition_count, b.half_transition_count);
+        }
+        for (a, b) in a.controllers.iter().zip(&b.controllers) {
+            assert_eq!(a.is_connected, b.is_connected);
+            assert_eq!(a.left_stick.x, b.left_stick.x);
+            assert_eq!(a.left_stick.y, b.left_stick.y);
+        }
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let replay = test_replay();
+        let bytes = replay.encode();
+        let decoded = Replay::decode(&bytes).expect("Replay decodes");
+        assert_eq!(decoded.header.seed, replay.header.seed);
+        assert_eq!(decoded.header.buffer_width, 435);
+        assert_eq!(decoded.header.buffer_height, 460);
+        for action in ACTIONS {
+            assert_eq!(
+                decoded.header.bindings.keys(action),
+                replay.header.bindings.keys(action)
+            );
+        }
+        assert_eq!(decoded.frames.len(), replay.frames.len());
+        for (a, b) in replay.frames.iter().zip(&decoded.frames) {
+            assert_same_input(a, b);
+        }
+        assert_eq!(&decoded.encode()[..], &bytes[..]);
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        for value in [0, 1, 0x7f, 0x80, 0x3fff, 0x4000, u32::MAX as u64, u64::MAX] {
+            let mut out = BytesMut::new();
+            put_varint(&mut out, value);
+            let mut buf = &out[..];
+            assert_eq!(get_varint(&mut buf).unwrap(), value);
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn truncated_replay_is_an_error() {
+        let bytes = test_replay().encode();
+        for length in [0, 3, 10, bytes.len() / 2, bytes.len() - 1] {
+            assert!(Replay::decode(&bytes[..length]).is_err());
+        }
+    }
+
+    #[test]
+    fn huge_frame_count_is_an_error() {
+        let mut replay = test_replay();
+        replay.frames.clear();
+        let mut bytes = replay.encode();
+        let count_at = bytes.len() - 4;
+        bytes[count_at..].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Replay::decode(&bytes).is_err());
+
+        // NOTE(Fermin): A run of repeated frames longer than the replay
+        bytes.put_u8(0);
+        put_varint(&mut bytes, u64::MAX);
+        assert!(Replay::decode(&bytes).is_err());
+    }
+}
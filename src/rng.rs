@@ -0,0 +1,48 @@
+use crate::savestate::*;
+use bytes::BytesMut;
+use rand::{Error, RngCore};
+
+// NOTE(Fermin): SplitMix64. The whole state is one u64 so it can be copied
+// into snapshots and written to disk, and it produces the same sequence on
+// every platform for a given seed
+#[derive(Copy, Clone)]
+pub struct GameRng {
+    state: u64,
+}
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+}
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+impl Persist for GameRng {
+    fn write(&self, out: &mut BytesMut) {
+        self.state.write(out);
+    }
+    fn read(buf: &mut &[u8]) -> std::io::Result<Self> {
+        Ok(Self {
+            state: u64::read(buf)?,
+        })
+    }
+}
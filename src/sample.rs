@@ -0,0 +1,393 @@
+use crate::audio::*;
+use crate::savestate::*;
+use crate::tracker::*;
+use bytes::Buf;
+use lewton::inside_ogg::OggStreamReader;
+use std::fs::{self, File};
+use std::io::Result;
+use std::path::Path;
+use std::rc::Rc;
+
+pub const ASSETS_PATH: &str = "assets";
+// NOTE(Fermin): Tried in this order, the first one found wins
+const SAMPLE_EXTENSIONS: [&str; 2] = ["wav", "ogg"];
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+// NOTE(Fermin): Decoded audio ready for the mixer, interleaved stereo frames
+// at AUDIO_SAMPLE_RATE. Mono files play on both sides, files with more than
+// two channels keep the first two.
+pub struct Sample {
+    pub frames: Vec<f32>,
+}
+impl Sample {
+    pub fn load(path: &Path) -> Result<Self> {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        let (channels, sample_rate, samples) = match extension.as_deref() {
+            Some("wav") => parse_wav(&fs::read(path)?)?,
+            Some("ogg") => decode_ogg(path)?,
+            _ => return Err(invalid_data("expected a .wav or .ogg file")),
+        };
+        if channels == 0 || sample_rate == 0 {
+            return Err(invalid_data("sample without channels or sample rate"));
+        }
+        Ok(resample(channels as usize, sample_rate, &samples))
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len() / 2
+    }
+
+    pub fn frame(&self, index: usize) -> (f32, f32) {
+        (self.frames[index * 2], self.frames[index * 2 + 1])
+    }
+
+    // NOTE(Fermin): Between two frames, for samples played at another pitch
+    pub fn frame_at(&self, position: f32) -> (f32, f32) {
+        let index = position as usize;
+        let t = position - index as f32;
+        let a = self.frame(index);
+        let b = self.frame((index + 1).min(self.frame_count() - 1));
+        (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+    }
+}
+
+// NOTE(Fermin): Only the fmt and data chunks matter, everything else is
+// skipped. PCM is 8 bit unsigned or 16 bit signed, float is 32 bit.
+fn parse_wav(bytes: &[u8]) -> Result<(u16, u32, Vec<f32>)> {
+    let mut buf = bytes;
+    ensure_remaining(&buf, 12)?;
+    if &buf[0..4] != b"RIFF" || &buf[8..12] != b"WAVE" {
+        return Err(invalid_data("not a RIFF WAVE file"));
+    }
+    buf.advance(12);
+
+    let mut format: Option<(u16, u16, u32, u16)> = None;
+    while buf.remaining() >= 8 {
+        let id = [buf[0], buf[1], buf[2], buf[3]];
+        buf.advance(4);
+        let size = buf.get_u32_le() as usize;
+        ensure_remaining(&buf, size)?;
+        let mut chunk = &buf[..size];
+        // NOTE(Fermin): Chunks are padded to an even size
+        buf.advance((size + size % 2).min(buf.remaining()));
+
+        match &id {
+            b"fmt " => {
+                ensure_remaining(&chunk, 16)?;
+                let mut tag = chunk.get_u16_le();
+                let channels = chunk.get_u16_le();
+                let sample_rate = chunk.get_u32_le();
+                chunk.advance(6);
+                let bits = chunk.get_u16_le();
+                if tag == WAVE_FORMAT_EXTENSIBLE {
+                    // NOTE(Fermin): The real tag starts the sub format GUID
+                    ensure_remaining(&chunk, 10)?;
+                    chunk.advance(8);
+                    tag = chunk.get_u16_le();
+                }
+                format = Some((tag, channels, sample_rate, bits));
+            }
+            b"data" => {
+                let (tag, channels, sample_rate, bits) =
+                    format.ok_or_else(|| invalid_data("data chunk before the fmt chunk"))?;
+                let samples = match (tag, bits) {
+                    (WAVE_FORMAT_PCM, 8) => chunk
+                        .iter()
+                        .map(|sample| (*sample as f32 - 128.0) / 128.0)
+                        .collect(),
+                    (WAVE_FORMAT_PCM, 16) => chunk
+                        .chunks_exact(2)
+                        .map(|sample| i16::from_le_bytes([sample[0], sample[1]]) as f32 / 32768.0)
+                        .collect(),
+                    (WAVE_FORMAT_IEEE_FLOAT, 32) => chunk
+                        .chunks_exact(4)
+                        .map(|sample| {
+                            f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]])
+                        })
+                        .collect(),
+                    _ => {
+                        return Err(invalid_data(&format!(
+                            "unsupported wav format {} with {} bits per sample",
+                            tag, bits
+                        )))
+                    }
+                };
+                return Ok((channels, sample_rate, samples));
+            }
+            _ => (),
+        }
+    }
+    Err(invalid_data("wav file without a data chunk"))
+}
+
+fn decode_ogg(path: &Path) -> Result<(u16, u32, Vec<f32>)> {
+    let vorbis_error = |error: lewton::VorbisError| invalid_data(&error.to_string());
+    let mut reader = OggStreamReader::new(File::open(path)?).map_err(vorbis_error)?;
+    let channels = reader.ident_hdr.audio_channels as u16;
+    let sample_rate = reader.ident_hdr.audio_sample_rate;
+    let mut samples = Vec::new();
+    while let Some(packet) = reader.read_dec_packet_itl().map_err(vorbis_error)? {
+        samples.extend(packet.iter().map(|sample| *sample as f32 / 32768.0));
+    }
+    Ok((channels, sample_rate, samples))
+}
+
+// NOTE(Fermin): Linear interpolation, good enough for game sounds and done
+// once at load time so the mixer never has to
+fn resample(channels: usize, sample_rate: u32, samples: &[f32]) -> Sample {
+    let frame_count = samples.len() / channels;
+    let source_frame = |index: usize| {
+        let frame = &samples[index * channels..(index + 1) * channels];
+        (frame[0], frame[if channels > 1 { 1 } else { 0 }])
+    };
+    let step = sample_rate as f64 / AUDIO_SAMPLE_RATE as f64;
+    let result_count = (frame_count as f64 / step) as usize;
+    let mut frames = Vec::with_capacity(result_count * 2);
+    for index in 0..result_count {
+        let at = index as f64 * step;
+        let source_index = at as usize;
+        let t = (at - source_index as f64) as f32;
+        let a = source_frame(source_index);
+        let b = source_frame((source_index + 1).min(frame_count - 1));
+        frames.push(a.0 + (b.0 - a.0) * t);
+        frames.push(a.1 + (b.1 - a.1) * t);
+    }
+    Sample { frames }
+}
+
+// NOTE(Fermin): Looks for NAME.wav then NAME.ogg in the directory. Missing
+// files are fine, files that fail to load are reported and skipped.
+pub fn load_named_sample(dir: &Path, name: &str) -> Option<Rc<Sample>> {
+    let path = SAMPLE_EXTENSIONS
+        .iter()
+        .map(|extension| dir.join(format!("{}.{}", name, extension)))
+        .find(|path| path.is_file())?;
+    match Sample::load(&path) {
+        Ok(sample) => Some(Rc::new(sample)),
+        Err(error) => {
+            println!("Error loading {}: {}", path.display(), error);
+            None
+        }
+    }
+}
+
+// NOTE(Fermin): Artists drop files into the assets directory to replace
+// sounds, the synthesized blips play for anything not found:
+//
+//   assets/sounds/paddle_hit.wav   one per SoundKind name
+//   assets/music/title.ogg         one per MusicTrack name, looped
+//   assets/music/game.song         tracker song, when there is no sample
+//
+// The game music falls back to the built in song.
+pub struct SoundBank {
+    pub sounds: Vec<Option<Rc<Sample>>>,
+    pub music: Vec<Option<Music>>,
+}
+impl SoundBank {
+    pub fn load(assets: &Path) -> Self {
+        let sounds_dir = assets.join("sounds");
+        let music_dir = assets.join("music");
+        Self {
+            sounds: SOUND_KINDS
+                .iter()
+                .map(|kind| load_named_sample(&sounds_dir, kind.name()))
+                .collect(),
+            music: MUSIC_TRACKS
+                .iter()
+                .map(|track| load_music(&music_dir, *track))
+                .collect(),
+        }
+    }
+
+    pub fn sound(&self, kind: SoundKind) -> Option<Rc<Sample>> {
+        self.sounds[kind as usize].clone()
+    }
+
+    pub fn music(&self, track: MusicTrack) -> Option<Music> {
+        self.music[track as usize].clone()
+    }
+}
+
+fn load_music(dir: &Path, track: MusicTrack) -> Option<Music> {
+    if let Some(sample) = load_named_sample(dir, track.name()) {
+        return Some(Music::Sample(sample));
+    }
+    let path = dir.join(format!("{}.song", track.name()));
+    if path.is_file() {
+        match Song::load(&path) {
+            Ok(song) => return Some(Music::Song(Rc::new(song))),
+            Err(error) => println!("Error loading {}: {}", path.display(), error),
+        }
+    }
+    (track == MusicTrack::Game).then(|| Music::Song(Rc::new(Song::default_song())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut result = id.to_vec();
+        result.extend((body.len() as u32).to_le_bytes());
+        result.extend(body);
+        if body.len() % 2 == 1 {
+            result.push(0);
+        }
+        result
+    }
+
+    fn fmt_chunk(tag: u16, channels: u16, sample_rate: u32, bits: u16) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+        let mut body = Vec::new();
+        body.extend(tag.to_le_bytes());
+        body.extend(channels.to_le_bytes());
+        body.extend(sample_rate.to_le_bytes());
+        body.extend((sample_rate * block_align as u32).to_le_bytes());
+        body.extend(block_align.to_le_bytes());
+        body.extend(bits.to_le_bytes());
+        if tag == WAVE_FORMAT_EXTENSIBLE {
+            body.extend(22u16.to_le_bytes());
+            body.extend(bits.to_le_bytes());
+            // NOTE(Fermin): Channel mask, then the sub format GUID
+            body.extend(3u32.to_le_bytes());
+            body.extend(WAVE_FORMAT_PCM.to_le_bytes());
+            body.extend([0, 0, 0, 0, 0x10, 0, 0x80, 0, 0, 0xAA, 0, 0x38, 0x9B, 0x71]);
+        }
+        chunk(b"fmt ", &body)
+    }
+
+    fn wav(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+        let mut result = b"RIFF".to_vec();
+        result.extend((body.len() as u32 + 4).to_le_bytes());
+        result.extend(b"WAVE");
+        result.extend(body);
+        result
+    }
+
+    fn pcm16(samples: &[i16]) -> Vec<u8> {
+        samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn parses_8_bit_pcm() {
+        let bytes = wav(&[
+            fmt_chunk(WAVE_FORMAT_PCM, 1, 22050, 8),
+            chunk(b"data", &[0, 128, 255]),
+        ]);
+        let (channels, sample_rate, samples) = parse_wav(&bytes).unwrap();
+        assert_eq!((channels, sample_rate), (1, 22050));
+        assert_eq!(samples, vec![-1.0, 0.0, 127.0 / 128.0]);
+    }
+
+    #[test]
+    fn parses_16_bit_pcm() {
+        let bytes = wav(&[
+            fmt_chunk(WAVE_FORMAT_PCM, 2, 44100, 16),
+            chunk(b"data", &pcm16(&[i16::MIN, 0, 16384, -16384])),
+        ]);
+        let (channels, sample_rate, samples) = parse_wav(&bytes).unwrap();
+        assert_eq!((channels, sample_rate), (2, 44100));
+        assert_eq!(samples, vec![-1.0, 0.0, 0.5, -0.5]);
+    }
+
+    #[test]
+    fn parses_float() {
+        let data: Vec<u8> = [0.25f32, -0.75, 1.0]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        let bytes = wav(&[
+            fmt_chunk(WAVE_FORMAT_IEEE_FLOAT, 1, 48000, 32),
+            chunk(b"data", &data),
+        ]);
+        let (channels, sample_rate, samples) = parse_wav(&bytes).unwrap();
+        assert_eq!((channels, sample_rate), (1, 48000));
+        assert_eq!(samples, vec![0.25, -0.75, 1.0]);
+    }
+
+    #[test]
+    fn parses_extensible() {
+        let bytes = wav(&[
+            fmt_chunk(WAVE_FORMAT_EXTENSIBLE, 2, 44100, 16),
+            chunk(b"data", &pcm16(&[16384, -16384])),
+        ]);
+        let (channels, _, samples) = parse_wav(&bytes).unwrap();
+        assert_eq!(channels, 2);
+        assert_eq!(samples, vec![0.5, -0.5]);
+    }
+
+    #[test]
+    fn skips_other_chunks() {
+        let bytes = wav(&[
+            chunk(b"LIST", b"odd"),
+            fmt_chunk(WAVE_FORMAT_PCM, 1, 44100, 16),
+            chunk(b"fact", &[1, 2, 3, 4]),
+            chunk(b"data", &pcm16(&[16384])),
+        ]);
+        let (_, _, samples) = parse_wav(&bytes).unwrap();
+        assert_eq!(samples, vec![0.5]);
+    }
+
+    #[test]
+    fn bad_wavs_are_errors() {
+        let data = chunk(b"data", &pcm16(&[0, 0]));
+        assert!(parse_wav(b"RIFF").is_err());
+        let mut not_riff = wav(&[]);
+        not_riff[0..4].copy_from_slice(b"RIFX");
+        assert!(parse_wav(&not_riff).is_err());
+        assert!(parse_wav(&wav(&[])).is_err());
+        assert!(parse_wav(&data).is_err());
+        assert!(parse_wav(&wav(std::slice::from_ref(&data))).is_err());
+        assert!(parse_wav(&wav(&[
+            fmt_chunk(WAVE_FORMAT_PCM, 1, 44100, 24),
+            data.clone()
+        ]))
+        .is_err());
+        assert!(parse_wav(&wav(&[fmt_chunk(2, 1, 44100, 16), data.clone()])).is_err());
+
+        let mut truncated = wav(&[fmt_chunk(WAVE_FORMAT_PCM, 1, 44100, 16), data]);
+        truncated.truncate(truncated.len() - 1);
+        assert!(parse_wav(&truncated).is_err());
+    }
+
+    #[test]
+    fn resample_at_the_mixer_rate_keeps_the_frames() {
+        let sample = resample(2, AUDIO_SAMPLE_RATE, &[0.1, 0.2, 0.3, 0.4]);
+        assert_eq!(sample.frames, vec![0.1, 0.2, 0.3, 0.4]);
+        let mono = resample(1, AUDIO_SAMPLE_RATE, &[0.1, 0.2]);
+        assert_eq!(mono.frames, vec![0.1, 0.1, 0.2, 0.2]);
+        // NOTE(Fermin): Only the first two channels are kept
+        let surround = resample(3, AUDIO_SAMPLE_RATE, &[0.1, 0.2, 0.9, 0.3, 0.4, 0.9]);
+        assert_eq!(surround.frames, vec![0.1, 0.2, 0.3, 0.4]);
+    }
+
+    #[test]
+    fn resample_interpolates_between_frames() {
+        let up = resample(1, AUDIO_SAMPLE_RATE / 2, &[0.0, 1.0, 0.0]);
+        assert_eq!(up.frame_count(), 6);
+        let left: Vec<f32> = up.frames.iter().step_by(2).copied().collect();
+        assert_eq!(left, vec![0.0, 0.5, 1.0, 0.5, 0.0, 0.0]);
+
+        let down = resample(1, AUDIO_SAMPLE_RATE * 2, &[0.0, 0.1, 0.2, 0.3, 0.4, 0.5]);
+        assert_eq!(down.frame_count(), 3);
+        assert_eq!(down.frame(1), (0.2, 0.2));
+    }
+
+    #[test]
+    fn frame_at_interpolates() {
+        let sample = Sample {
+            frames: vec![0.0, 1.0, 1.0, 0.0],
+        };
+        assert_eq!(sample.frame_at(0.25), (0.25, 0.75));
+        assert_eq!(sample.frame_at(1.5), (1.0, 0.0));
+    }
+}
@@ -0,0 +1,251 @@
+use crate::*;
+use bytes::{Buf, BufMut, BytesMut};
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+const SAVE_STATE_MAGIC: &[u8; 4] = b"PSAV";
+const SAVE_STATE_VERSION: u16 = 13;
+pub const SAVE_STATE_SLOTS: usize = 4;
+
+// NOTE(Fermin): Little endian binary, every type writes its fields in
+// declaration order. Bump SAVE_STATE_VERSION whenever a layout changes.
+pub trait Persist: Sized {
+    fn write(&self, out: &mut BytesMut);
+    fn read(buf: &mut &[u8]) -> Result<Self>;
+}
+
+impl Persist for bool {
+    fn write(&self, out: &mut BytesMut) {
+        out.put_u8(*self as u8);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        ensure_remaining(buf, 1)?;
+        match buf.get_u8() {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(invalid_data(&format!("invalid bool {}", value))),
+        }
+    }
+}
+impl Persist for i32 {
+    fn write(&self, out: &mut BytesMut) {
+        out.put_i32_le(*self);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        ensure_remaining(buf, 4)?;
+        Ok(buf.get_i32_le())
+    }
+}
+impl Persist for u32 {
+    fn write(&self, out: &mut BytesMut) {
+        out.put_u32_le(*self);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        ensure_remaining(buf, 4)?;
+        Ok(buf.get_u32_le())
+    }
+}
+impl Persist for u64 {
+    fn write(&self, out: &mut BytesMut) {
+        out.put_u64_le(*self);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        ensure_remaining(buf, 8)?;
+        Ok(buf.get_u64_le())
+    }
+}
+impl Persist for f32 {
+    fn write(&self, out: &mut BytesMut) {
+        out.put_f32_le(*self);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        ensure_remaining(buf, 4)?;
+        Ok(buf.get_f32_le())
+    }
+}
+impl Persist for String {
+    fn write(&self, out: &mut BytesMut) {
+        (self.len() as u32).write(out);
+        out.put_slice(self.as_bytes());
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        let len = u32::read(buf)? as usize;
+        ensure_remaining(buf, len)?;
+        let result = String::from_utf8(buf[..len].to_vec())
+            .map_err(|_| invalid_data("invalid utf-8 string"))?;
+        buf.advance(len);
+        Ok(result)
+    }
+}
+impl Persist for V2 {
+    fn write(&self, out: &mut BytesMut) {
+        self.x.write(out);
+        self.y.write(out);
+    }
+    fn read(buf: &mut &[u8]) -> Result<Self> {
+        Ok(V2 {
+            x: f32::read(buf)?,
+            y: f32::read(buf)?,
+        })
+    }
+}
+
+pub fn write_vec<T: Persist>(items: &[T], out: &mut BytesMut) {
+    (items.len() as u32).write(out);
+    for item in items {
+        item.write(out);
+    }
+}
+
+pub fn read_vec<T: Persist>(buf: &mut &[u8]) -> Result<Vec<T>> {
+    let count = u32::read(buf)?;
+    let mut result = Vec::new();
+    for _ in 0..count {
+        result.push(T::read(buf)?);
+    }
+    Ok(result)
+}
+
+pub fn ensure_remaining(buf: &&[u8], count: usize) -> Result<()> {
+    if buf.remaining() < count {
+        Err(Error::new(ErrorKind::UnexpectedEof, "file is truncated"))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn slot_path(slot: usize) -> PathBuf {
+    PathBuf::from(format!("saves/slot{}.psav", slot + 1))
+}
+
+pub fn save_state(slot: usize, memory: &GameMemory, game_state: &GameState) -> Result<PathBuf> {
+    let path = slot_path(slot);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, encode_state(memory, game_state))?;
+    Ok(path)
+}
+
+pub fn load_state(slot: usize) -> Result<(GameMemory, GameState)> {
+    decode_state(&fs::read(slot_path(slot))?)
+}
+
+fn encode_state(memory: &GameMemory, game_state: &GameState) -> BytesMut {
+    let mut out = BytesMut::new();
+    out.put_slice(SAVE_STATE_MAGIC);
+    out.put_u16_le(SAVE_STATE_VERSION);
+    memory.write(&mut out);
+    game_state.write(&mut out);
+    out
+}
+
+fn decode_state(mut bytes: &[u8]) -> Result<(GameMemory, GameState)> {
+    let buf = &mut bytes;
+
+    ensure_remaining(buf, SAVE_STATE_MAGIC.len() + 2)?;
+    if &buf[..SAVE_STATE_MAGIC.len()] != SAVE_STATE_MAGIC {
+        return Err(invalid_data("not a save state file"));
+    }
+    buf.advance(SAVE_STATE_MAGIC.len());
+    let version = buf.get_u16_le();
+    if version != SAVE_STATE_VERSION {
+        return Err(invalid_data(&format!(
+            "unsupported save state version {}, expected {}",
+            version, SAVE_STATE_VERSION
+        )));
+    }
+
+    let memory = GameMemory::read(buf)?;
+    let game_state = GameState::read(buf)?;
+    if buf.has_remaining() {
+        return Err(invalid_data("unexpected data after the game state"));
+    }
+    Ok((memory, game_state))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_frames(
+        memory: &mut GameMemory,
+        game_state: &mut GameState,
+        buffer: &mut Win32OffscreenBuffer,
+        frames: u32,
+    ) {
+        let mut bindings = KeyBindings::new();
+        let mut input = GameInput::new();
+        input.dt_for_frame = 1.0 / 60.0;
+        for frame in 0..frames {
+            input.prepare_for_new_frame();
+            let move_up = &mut input.keyboard.buttons.move_up;
+            move_up.ended_down = (frame / 30) % 2 == 0;
+            move_up.half_transition_count = (frame % 30 == 0) as u32;
+            update_and_render(memory, buffer, &input, game_state, &mut bindings);
+        }
+    }
+
+    fn played_game() -> (GameMemory, GameState, Win32OffscreenBuffer) {
+        let mut settings = GameSettings::new();
+        settings.power_ups = true;
+        let mut memory = GameMemory::new(42, settings);
+        let mut game_state = GameState {
+            state: GameStates::Play,
+            pause_seconds_elapsed: 0.0,
+        };
+        let mut buffer = Win32OffscreenBuffer::new(BUFFER_WIDTH, BUFFER_HEIGHT).unwrap();
+        run_frames(&mut memory, &mut game_state, &mut buffer, 600);
+        (memory, game_state, buffer)
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let (memory, game_state, _) = played_game();
+        let bytes = encode_state(&memory, &game_state);
+        let (loaded_memory, loaded_state) = decode_state(&bytes).expect("State decodes");
+        assert_eq!(&encode_state(&loaded_memory, &loaded_state)[..], &bytes[..]);
+    }
+
+    #[test]
+    fn loaded_state_plays_the_same() {
+        let (mut memory, mut game_state, mut buffer) = played_game();
+        let bytes = encode_state(&memory, &game_state);
+        let (mut loaded_memory, mut loaded_state) = decode_state(&bytes).unwrap();
+        let mut loaded_buffer = Win32OffscreenBuffer::new(BUFFER_WIDTH, BUFFER_HEIGHT).unwrap();
+        run_frames(&mut memory, &mut game_state, &mut buffer, 300);
+        run_frames(
+            &mut loaded_memory,
+            &mut loaded_state,
+            &mut loaded_buffer,
+            300,
+        );
+        assert_eq!(
+            &encode_state(&loaded_memory, &loaded_state)[..],
+            &encode_state(&memory, &game_state)[..]
+        );
+    }
+
+    #[test]
+    fn truncated_state_is_an_error() {
+        let (memory, game_state, _) = played_game();
+        let bytes = encode_state(&memory, &game_state);
+        for length in (0..bytes.len()).step_by(7) {
+            assert!(decode_state(&bytes[..length]).is_err());
+        }
+        assert!(decode_state(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn wrong_version_is_an_error() {
+        let (memory, game_state, _) = played_game();
+        let mut bytes = encode_state(&memory, &game_state);
+        bytes[SAVE_STATE_MAGIC.len()] ^= 0xff;
+        assert!(decode_state(&bytes).is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use crate::audio::*;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Noise,
+}
+
+// NOTE(Fermin): Runs at whatever pitch it is asked for every sample, so
+// sweeps don't click. Noise picks a new value every period like the old
+// sound chips did.
+#[derive(Clone)]
+pub struct Oscillator {
+    waveform: Waveform,
+    phase: f32,
+    noise_state: u32,
+    noise_value: f32,
+}
+impl Oscillator {
+    pub fn new(waveform: Waveform) -> Self {
+        Self {
+            waveform,
+            phase: 0.0,
+            noise_state: 0x1234_5678,
+            noise_value: 0.0,
+        }
+    }
+
+    pub fn next(&mut self, hz: f32, sample_rate: u32) -> f32 {
+        let result = match self.waveform {
+            Waveform::Square => {
+                if self.phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Noise => self.noise_value,
+        };
+        self.phase += hz / sample_rate as f32;
+        if self.phase >= 1.0 {
+            self.phase -= self.phase.floor();
+            // NOTE(Fermin): xorshift32, audio noise doesn't need the game rng
+            self.noise_state ^= self.noise_state << 13;
+            self.noise_state ^= self.noise_state >> 17;
+            self.noise_state ^= self.noise_state << 5;
+            self.noise_value = (self.noise_state as f32 / u32::MAX as f32) * 2.0 - 1.0;
+        }
+        result
+    }
+}
+
+// NOTE(Fermin): A tone sliding from start_hz to end_hz that fades out
+// linearly, starting `delay` seconds after the effect does
+#[derive(Copy, Clone)]
+pub struct Blip {
+    pub waveform: Waveform,
+    pub start_hz: f32,
+    pub end_hz: f32,
+    pub delay: f32,
+    pub seconds: f32,
+    pub volume: f32,
+}
+impl Blip {
+    const fn new(waveform: Waveform, start_hz: f32, end_hz: f32, seconds: f32) -> Self {
+        Self {
+            waveform,
+            start_hz,
+            end_hz,
+            delay: 0.0,
+            seconds,
+            volume: 1.0,
+        }
+    }
+    const fn after(mut self, delay: f32) -> Self {
+        self.delay = delay;
+        self
+    }
+    const fn volume(mut self, volume: f32) -> Self {
+        self.volume = volume;
+        self
+    }
+}
+
+#[derive(Clone)]
+pub struct SynthVoice {
+    blip: Blip,
+    pitch: f32,
+    oscillator: Oscillator,
+    seconds_elapsed: f32,
+}
+impl SynthVoice {
+    pub fn new(blip: Blip, pitch: f32) -> Self {
+        Self {
+            blip,
+            pitch,
+            oscillator: Oscillator::new(blip.waveform),
+            seconds_elapsed: 0.0,
+        }
+    }
+
+    // NOTE(Fermin): None once the blip is over
+    pub fn next(&mut self, sample_rate: u32) -> Option<f32> {
+        let blip = &self.blip;
+        let t = self.seconds_elapsed - blip.delay;
+        self.seconds_elapsed += 1.0 / sample_rate as f32;
+        if t < 0.0 {
+            return Some(0.0);
+        }
+        if t >= blip.seconds {
+            return None;
+        }
+        let progress = t / blip.seconds;
+        let hz = (blip.start_hz + (blip.end_hz - blip.start_hz) * progress) * self.pitch;
+        let fade = 1.0 - progress;
+        Some(self.oscillator.next(hz, sample_rate) * fade * blip.volume)
+    }
+}
+
+use Waveform::*;
+
+// NOTE(Fermin): Every effect is a few blips played together
+const PADDLE_HIT: &[Blip] = &[Blip::new(Square, 440.0, 520.0, 0.06)];
+const WALL_HIT: &[Blip] = &[Blip::new(Triangle, 220.0, 200.0, 0.05)];
+const BRICK_BREAK: &[Blip] = &[
+    Blip::new(Square, 660.0, 880.0, 0.05).volume(0.7),
+    Blip::new(Noise, 4000.0, 2000.0, 0.08).volume(0.5),
+];
+const POWER_UP: &[Blip] = &[
+    Blip::new(Square, 523.0, 523.0, 0.06).volume(0.6),
+    Blip::new(Square, 659.0, 659.0, 0.06)
+        .volume(0.6)
+        .after(0.06),
+    Blip::new(Square, 784.0, 784.0, 0.1).volume(0.6).after(0.12),
+];
+const BALL_LOST: &[Blip] = &[Blip::new(Triangle, 330.0, 110.0, 0.25)];
+const DEATH: &[Blip] = &[
+    Blip::new(Square, 392.0, 98.0, 0.6).volume(0.6),
+    Blip::new(Noise, 1200.0, 200.0, 0.7).volume(0.5),
+];
+const LEVEL_CLEAR: &[Blip] = &[
+    Blip::new(Triangle, 523.0, 523.0, 0.1),
+    Blip::new(Triangle, 659.0, 659.0, 0.1).after(0.1),
+    Blip::new(Triangle, 784.0, 784.0, 0.1).after(0.2),
+    Blip::new(Triangle, 1047.0, 1047.0, 0.3).after(0.3),
+];
+const MENU_MOVE: &[Blip] = &[Blip::new(Square, 880.0, 880.0, 0.03).volume(0.4)];
+const MENU_SELECT: &[Blip] = &[
+    Blip::new(Square, 660.0, 660.0, 0.05).volume(0.5),
+    Blip::new(Square, 990.0, 990.0, 0.08)
+        .volume(0.5)
+        .after(0.05),
+];
+const PADDLE_CUE: &[Blip] = &[Blip::new(Square, 330.0, 330.0, 0.03).volume(0.5)];
+
+pub fn sound_blips(kind: SoundKind) -> &'static [Blip] {
+    match kind {
+        SoundKind::PaddleHit => PADDLE_HIT,
+        SoundKind::WallHit => WALL_HIT,
+        SoundKind::BrickBreak => BRICK_BREAK,
+        SoundKind::PowerUp => POWER_UP,
+        SoundKind::BallLost => BALL_LOST,
+        SoundKind::Death => DEATH,
+        SoundKind::LevelClear => LEVEL_CLEAR,
+        SoundKind::MenuMove => MENU_MOVE,
+        SoundKind::MenuSelect => MENU_SELECT,
+        SoundKind::PaddleCue => PADDLE_CUE,
+    }
+}
@@ -0,0 +1,172 @@
+use crate::bindings::*;
+use crate::daily::*;
+use crate::font::*;
+use crate::game::*;
+use crate::window::*;
+use crate::*;
+
+const ATTRACT_IDLE_SECONDS: f32 = 10.0;
+const PRESS_START_BLINK_SECONDS: f32 = 0.5;
+const TITLE_LINE_HEIGHT: f32 = 14.0;
+
+// NOTE(Fermin): A whole game of its own, it never touches the real one
+#[derive(Clone)]
+struct AttractDemo {
+    memory: GameMemory,
+    state: GameState,
+    seconds_elapsed: f32,
+}
+
+#[derive(Clone)]
+pub struct TitleScreen {
+    // NOTE(Fermin): Index into GAME_MODES, picked up from the settings on
+    // the first frame
+    selected: Option<usize>,
+    // NOTE(Fermin): Read from disk once, on the first frame of a daily
+    // challenge
+    daily_best: Option<Option<u32>>,
+    idle_seconds: f32,
+    demo_count: u64,
+    demo: Option<Box<AttractDemo>>,
+}
+impl TitleScreen {
+    pub fn new() -> Self {
+        Self {
+            selected: None,
+            daily_best: None,
+            idle_seconds: 0.0,
+            demo_count: 0,
+            demo: None,
+        }
+    }
+}
+
+fn any_input(input: &GameInput) -> bool {
+    input.key_pressed.is_some()
+        || input
+            .buttons()
+            .iter()
+            .any(|button| button.half_transition_count > 0)
+}
+
+// NOTE(Fermin): Returns true when the player starts a game, the mode is
+// already in the settings
+pub fn update_and_render_title(
+    title: &mut TitleScreen,
+    input: &GameInput,
+    settings: &mut GameSettings,
+    seed: u64,
+    bindings: &mut KeyBindings,
+    buffer: &mut Win32OffscreenBuffer,
+) -> bool {
+    if let Some(demo) = &mut title.demo {
+        if any_input(input) {
+            title.demo = None;
+            title.idle_seconds = 0.0;
+            clear_screen(buffer);
+        } else {
+            let demo_input = demo_input(&mut demo.memory, input.dt_for_frame);
+            update_and_render(
+                &mut demo.memory,
+                buffer,
+                &demo_input,
+                &mut demo.state,
+                bindings,
+            );
+            // NOTE(Fermin): The demo plays muted
+            demo.memory.take_sounds();
+            demo.seconds_elapsed += input.dt_for_frame;
+
+            let blink = (demo.seconds_elapsed / PRESS_START_BLINK_SECONDS) as u32;
+            if blink.is_multiple_of(2) {
+                draw_centered_text("PRESS START", buffer.height as f32 * 0.5, 2, buffer);
+            }
+            draw_overlay_text("DEMO", buffer);
+            return false;
+        }
+    }
+
+    let selected = title.selected.get_or_insert_with(|| {
+        GAME_MODES
+            .iter()
+            .position(|mode| *mode == settings.mode)
+            .unwrap_or(0)
+    });
+    let buttons = input.combined_buttons();
+    if any_input(input) {
+        title.idle_seconds = 0.0;
+    } else {
+        title.idle_seconds += input.dt_for_frame;
+    }
+    // NOTE(Fermin): The daily challenge comes with its own mode
+    if settings.daily.is_none() {
+        if buttons.move_up.was_pressed() {
+            *selected = (*selected + GAME_MODES.len() - 1) % GAME_MODES.len();
+        }
+        if buttons.move_down.was_pressed() {
+            *selected = (*selected + 1) % GAME_MODES.len();
+        }
+        settings.mode = GAME_MODES[*selected];
+    }
+    if buttons.start.was_pressed() {
+        return true;
+    }
+
+    if title.idle_seconds >= ATTRACT_IDLE_SECONDS {
+        // NOTE(Fermin): Survival with the hardest AI, every demo with a new seed
+        let mut demo_settings = GameSettings::new();
+        demo_settings.ai_level = AiLevel::Hard;
+        let demo_seed = seed ^ title.demo_count.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        title.demo_count += 1;
+        title.demo = Some(Box::new(AttractDemo {
+            memory: GameMemory::new(demo_seed, demo_settings),
+            state: GameState {
+                state: GameStates::Play,
+                pause_seconds_elapsed: 0.0,
+            },
+            seconds_elapsed: 0.0,
+        }));
+        return false;
+    }
+
+    draw_centered_text("POONG", 80.0, 6, buffer);
+    if let Some(date) = settings.daily {
+        let best = *title
+            .daily_best
+            .get_or_insert_with(|| best_daily_result(date));
+        draw_centered_text("DAILY CHALLENGE", 170.0, 2, buffer);
+        draw_centered_text(&date.text(), 170.0 + 3.0 * TITLE_LINE_HEIGHT, 1, buffer);
+        let best = match best {
+            Some(score) => format!("BEST {}", score),
+            None => "NOT PLAYED YET".to_string(),
+        };
+        draw_centered_text(&best, 170.0 + 4.0 * TITLE_LINE_HEIGHT, 1, buffer);
+        draw_centered_text("PRESS START", buffer.height as f32 - 60.0, 1, buffer);
+        return false;
+    }
+    for (index, mode) in GAME_MODES.iter().enumerate() {
+        let y = 170.0 + index as f32 * TITLE_LINE_HEIGHT;
+        let color = if index == *selected {
+            &NEON_YELLOW
+        } else {
+            &BABY_PINK
+        };
+        let label = mode.label();
+        let x = ((buffer.width - text_width(label, 1)) / 2) as f32;
+        if index == *selected {
+            draw_text(">", &V2 { x: x - 12.0, y }, 1, color, buffer);
+        }
+        draw_text(label, &V2 { x, y }, 1, color, buffer);
+    }
+    draw_centered_text("PRESS START", buffer.height as f32 - 60.0, 1, buffer);
+
+    false
+}
+
+fn draw_centered_text(text: &str, y: f32, scale: i32, buffer: &mut Win32OffscreenBuffer) {
+    let p = V2 {
+        x: ((buffer.width - text_width(text, scale)) / 2) as f32,
+        y: y - (GLYPH_HEIGHT * scale) as f32 * 0.5,
+    };
+    draw_text(text, &p, scale, &NEON_MINT, buffer);
+}
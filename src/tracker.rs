@@ -0,0 +1,642 @@
+use crate::level::*;
+use crate::savestate::*;
+use crate::synth::*;
+use std::fs;
+use std::io::Result;
+use std::path::Path;
+use std::rc::Rc;
+
+// NOTE(Fermin): Layers fade in and out over this long so they don't click
+const LAYER_FADE_SECONDS: f32 = 0.5;
+const MIN_TEMPO_SCALE: f32 = 0.5;
+const MAX_TEMPO_SCALE: f32 = 2.0;
+
+// NOTE(Fermin): Plays when there is no game song in the assets
+pub const DEFAULT_SONG: &str = "tempo = 112
+rows_per_beat = 4
+channel bass = triangle 0.8 0 0
+channel drums = noise 0.35 0.08 0
+channel lead = square 0.25 0.18 4
+channel arp = square 0.15 0.1 12
+
+pattern a bass = C2 . . . C3 . . . G2 . . . A#2 . G2 .
+pattern a drums = C8 . . . C6 . . . C8 . C8 . C6 . . .
+pattern a lead = C5 . D#5 . G5 . . . F5 . D#5 . D5 . . .
+pattern a arp = C6 G5 D#5 G5 C6 G5 D#5 G5 C6 G5 D#5 G5 C6 G5 D#5 G5
+
+pattern b bass = G#1 . . . G#2 . . . A#1 . . . A#2 . G2 .
+pattern b drums = C8 . . . C6 . . . C8 . C8 . C6 . C6 C6
+pattern b lead = D#5 . . . C5 . . . D5 . F5 . D5 . . .
+pattern b arp = G#5 D#5 C5 D#5 G#5 D#5 C5 D#5 A#5 F5 D5 F5 A#5 F5 D5 F5
+
+pattern death bass = C3 B2 A#2 A2 G#2 . . . -
+pattern death lead = G4 F#4 F4 E4 D#4 . . . -
+
+order = a a b a
+stinger = death";
+
+// NOTE(Fermin): What the music follows, sent by the platform layer every
+// frame. Tempo goes with the ball speed and layers come in as the rally grows.
+#[derive(Copy, Clone)]
+pub struct MusicMood {
+    pub tempo_scale: f32,
+    pub rally: u32,
+    pub playing: bool,
+}
+impl MusicMood {
+    pub fn new() -> Self {
+        Self {
+            tempo_scale: 1.0,
+            rally: 0,
+            playing: false,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Note {
+    // NOTE(Fermin): Whatever was playing keeps going
+    Hold,
+    Off,
+    On(f32),
+}
+
+// NOTE(Fermin): Decay is how long a note takes to fade out, 0 holds it until
+// the next one. Rally is the hits it takes for the channel to come in.
+struct SongChannel {
+    name: String,
+    waveform: Waveform,
+    volume: f32,
+    decay: f32,
+    rally: u32,
+}
+
+// NOTE(Fermin): One row of notes per channel, channels the pattern doesn't
+// mention stay quiet
+struct Pattern {
+    name: String,
+    rows: usize,
+    notes: Vec<Vec<Note>>,
+}
+
+pub struct Song {
+    tempo: f32,
+    rows_per_beat: u32,
+    channels: Vec<SongChannel>,
+    patterns: Vec<Pattern>,
+    order: Vec<usize>,
+    stinger: Option<usize>,
+}
+impl Song {
+    pub fn default_song() -> Self {
+        parse_song(DEFAULT_SONG, "default song").expect("The default song is valid")
+    }
+
+    // NOTE(Fermin): One 'key = values' per line, # at the start of a word
+    // starts a comment so sharps still work:
+    //
+    //   tempo = BEATS_PER_MINUTE
+    //   rows_per_beat = ROWS
+    //   channel NAME = square | triangle | noise VOLUME DECAY RALLY
+    //   pattern NAME CHANNEL = NOTE ...
+    //   order = PATTERN ...
+    //   stinger = PATTERN
+    //
+    // Notes are like C4, F#3 or Bb2, '.' keeps the last note going and '-'
+    // stops it. Noise channels use the pitch as the noise rate.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        parse_song(&text, &path.display().to_string())
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut previous = ' ';
+    for (index, c) in line.char_indices() {
+        if c == '#' && previous.is_whitespace() {
+            return &line[..index];
+        }
+        previous = c;
+    }
+    line
+}
+
+fn parse_note(text: &str) -> std::result::Result<Note, String> {
+    match text {
+        "." => return Ok(Note::Hold),
+        "-" => return Ok(Note::Off),
+        _ => (),
+    }
+    let error = || {
+        format!(
+            "invalid note '{}', expected one like C4, F#3, Bb2, . or -",
+            text
+        )
+    };
+    let mut chars = text.chars();
+    let mut semitone: i32 = match chars.next().map(|c| c.to_ascii_uppercase()) {
+        Some('C') => 0,
+        Some('D') => 2,
+        Some('E') => 4,
+        Some('F') => 5,
+        Some('G') => 7,
+        Some('A') => 9,
+        Some('B') => 11,
+        _ => return Err(error()),
+    };
+    let mut rest = chars.as_str();
+    if let Some(after) = rest.strip_prefix('#') {
+        semitone += 1;
+        rest = after;
+    } else if let Some(after) = rest.strip_prefix('b') {
+        semitone -= 1;
+        rest = after;
+    }
+    let octave: i32 = rest.parse().map_err(|_| error())?;
+    if !(0..=9).contains(&octave) {
+        return Err(error());
+    }
+    let midi = (octave + 1) * 12 + semitone;
+    Ok(Note::On(440.0 * 2.0f32.powf((midi - 69) as f32 / 12.0)))
+}
+
+fn parse_song(text: &str, source: &str) -> Result<Song> {
+    let mut result = Song {
+        tempo: 120.0,
+        rows_per_beat: 4,
+        channels: Vec::new(),
+        patterns: Vec::new(),
+        order: Vec::new(),
+        stinger: None,
+    };
+    // NOTE(Fermin): Patterns can come after the order that uses them, names
+    // are resolved at the end
+    let mut order_names: Vec<String> = Vec::new();
+    let mut stinger_name: Option<(String, usize)> = None;
+    let mut order_line = 0;
+    for (line_index, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error =
+            |message: String| invalid_data(&format!("{}:{}: {}", source, line_index + 1, message));
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error(format!("expected 'key = value', got '{}'", line)))?;
+        let keys: Vec<&str> = key.split_whitespace().collect();
+        let values: Vec<&str> = value.split_whitespace().collect();
+        match keys.as_slice() {
+            ["tempo"] => {
+                let usage = "tempo = BEATS_PER_MINUTE";
+                let [tempo] = values.as_slice() else {
+                    return Err(error(format!("expected '{}'", usage)));
+                };
+                result.tempo = parse_number(tempo, usage).map_err(error)?;
+                if result.tempo <= 0.0 {
+                    return Err(error("tempo must be above 0".to_string()));
+                }
+            }
+            ["rows_per_beat"] => {
+                let usage = "rows_per_beat = ROWS";
+                let [rows] = values.as_slice() else {
+                    return Err(error(format!("expected '{}'", usage)));
+                };
+                result.rows_per_beat = parse_number(rows, usage).map_err(error)?;
+                if result.rows_per_beat == 0 {
+                    return Err(error("rows_per_beat must be above 0".to_string()));
+                }
+            }
+            ["channel", name] => {
+                let usage = "channel NAME = square | triangle | noise VOLUME DECAY RALLY";
+                let [waveform, volume, decay, rally] = values.as_slice() else {
+                    return Err(error(format!("expected '{}'", usage)));
+                };
+                if result.channels.iter().any(|channel| channel.name == *name) {
+                    return Err(error(format!("channel '{}' is already defined", name)));
+                }
+                let waveform = match *waveform {
+                    "square" => Waveform::Square,
+                    "triangle" => Waveform::Triangle,
+                    "noise" => Waveform::Noise,
+                    _ => return Err(error(format!("expected '{}'", usage))),
+                };
+                result.channels.push(SongChannel {
+                    name: name.to_string(),
+                    waveform,
+                    volume: parse_number(volume, usage).map_err(error)?,
+                    decay: parse_number::<f32>(decay, usage).map_err(error)?.max(0.0),
+                    rally: parse_number(rally, usage).map_err(error)?,
+                });
+                for pattern in &mut result.patterns {
+                    pattern.notes.push(Vec::new());
+                }
+            }
+            ["pattern", name, channel] => {
+                let channel_index = result
+                    .channels
+                    .iter()
+                    .position(|song_channel| song_channel.name == *channel)
+                    .ok_or_else(|| error(format!("unknown channel '{}'", channel)))?;
+                let notes = values
+                    .iter()
+                    .map(|note| parse_note(note))
+                    .collect::<std::result::Result<Vec<Note>, String>>()
+                    .map_err(error)?;
+                if notes.is_empty() {
+                    return Err(error(
+                        "expected 'pattern NAME CHANNEL = NOTE ...'".to_string(),
+                    ));
+                }
+                let pattern_index = match result
+                    .patterns
+                    .iter()
+                    .position(|pattern| pattern.name == *name)
+                {
+                    Some(index) => index,
+                    None => {
+                        result.patterns.push(Pattern {
+                            name: name.to_string(),
+                            rows: notes.len(),
+                            notes: result.channels.iter().map(|_| Vec::new()).collect(),
+                        });
+                        result.patterns.len() - 1
+                    }
+                };
+                let pattern = &mut result.patterns[pattern_index];
+                if notes.len() != pattern.rows {
+                    return Err(error(format!(
+                        "pattern '{}' has {} rows, got {}",
+                        name,
+                        pattern.rows,
+                        notes.len()
+                    )));
+                }
+                pattern.notes[channel_index] = notes;
+            }
+            ["order"] => {
+                if values.is_empty() {
+                    return Err(error("expected 'order = PATTERN ...'".to_string()));
+                }
+                order_names = values.iter().map(|name| name.to_string()).collect();
+                order_line = line_index + 1;
+            }
+            ["stinger"] => {
+                let [name] = values.as_slice() else {
+                    return Err(error("expected 'stinger = PATTERN'".to_string()));
+                };
+                stinger_name = Some((name.to_string(), line_index + 1));
+            }
+            _ => return Err(error(format!("unknown key '{}'", key.trim()))),
+        }
+    }
+
+    let find_pattern = |name: &str, line: usize| {
+        result
+            .patterns
+            .iter()
+            .position(|pattern| pattern.name == name)
+            .ok_or_else(|| {
+                invalid_data(&format!("{}:{}: unknown pattern '{}'", source, line, name))
+            })
+    };
+    if order_names.is_empty() {
+        return Err(invalid_data(&format!("{}: song without an order", source)));
+    }
+    let order = order_names
+        .iter()
+        .map(|name| find_pattern(name, order_line))
+        .collect::<Result<Vec<usize>>>()?;
+    let stinger = match &stinger_name {
+        Some((name, line)) => Some(find_pattern(name, *line)?),
+        None => None,
+    };
+    result.order = order;
+    result.stinger = stinger;
+    Ok(result)
+}
+
+struct ChannelState {
+    oscillator: Oscillator,
+    hz: f32,
+    on: bool,
+    note_seconds: f32,
+    layer_volume: f32,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum SequencerState {
+    Song,
+    Stinger,
+    // NOTE(Fermin): Quiet after the stinger until the next game starts
+    Done,
+}
+
+// NOTE(Fermin): Walks the song order row by row, the row length follows the
+// mood's tempo so the music speeds up with the ball
+pub struct Sequencer {
+    song: Rc<Song>,
+    channels: Vec<ChannelState>,
+    state: SequencerState,
+    order_index: usize,
+    row: usize,
+    row_seconds_elapsed: f32,
+    mood: MusicMood,
+}
+impl Sequencer {
+    pub fn new(song: Rc<Song>) -> Self {
+        let channels = song
+            .channels
+            .iter()
+            .map(|channel| ChannelState {
+                oscillator: Oscillator::new(channel.waveform),
+                hz: 0.0,
+                on: false,
+                note_seconds: 0.0,
+                layer_volume: if channel.rally == 0 { 1.0 } else { 0.0 },
+            })
+            .collect();
+        let mut result = Self {
+            song,
+            channels,
+            state: SequencerState::Song,
+            order_index: 0,
+            row: 0,
+            row_seconds_elapsed: 0.0,
+            mood: MusicMood::new(),
+        };
+        result.trigger_row();
+        result
+    }
+
+    // NOTE(Fermin): A new game after the stinger starts the song over
+    pub fn set_mood(&mut self, mood: MusicMood) {
+        self.mood = mood;
+        if self.state == SequencerState::Done && mood.playing {
+            self.start(SequencerState::Song);
+        }
+    }
+
+    pub fn stinger(&mut self) {
+        if self.song.stinger.is_some() && self.state != SequencerState::Stinger {
+            self.start(SequencerState::Stinger);
+        }
+    }
+
+    fn start(&mut self, state: SequencerState) {
+        self.state = state;
+        self.order_index = 0;
+        self.row = 0;
+        self.row_seconds_elapsed = 0.0;
+        for channel in &mut self.channels {
+            channel.on = false;
+        }
+        self.trigger_row();
+    }
+
+    fn pattern(&self) -> &Pattern {
+        let index = match self.state {
+            SequencerState::Stinger => self.song.stinger.unwrap_or(0),
+            _ => self.song.order[self.order_index],
+        };
+        &self.song.patterns[index]
+    }
+
+    fn row_seconds(&self) -> f32 {
+        let tempo_scale = match self.state {
+            SequencerState::Stinger => 1.0,
+            _ => self
+                .mood
+                .tempo_scale
+                .clamp(MIN_TEMPO_SCALE, MAX_TEMPO_SCALE),
+        };
+        60.0 / (self.song.tempo * tempo_scale * self.song.rows_per_beat as f32)
+    }
+
+    fn trigger_row(&mut self) {
+        let row = self.row;
+        let notes: Vec<Note> = self
+            .pattern()
+            .notes
+            .iter()
+            .map(|channel_notes| channel_notes.get(row).copied().unwrap_or(Note::Off))
+            .collect();
+        for (channel, note) in self.channels.iter_mut().zip(notes) {
+            match note {
+                Note::Hold => (),
+                Note::Off => channel.on = false,
+                Note::On(hz) => {
+                    channel.hz = hz;
+                    channel.on = true;
+                    channel.note_seconds = 0.0;
+                }
+            }
+        }
+    }
+
+    fn advance_row(&mut self) {
+        self.row += 1;
+        if self.row >= self.pattern().rows {
+            self.row = 0;
+            if self.state == SequencerState::Stinger {
+                self.state = SequencerState::Done;
+                for channel in &mut self.channels {
+                    channel.on = false;
+                }
+                return;
+            }
+            self.order_index = (self.order_index + 1) % self.song.order.len();
+        }
+        self.trigger_row();
+    }
+
+    pub fn next(&mut self, sample_rate: u32) -> f32 {
+        if self.state == SequencerState::Done {
+            return 0.0;
+        }
+        let dt = 1.0 / sample_rate as f32;
+        self.row_seconds_elapsed += dt;
+        let row_seconds = self.row_seconds();
+        if self.row_seconds_elapsed >= row_seconds {
+            self.row_seconds_elapsed -= row_seconds;
+            self.advance_row();
+        }
+
+        let stinger = self.state == SequencerState::Stinger;
+        let mut result = 0.0;
+        for (channel, song_channel) in self.channels.iter_mut().zip(&self.song.channels) {
+            let layer_target = if stinger || self.mood.rally >= song_channel.rally {
+                1.0
+            } else {
+                0.0
+            };
+            let layer_step = dt / LAYER_FADE_SECONDS;
+            channel.layer_volume +=
+                (layer_target - channel.layer_volume).clamp(-layer_step, layer_step);
+            if !channel.on {
+                continue;
+            }
+            let envelope = if song_channel.decay > 0.0 {
+                (1.0 - channel.note_seconds / song_channel.decay).max(0.0)
+            } else {
+                1.0
+            };
+            channel.note_seconds += dt;
+            result += channel.oscillator.next(channel.hz, sample_rate)
+                * song_channel.volume
+                * envelope
+                * channel.layer_volume;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hz(text: &str) -> f32 {
+        match parse_note(text) {
+            Ok(Note::On(hz)) => hz,
+            _ => panic!("{} is not a note", text),
+        }
+    }
+
+    fn assert_hz(text: &str, expected: f32) {
+        assert!(
+            (hz(text) - expected).abs() < 0.01,
+            "{} is {} Hz, expected {}",
+            text,
+            hz(text),
+            expected
+        );
+    }
+
+    fn parse(text: &str) -> Result<Song> {
+        parse_song(text, "test")
+    }
+
+    const SONG: &str = "tempo = 90
+rows_per_beat = 2
+channel lead = square 0.5 0.1 0
+channel drums = noise 0.3 0 8 # comes in at 8 hits
+pattern a lead = C#4 . Bb3 -
+pattern a drums = C8 . . .
+pattern end lead = F#3 -
+order = a a
+stinger = end";
+
+    #[test]
+    fn strip_comment_keeps_sharps() {
+        assert_eq!(strip_comment("# the whole line"), "");
+        assert_eq!(strip_comment("tempo = 120 # fast"), "tempo = 120 ");
+        assert_eq!(strip_comment("tempo = 120 #fast"), "tempo = 120 ");
+        assert_eq!(
+            strip_comment("pattern a x = C#4 F#3"),
+            "pattern a x = C#4 F#3"
+        );
+        assert_eq!(
+            strip_comment("pattern a x = C#4 # G#4"),
+            "pattern a x = C#4 "
+        );
+        assert_eq!(strip_comment("\t# indented"), "\t");
+    }
+
+    #[test]
+    fn parse_note_pitches() {
+        assert!(parse_note(".") == Ok(Note::Hold));
+        assert!(parse_note("-") == Ok(Note::Off));
+        assert_hz("A4", 440.0);
+        assert_hz("a4", 440.0);
+        assert_hz("C4", 261.63);
+        assert_hz("F#3", 185.0);
+        assert_hz("Bb2", 116.54);
+        assert_eq!(hz("Bb2"), hz("A#2"));
+        assert_eq!(hz("Db5"), hz("C#5"));
+        assert_hz("C0", 16.35);
+        assert_hz("B9", 15804.27);
+    }
+
+    #[test]
+    fn parse_note_rejects_bad_notes() {
+        for text in ["", "H4", "C", "C#", "Cb", "C10", "C-1", "C#b4", "4", "C4.5"] {
+            assert!(parse_note(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn parses_a_song() {
+        let song = parse(SONG).unwrap();
+        assert_eq!(song.tempo, 90.0);
+        assert_eq!(song.rows_per_beat, 2);
+        assert_eq!(song.channels.len(), 2);
+        assert_eq!(song.channels[1].rally, 8);
+        assert_eq!(song.patterns.len(), 2);
+        assert_eq!(song.patterns[0].rows, 4);
+        assert!(song.patterns[0].notes[0][1] == Note::Hold);
+        assert!(song.patterns[0].notes[0][3] == Note::Off);
+        assert!(song.patterns[1].notes[1].is_empty());
+        assert_eq!(song.order, vec![0, 0]);
+        assert_eq!(song.stinger, Some(1));
+    }
+
+    #[test]
+    fn default_song_parses() {
+        let song = Song::default_song();
+        assert!(!song.order.is_empty());
+        assert!(song.stinger.is_some());
+    }
+
+    #[test]
+    fn bad_songs_are_errors() {
+        let with = |line: &str| format!("{}\n{}", SONG, line);
+        for line in [
+            "tempo = nan",
+            "tempo = inf",
+            "tempo = 0",
+            "tempo = -60",
+            "tempo = 60 120",
+            "rows_per_beat = 0",
+            "rows_per_beat = 1.5",
+            "channel lead = square 0.5 0.1 0",
+            "channel pad = sine 0.5 0.1 0",
+            "channel pad = square nan 0.1 0",
+            "channel pad = square 0.5 inf 0",
+            "channel pad = square 0.5 0.1",
+            "pattern a bass = C2 . . .",
+            "pattern a lead = C2 . .",
+            "pattern b lead = H2",
+            "pattern b lead =",
+            "order = a c",
+            "stinger = c",
+            "speed = 3",
+            "tempo 120",
+        ] {
+            assert!(parse(&with(line)).is_err(), "{}", line);
+        }
+        assert!(parse("channel lead = square 0.5 0.1 0\npattern a lead = C4\n").is_err());
+    }
+
+    #[test]
+    fn stinger_plays_once_then_goes_quiet() {
+        let mut sequencer = Sequencer::new(Rc::new(parse(SONG).unwrap()));
+        sequencer.stinger();
+        let sample_rate = 8000;
+        let mut heard = false;
+        // NOTE(Fermin): Two rows at 90 bpm and two rows per beat, a bit
+        // under 0.7 seconds
+        for _ in 0..sample_rate {
+            heard |= sequencer.next(sample_rate) != 0.0;
+        }
+        assert!(heard);
+        assert!(sequencer.state == SequencerState::Done);
+        assert_eq!(sequencer.next(sample_rate), 0.0);
+
+        sequencer.set_mood(MusicMood {
+            playing: true,
+            ..MusicMood::new()
+        });
+        assert!(sequencer.state == SequencerState::Song);
+    }
+}
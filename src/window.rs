@@ -1,4 +1,6 @@
+use crate::bindings::*;
 use crate::handle::*;
+use crate::savestate::*;
 use crate::*;
 use bytes::BytesMut;
 use std::mem::size_of;
@@ -21,8 +23,8 @@ use windows::{
 const WINDOW_CLASS_NAME: PCSTR = s!("win32.Window");
 const DISPLAY_OFFSET_X: i32 = 10;
 const DISPLAY_OFFSET_Y: i32 = 10;
-const BUFFER_WIDTH: i32 = 400;
-const BUFFER_HEIGHT: i32 = 400;
+pub const BUFFER_WIDTH: i32 = 400;
+pub const BUFFER_HEIGHT: i32 = 400;
 
 pub struct Win32OffscreenBuffer {
     // Pixels always are 32-bits wide, Memory Order BB GG RR XX
@@ -60,11 +62,28 @@ impl Win32OffscreenBuffer {
     }
 }
 
+// NOTE(Fermin): Requests that are handled by the platform layer instead of
+// the game, collected while processing messages
+pub enum PlatformCommand {
+    ToggleInputLoop,
+    SaveReplay,
+    SelectSaveStateSlot(usize),
+    QuickSave,
+    QuickLoad,
+    ReplayTogglePause,
+    ReplayStep,
+    ReplayFastForward,
+    ReplaySeekBack,
+    ReplaySeekForward,
+}
+
 pub struct Window {
     handle: HWND,
     pub buffer: Win32OffscreenBuffer,
     pub window_running: bool,
     pub refresh_rate: i32,
+    pub commands: Vec<PlatformCommand>,
+    held_keys: Vec<u16>,
 }
 
 impl Window {
@@ -90,6 +109,8 @@ impl Window {
             buffer,
             window_running: true,
             refresh_rate: 60,
+            commands: Vec::new(),
+            held_keys: Vec::new(),
         });
 
         let window = unsafe {
@@ -180,14 +201,36 @@ impl Window {
         }
     }
 
-    fn win32_process_keyboard_message(new_state: &mut GameButtonState, is_down: bool) {
+    fn win32_process_button_message(new_state: &mut GameButtonState, is_down: bool) {
         if new_state.ended_down != is_down {
             new_state.ended_down = is_down;
-            //TODO(Fermin): Half transitions logic
+            new_state.half_transition_count += 1;
+        }
+    }
+
+    fn win32_platform_command(v_k_code: char) -> Option<PlatformCommand> {
+        match v_k_code {
+            'L' => Some(PlatformCommand::ToggleInputLoop),
+            'P' => Some(PlatformCommand::ReplayTogglePause),
+            'N' => Some(PlatformCommand::ReplayStep),
+            'F' => Some(PlatformCommand::ReplayFastForward),
+            _ if v_k_code as u16 == VK_F6.0 => Some(PlatformCommand::SaveReplay),
+            _ if v_k_code as u16 == VK_F5.0 => Some(PlatformCommand::QuickSave),
+            _ if v_k_code as u16 == VK_F9.0 => Some(PlatformCommand::QuickLoad),
+            _ if (VK_F1.0..VK_F1.0 + SAVE_STATE_SLOTS as u16).contains(&(v_k_code as u16)) => Some(
+                PlatformCommand::SelectSaveStateSlot((v_k_code as u16 - VK_F1.0) as usize),
+            ),
+            _ if v_k_code as u16 == VK_OEM_COMMA.0 => Some(PlatformCommand::ReplaySeekBack),
+            _ if v_k_code as u16 == VK_OEM_PERIOD.0 => Some(PlatformCommand::ReplaySeekForward),
+            _ => None,
         }
     }
 
-    pub fn win32_process_pending_messages(&mut self, input: &mut GameInput) {
+    pub fn win32_process_pending_messages(
+        &mut self,
+        input: &mut GameInput,
+        bindings: &KeyBindings,
+    ) {
         let mut message: MSG = Default::default();
         unsafe {
             while PeekMessageA(&mut message, HWND(0), 0, 0, PM_REMOVE).into() {
@@ -198,23 +241,17 @@ impl Window {
                         input.cursor_pos.y = y as f32 - DISPLAY_OFFSET_Y as f32;
                         //println!("cursor x: {}, y: {}", input.cursor_pos.x, input.cursor_pos.y);
                     }
-                    // NOTE(Fermin): Consider following the same logic for
-                    // mouse button than keyboard buttons
-                    WM_LBUTTONDOWN => {
-                        input.mouse_buttons[0].ended_down = true;
-                        println!("WM_LBUTTONDOWN");
+                    WM_LBUTTONDOWN | WM_LBUTTONUP => {
+                        Self::win32_process_button_message(
+                            &mut input.mouse_buttons[0],
+                            message.message == WM_LBUTTONDOWN,
+                        );
                     }
-                    WM_LBUTTONUP => {
-                        input.mouse_buttons[0].ended_down = false;
-                        println!("WM_LBUTTONUP");
-                    }
-                    WM_RBUTTONDOWN => {
-                        input.mouse_buttons[1].ended_down = true;
-                        println!("WM_RBUTTONDOWN");
-                    }
-                    WM_RBUTTONUP => {
-                        input.mouse_buttons[1].ended_down = false;
-                        println!("WM_RBUTTONUP");
+                    WM_RBUTTONDOWN | WM_RBUTTONUP => {
+                        Self::win32_process_button_message(
+                            &mut input.mouse_buttons[1],
+                            message.message == WM_RBUTTONDOWN,
+                        );
                     }
                     WM_SYSKEYDOWN | WM_SYSKEYUP | WM_KEYDOWN | WM_KEYUP => {
                         let v_k_code: char = char::from_u32(message.wParam.0 as u32)
@@ -227,56 +264,38 @@ impl Window {
                         //println!("key: {} is_down: {}", v_k_code, is_down);
 
                         if was_down != is_down {
-                            if v_k_code == 'W' {
-                                println!("W");
-                                Self::win32_process_keyboard_message(
-                                    &mut input.keyboard.buttons.move_up,
-                                    is_down,
-                                );
-                            } else if v_k_code == 'A' {
-                                println!("A");
-                                Self::win32_process_keyboard_message(
-                                    &mut input.keyboard.buttons.move_left,
-                                    is_down,
-                                );
-                            } else if v_k_code == 'S' {
-                                println!("S");
-                                Self::win32_process_keyboard_message(
-                                    &mut input.keyboard.buttons.move_down,
-                                    is_down,
-                                );
-                            } else if v_k_code == 'D' {
-                                println!("D");
-                                Self::win32_process_keyboard_message(
-                                    &mut input.keyboard.buttons.move_right,
-                                    is_down,
-                                );
-                            } else if v_k_code as u16 == VK_ESCAPE.0 {
-                                println!("Escape");
-                                Self::win32_process_keyboard_message(
-                                    &mut input.keyboard.buttons.back,
-                                    is_down,
-                                );
-                            } else if v_k_code as u16 == VK_RETURN.0 {
-                                println!("Return");
-                                Self::win32_process_keyboard_message(
-                                    &mut input.keyboard.buttons.start,
-                                    is_down,
-                                );
-                            } else if v_k_code as u16 == VK_SPACE.0 {
-                                println!("Space");
-                                Self::win32_process_keyboard_message(
-                                    &mut input.keyboard.buttons.jump,
-                                    is_down,
-                                );
+                            let key = message.wParam.0 as u16;
+                            if is_down {
+                                input.key_pressed = Some(key);
                             }
 
-                            if is_down {
-                                if (v_k_code as u16 == VK_F4.0) && alt_key_was_down {
-                                    println!("Alt+F4");
-                                    self.window_running = false;
+                            // NOTE(Fermin): Keys bound to an action never
+                            // trigger platform commands
+                            if let Some(action) = bindings.action_for_key(key) {
+                                if is_down {
+                                    self.held_keys.push(key);
+                                } else {
+                                    self.held_keys.retain(|held_key| *held_key != key);
+                                }
+                                let action_down = bindings
+                                    .keys(action)
+                                    .iter()
+                                    .any(|bound_key| self.held_keys.contains(bound_key));
+                                Self::win32_process_button_message(
+                                    action
+                                        .button(input.keyboard.player_buttons_mut(action.player())),
+                                    action_down,
+                                );
+                            } else if is_down {
+                                if let Some(command) = Self::win32_platform_command(v_k_code) {
+                                    self.commands.push(command);
                                 }
                             }
+
+                            if is_down && (v_k_code as u16 == VK_F4.0) && alt_key_was_down {
+                                println!("Alt+F4");
+                                self.window_running = false;
+                            }
                         }
                     }
                     _ => {